@@ -1,5 +1,5 @@
 use tide::{Request, Response, StatusCode};
-use tide_tracing_middleware::TracingMiddleware;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 
@@ -7,7 +7,7 @@ use tracing_subscriber::FmtSubscriber;
 async fn main() -> tide::Result<()> {
     FmtSubscriber::builder().with_max_level(Level::DEBUG).init();
 
-    let tracing_middleware = TracingMiddleware::new(
+    let tracing_middleware = TracingMiddlewareBuilder::new(
         "%t  %a(%{r}a)  %r(%M %U %Q %V) %s %b(bytes) %T(seconds) %D(milliseconds) REQ_HEADERS:%{ALL_REQ_HEADERS}xi RES_HEADERS:%{ALL_RES_HEADERS}xo",
     ).custom_request_replace("ALL_REQ_HEADERS", |req| {
         let pairs = req.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<String>>();
@@ -15,7 +15,7 @@ async fn main() -> tide::Result<()> {
     }).custom_response_replace("ALL_RES_HEADERS", |res| {
         let pairs = res.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<String>>();
         "{".to_owned() + &pairs.join(",") + "}"
-    });
+    }).build();
 
     let mut app = tide::new();
     app.with(tracing_middleware);
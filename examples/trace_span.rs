@@ -1,5 +1,5 @@
 use tide::{Request, Response, StatusCode};
-use tide_tracing_middleware::TracingMiddleware;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
@@ -7,7 +7,7 @@ use tracing_subscriber::FmtSubscriber;
 async fn main() -> tide::Result<()> {
     FmtSubscriber::builder().with_max_level(Level::DEBUG).init();
 
-    let tracing_middleware = TracingMiddleware::new(
+    let tracing_middleware = TracingMiddlewareBuilder::new(
         "%t  %a(%{r}a)  %r(%M %U %Q %V) %s %b(bytes) %T(seconds) %D(milliseconds) REQ_HEADERS:%{ALL_REQ_HEADERS}xi RES_HEADERS:%{ALL_RES_HEADERS}xo",
     ).custom_request_replace("ALL_REQ_HEADERS", |req| {
         let pairs = req.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<String>>();
@@ -17,7 +17,7 @@ async fn main() -> tide::Result<()> {
         "{".to_owned() + &pairs.join(",") + "}"
     }).gen_tracing_span(|_req| {
         tracing::info_span!("R", "{}", uuid::Uuid::new_v4().to_simple().to_string())
-    });
+    }).build();
 
     let mut app = tide::new();
     app.with(tracing_middleware);
@@ -0,0 +1,67 @@
+//! Anonymization of client IP addresses.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// How much of the client IP addresses is zeroed before they are logged, see
+/// [`TracingMiddlewareBuilder::anonymize_ip`](crate::TracingMiddlewareBuilder::anonymize_ip).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAnonymization {
+    /// Zero the last octets of IPv4 addresses, `TruncateV4Octets(1)` logs `192.0.2.17` as
+    /// `192.0.2.0`. At most 4 octets are zeroed.
+    TruncateV4Octets(u8),
+    /// Zero the last bits of IPv6 addresses, `TruncateV6Bits(80)` logs `2001:db8:1:2::17` as
+    /// `2001:db8:1::`. At most 128 bits are zeroed.
+    TruncateV6Bits(u8),
+}
+
+/// The number of zeroed octets of IPv4 and bits of IPv6 addresses.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IpAnonymizer {
+    v4_octets: u8,
+    v6_bits: u8,
+}
+
+impl Default for IpAnonymizer {
+    fn default() -> Self {
+        Self {
+            v4_octets: 1,
+            v6_bits: 80,
+        }
+    }
+}
+
+impl IpAnonymizer {
+    pub(crate) fn set(&mut self, mode: IpAnonymization) {
+        match mode {
+            IpAnonymization::TruncateV4Octets(octets) => self.v4_octets = octets.min(4),
+            IpAnonymization::TruncateV6Bits(bits) => self.v6_bits = bits.min(128),
+        }
+    }
+
+    fn ip(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(8 * u32::from(self.v4_octets))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(u32::from(self.v6_bits)).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+
+    /// Anonymize an address rendered as `ip` or `ip:port`, other values like host names are
+    /// returned unchanged.
+    pub(crate) fn addr(&self, addr: &str) -> String {
+        if let Ok(addr) = addr.parse::<SocketAddr>() {
+            SocketAddr::new(self.ip(addr.ip()), addr.port()).to_string()
+        } else if let Ok(ip) = addr.parse::<IpAddr>() {
+            self.ip(ip).to_string()
+        } else {
+            addr.to_owned()
+        }
+    }
+}
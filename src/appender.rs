@@ -0,0 +1,25 @@
+//! Access records written by a `tracing-appender` worker thread.
+
+use std::io::Write;
+
+use tracing_appender::non_blocking::NonBlocking;
+
+use crate::{LogRecord, LogSink};
+
+/// Hands the records to the worker of
+/// [`TracingMiddlewareBuilder::non_blocking`](crate::TracingMiddlewareBuilder::non_blocking).
+pub(crate) struct NonBlockingSink(pub(crate) NonBlocking);
+
+impl LogSink for NonBlockingSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        if record.message().is_some() {
+            let mut lines = String::new();
+            for line in record.lines() {
+                lines.push_str(line);
+                lines.push('\n');
+            }
+            // a single write is a single message to the worker, so lines are never interleaved
+            let _ = self.0.clone().write_all(lines.as_bytes());
+        }
+    }
+}
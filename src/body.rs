@@ -0,0 +1,196 @@
+//! Capture of request and response bodies.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncReadExt, BufReader, Cursor};
+use tide::http::Mime;
+use tide::{Body, Request};
+
+/// The content types of the bodies that are captured, see
+/// [`TracingMiddlewareBuilder::capture_body_if`](crate::TracingMiddlewareBuilder::capture_body_if).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentTypeFilter {
+    /// `application/json`, and JSON based types like `application/problem+json`.
+    Json,
+    /// `application/xml`, `text/xml`, and XML based types like `application/atom+xml`.
+    Xml,
+    /// `application/x-www-form-urlencoded`.
+    Form,
+    /// All `text/*` types.
+    Text,
+    /// The media type, like `application/graphql`, ignoring parameters like `charset`.
+    Exact(String),
+}
+
+impl ContentTypeFilter {
+    pub(crate) fn matches(&self, mime: &Mime) -> bool {
+        let subtype = mime.subtype();
+        match self {
+            ContentTypeFilter::Json => {
+                (mime.basetype() == "application" && subtype == "json")
+                    || subtype.ends_with("+json")
+            }
+            ContentTypeFilter::Xml => {
+                (matches!(mime.basetype(), "application" | "text") && subtype == "xml")
+                    || subtype.ends_with("+xml")
+            }
+            ContentTypeFilter::Form => mime.essence() == "application/x-www-form-urlencoded",
+            ContentTypeFilter::Text => mime.basetype() == "text",
+            ContentTypeFilter::Exact(essence) => mime.essence().eq_ignore_ascii_case(essence),
+        }
+    }
+}
+
+/// The first bytes of the request body, captured when
+/// [`TracingMiddlewareBuilder::capture_request_body`](crate::TracingMiddlewareBuilder::capture_request_body)
+/// is enabled.
+///
+/// The middleware adds it to the request extensions, so custom replacements can read it with
+/// `req.ext::<RequestBody>()`. The handler still receives the whole body.
+#[derive(Debug, Clone)]
+pub struct RequestBody(Captured);
+
+impl RequestBody {
+    /// The captured bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0.bytes
+    }
+
+    /// Whether the body is longer than the captured bytes.
+    pub fn is_truncated(&self) -> bool {
+        self.0.truncated
+    }
+
+    /// Read up to `max` bytes of the request body into the request extensions, and put them
+    /// back in front of the rest of the body.
+    pub(crate) async fn capture<State: Clone + Send + Sync + 'static>(
+        req: &mut Request<State>,
+        max: usize,
+    ) {
+        let mut body = req.take_body();
+        let len = body.len();
+        let mime = body.mime().clone();
+
+        // one more byte tells whether the body is truncated
+        let mut bytes = Vec::new();
+        let _ = (&mut body)
+            .take(max as u64 + 1)
+            .read_to_end(&mut bytes)
+            .await;
+        let mut captured = Captured::new(max);
+        captured.push(&bytes);
+
+        let restored = Cursor::new(bytes).chain(body);
+        let mut body = Body::from_reader(restored, len);
+        body.set_mime(mime);
+        req.set_body(body);
+        req.set_ext(RequestBody(captured));
+    }
+}
+
+impl Display for RequestBody {
+    /// The captured bytes as UTF-8, with invalid sequences replaced, followed by `...` if the
+    /// body is truncated.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The size of the request body for `%I`.
+///
+/// It is the `Content-Length` of the request if present, otherwise the body is wrapped to
+/// count the bytes the handler reads.
+pub(crate) enum RequestSize {
+    Known(usize),
+    Read(Arc<AtomicUsize>),
+}
+
+impl RequestSize {
+    pub(crate) fn start<State: Clone + Send + Sync + 'static>(req: &mut Request<State>) -> Self {
+        if let Some(len) = req.len() {
+            return RequestSize::Known(len);
+        }
+        let read = Arc::new(AtomicUsize::new(0));
+        let body = req.take_body();
+        let mime = body.mime().clone();
+        let counting = Counting {
+            inner: body,
+            read: read.clone(),
+        };
+        let mut body = Body::from_reader(BufReader::new(counting), None);
+        body.set_mime(mime);
+        req.set_body(body);
+        RequestSize::Read(read)
+    }
+
+    pub(crate) fn get(&self) -> usize {
+        match self {
+            RequestSize::Known(len) => *len,
+            RequestSize::Read(read) => read.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts the bytes read from `inner`.
+struct Counting {
+    inner: Body,
+    read: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for Counting {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.read.fetch_add(n, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/// Up to `max` bytes of a body.
+#[derive(Debug, Clone)]
+pub(crate) struct Captured {
+    bytes: Vec<u8>,
+    max: usize,
+    truncated: bool,
+}
+
+impl Captured {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            bytes: Vec::new(),
+            max,
+            truncated: false,
+        }
+    }
+
+    /// Append the next bytes of the body, as far as they fit.
+    pub(crate) fn push(&mut self, buf: &[u8]) {
+        let n = buf.len().min(self.max - self.bytes.len());
+        self.bytes.extend_from_slice(&buf[..n]);
+        self.truncated |= n < buf.len();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl Display for Captured {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.bytes))?;
+        if self.truncated {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! The string buffers reused to render the access records.
+//!
+//! Each request renders its values into a buffer taken from the pool of the thread, and
+//! returns it once it is logged. The records themselves are rendered into a single buffer
+//! per thread, which is written to the log and cleared.
+
+use std::cell::RefCell;
+
+/// Buffers grown beyond this capacity by very long values are freed instead of reused.
+const MAX_CAPACITY: usize = 16 * 1024;
+/// The number of free buffers kept per thread.
+const POOL_SIZE: usize = 64;
+
+thread_local! {
+    static POOL: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static RECORD: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Take an empty buffer from the pool of the thread.
+pub(crate) fn take() -> String {
+    POOL.try_with(|pool| pool.borrow_mut().pop())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Return `buf` to the pool of the thread.
+pub(crate) fn recycle(mut buf: String) {
+    if buf.capacity() == 0 || buf.capacity() > MAX_CAPACITY {
+        return;
+    }
+    buf.clear();
+    // the pool is gone while the thread exits
+    let _ = POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < POOL_SIZE {
+            pool.push(buf);
+        }
+    });
+}
+
+/// Call `f` with an empty buffer to render a record into, the one of the thread unless it is
+/// already in use by a record logged while rendering another.
+pub(crate) fn with_record<R>(f: impl FnOnce(&mut String) -> R) -> R {
+    let mut f = Some(f);
+    let result = RECORD.try_with(|record| {
+        let mut record = record.try_borrow_mut().ok()?;
+        record.clear();
+        let result = f.take().map(|f| f(&mut record));
+        if record.capacity() > MAX_CAPACITY {
+            *record = String::new();
+        }
+        result
+    });
+    match (result, f) {
+        (Ok(Some(result)), _) => result,
+        (_, Some(f)) => f(&mut String::new()),
+        (_, None) => unreachable!("the record is rendered once"),
+    }
+}
@@ -0,0 +1,63 @@
+//! The source of the time, replaceable in tests.
+
+use std::time::Instant;
+
+use time::OffsetDateTime;
+
+/// The source of the time used by the middleware, see
+/// [`TracingMiddlewareBuilder::clock`](crate::TracingMiddlewareBuilder::clock).
+///
+/// `now_utc` is the wall-clock time rendered by `%t` and its variants, durations like `%T`
+/// and `%D` are measured with the monotonic `now`.
+///
+/// A mock clock for tests that only moves when told to:
+///
+/// ```
+/// use std::sync::Mutex;
+/// use std::time::{Duration, Instant};
+///
+/// use tide_tracing_middleware::Clock;
+/// use time::OffsetDateTime;
+///
+/// struct MockClock {
+///     start: Instant,
+///     elapsed: Mutex<Duration>,
+/// }
+///
+/// impl MockClock {
+///     fn advance(&self, by: Duration) {
+///         *self.elapsed.lock().unwrap() += by;
+///     }
+/// }
+///
+/// impl Clock for MockClock {
+///     fn now_utc(&self) -> OffsetDateTime {
+///         OffsetDateTime::unix_epoch() + *self.elapsed.lock().unwrap()
+///     }
+///
+///     fn now(&self) -> Instant {
+///         self.start + *self.elapsed.lock().unwrap()
+///     }
+/// }
+/// ```
+pub trait Clock: Send + Sync {
+    /// The current wall-clock time in UTC.
+    fn now_utc(&self) -> OffsetDateTime;
+
+    /// The current monotonic time.
+    fn now(&self) -> Instant;
+}
+
+/// The system clock, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
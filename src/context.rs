@@ -0,0 +1,144 @@
+//! Everything known about a request when it is logged, for custom replacements.
+
+use std::time::Duration;
+
+use tide::http::headers::{HeaderName, HeaderValues, Headers};
+use tide::http::{Method, Url, Version};
+use tide::{Request, Response, StatusCode};
+
+/// The request parts kept for the [`LogContext`], taken before the handler consumes the
+/// request.
+pub(crate) struct RequestSnapshot<State> {
+    method: Method,
+    url: Url,
+    version: Option<Version>,
+    headers: Headers,
+    client_addr: Option<String>,
+    route: Option<String>,
+    state: State,
+}
+
+impl<State: Clone + Send + Sync + 'static> RequestSnapshot<State> {
+    pub(crate) fn take(
+        req: &Request<State>,
+        client_addr: Option<String>,
+        route: Option<String>,
+    ) -> Self {
+        let headers: &Headers = req.as_ref();
+        Self {
+            method: req.method(),
+            url: req.url().clone(),
+            version: req.version(),
+            headers: headers.clone(),
+            client_addr,
+            route,
+            state: req.state().clone(),
+        }
+    }
+}
+
+/// The request, the response and the timing of an access record, passed to the replacements
+/// of `%{label}xc`, see
+/// [`TracingMiddlewareBuilder::custom_replace`](crate::TracingMiddlewareBuilder::custom_replace).
+///
+/// The request and the response are snapshots: the handler consumed the request, and the
+/// response body is sent to the client by the time the record is logged.
+///
+/// ```
+/// use tide_tracing_middleware::{LogContext, TracingMiddlewareBuilder};
+///
+/// fn bytes_per_second<State>(ctx: &LogContext<State>) -> String {
+///     let secs = ctx.elapsed().as_secs_f64();
+///     if secs == 0.0 {
+///         return "-".to_owned();
+///     }
+///     format!("{:.0}", ctx.body_size() as f64 / secs)
+/// }
+///
+/// let builder = TracingMiddlewareBuilder::<()>::new("%r %s %{bps}xc %{status_path}xc")
+///     .custom_replace("bps", bytes_per_second)
+///     .custom_replace("status_path", |ctx| {
+///         format!("{}:{}", ctx.status() as u16, ctx.url().path())
+///     });
+/// ```
+pub struct LogContext<State> {
+    request: RequestSnapshot<State>,
+    status: StatusCode,
+    response_headers: Headers,
+    elapsed: Duration,
+    body_size: usize,
+}
+
+impl<State> LogContext<State> {
+    pub(crate) fn new(request: RequestSnapshot<State>, resp: &Response) -> Self {
+        let headers: &Headers = resp.as_ref();
+        Self {
+            request,
+            status: resp.status(),
+            response_headers: headers.clone(),
+            elapsed: Duration::ZERO,
+            body_size: 0,
+        }
+    }
+
+    /// Set the timing and size known once the record is logged.
+    pub(crate) fn finish(&mut self, elapsed: Duration, body_size: usize) {
+        self.elapsed = elapsed;
+        self.body_size = body_size;
+    }
+
+    /// The method of the request.
+    pub fn method(&self) -> Method {
+        self.request.method
+    }
+
+    /// The URL of the request.
+    pub fn url(&self) -> &Url {
+        &self.request.url
+    }
+
+    /// The HTTP version of the request.
+    pub fn version(&self) -> Option<Version> {
+        self.request.version
+    }
+
+    /// The values of the request header `name`.
+    pub fn request_header(&self, name: impl Into<HeaderName>) -> Option<&HeaderValues> {
+        self.request.headers.get(name)
+    }
+
+    /// The address of the client, resolved like `%{r}a`.
+    pub fn client_addr(&self) -> Option<&str> {
+        self.request.client_addr.as_deref()
+    }
+
+    /// The route of the request, like `%R`.
+    pub fn route(&self) -> Option<&str> {
+        self.request.route.as_deref()
+    }
+
+    /// The state of the app.
+    pub fn state(&self) -> &State {
+        &self.request.state
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The values of the response header `name`.
+    pub fn response_header(&self, name: impl Into<HeaderName>) -> Option<&HeaderValues> {
+        self.response_headers.get(name)
+    }
+
+    /// The time taken to serve the request, like `%T`.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The size of the response body, like `%b`.
+    pub fn body_size(&self) -> usize {
+        self.body_size
+    }
+}
@@ -0,0 +1,67 @@
+//! Datadog log and trace correlation.
+//!
+//! Datadog links a log to an APM trace by the `dd.trace_id` and `dd.span_id` attributes, in
+//! the decimal form of the 64 bit IDs its tracers use.
+
+use std::fmt;
+
+use tracing::{debug, error, info, trace, warn, Level, Span};
+
+use crate::TraceContext;
+
+/// The IDs an access record is correlated by.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DatadogIds {
+    pub(crate) trace_id: u64,
+    pub(crate) span_id: u64,
+}
+
+impl DatadogIds {
+    /// The IDs of the request span with the `opentelemetry` feature, or else of the caller's
+    /// span from the trace context.
+    pub(crate) fn new(span: &Span, cx: Option<&TraceContext>) -> Option<Self> {
+        #[cfg(feature = "opentelemetry")]
+        if let Some((trace_id, span_id)) = crate::otel::span_ids(span) {
+            return Some(Self::from_bytes(trace_id, span_id));
+        }
+        let _ = span;
+        cx.map(|cx| Self::from_bytes(cx.trace_id(), cx.span_id()))
+    }
+
+    /// Datadog only keeps the lower 64 bits of 128 bit trace IDs.
+    fn from_bytes(trace_id: [u8; 16], span_id: [u8; 8]) -> Self {
+        let mut low = [0; 8];
+        low.copy_from_slice(&trace_id[8..]);
+        Self {
+            trace_id: u64::from_be_bytes(low),
+            span_id: u64::from_be_bytes(span_id),
+        }
+    }
+}
+
+/// The target of the access records emitted by the crate root.
+const TARGET: &str = "tide_tracing_middleware";
+
+/// Emit an access record with the correlation fields, see [`crate::emit`].
+pub(crate) fn emit(level: Level, parent: &Span, ids: DatadogIds, record: fmt::Arguments<'_>) {
+    let trace_id = ids.trace_id.to_string();
+    let span_id = ids.span_id.to_string();
+    macro_rules! emit {
+        ($event:ident) => {
+            $event!(
+                target: TARGET,
+                parent: parent,
+                { dd.trace_id = %trace_id, dd.span_id = %span_id },
+                "{}",
+                record
+            )
+        };
+    }
+    match level {
+        Level::ERROR => emit!(error),
+        Level::WARN => emit!(warn),
+        Level::INFO => emit!(info),
+        Level::DEBUG => emit!(debug),
+        _ => emit!(trace),
+    }
+}
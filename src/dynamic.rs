@@ -0,0 +1,120 @@
+//! Tracing events whose field names are only known at runtime.
+//!
+//! The tracing macros need the field names at compile time, but the fields of an access
+//! record depend on the configured format. A callsite is created and leaked for every
+//! distinct combination of level and field names. Callers pass every field they may record,
+//! without a value if it is missing, so the names only depend on the configuration.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use tracing::callsite::{self, Callsite};
+use tracing::field::{Field, FieldSet, Value};
+use tracing::level_filters::LevelFilter;
+use tracing::metadata::Kind;
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Span};
+
+/// The maximum number of fields of a single event, further fields are dropped.
+pub(crate) const MAX_FIELDS: usize = 64;
+
+const INTEREST_NEVER: u8 = 0;
+const INTEREST_SOMETIMES: u8 = 1;
+const INTEREST_ALWAYS: u8 = 2;
+
+struct DynamicCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+    interest: AtomicU8,
+}
+
+impl Callsite for DynamicCallsite {
+    fn set_interest(&self, interest: Interest) {
+        let interest = if interest.is_never() {
+            INTEREST_NEVER
+        } else if interest.is_always() {
+            INTEREST_ALWAYS
+        } else {
+            INTEREST_SOMETIMES
+        };
+        self.interest.store(interest, Ordering::SeqCst);
+    }
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata
+            .get()
+            .expect("callsite metadata is set before registration")
+    }
+}
+
+type CallsiteKey = (Level, Vec<String>);
+
+/// Returns the callsite for events at `level` with the given field `names`, creating and
+/// registering it on first use.
+fn callsite(level: Level, names: &[&str]) -> &'static DynamicCallsite {
+    static CALLSITES: OnceLock<Mutex<HashMap<CallsiteKey, &'static DynamicCallsite>>> =
+        OnceLock::new();
+
+    let key = (level, names.iter().map(|name| name.to_string()).collect());
+    let mut callsites = CALLSITES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(callsite) = callsites.get(&key) {
+        return callsite;
+    }
+
+    let cs: &'static DynamicCallsite = Box::leak(Box::new(DynamicCallsite {
+        metadata: OnceLock::new(),
+        interest: AtomicU8::new(INTEREST_SOMETIMES),
+    }));
+    let names: Vec<&'static str> = names
+        .iter()
+        .map(|name| &*Box::leak(name.to_string().into_boxed_str()))
+        .collect();
+    let names: &'static [&'static str] = Box::leak(names.into_boxed_slice());
+    let metadata = Metadata::new(
+        concat!("event ", file!(), ":", line!()),
+        module_path!().split("::").next().unwrap(),
+        level,
+        Some(file!()),
+        Some(line!()),
+        Some(module_path!()),
+        FieldSet::new(names, callsite::Identifier(cs)),
+        Kind::EVENT,
+    );
+    let _ = cs.metadata.set(metadata);
+    callsite::register(cs);
+
+    callsites.insert(key, cs);
+    cs
+}
+
+/// Emit an event at `level` with one field per `(name, value)` pair as a child of `parent`,
+/// the fields without a value are not recorded.
+pub(crate) fn emit(level: Level, parent: &Span, fields: &[(&str, Option<&dyn Value>)]) {
+    if fields.is_empty() || level > LevelFilter::current() {
+        return;
+    }
+    let fields = &fields[..fields.len().min(MAX_FIELDS)];
+
+    let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+    let cs = callsite(level, &names);
+    if cs.interest.load(Ordering::Relaxed) == INTEREST_NEVER {
+        return;
+    }
+    let metadata: &'static Metadata<'static> = cs.metadata.get().unwrap();
+    if !tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata)) {
+        return;
+    }
+
+    let keys: Vec<Field> = metadata.fields().iter().collect();
+    // `ValueSet` only accepts arrays, unused entries repeat the first field without a value
+    let values: [(&Field, Option<&dyn Value>); MAX_FIELDS] =
+        std::array::from_fn(|i| match fields.get(i) {
+            Some((_, value)) => (&keys[i], *value),
+            None => (&keys[0], None),
+        });
+    let values = metadata.fields().value_set(&values);
+    Event::child_of(parent.id(), metadata, &values);
+}
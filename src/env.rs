@@ -0,0 +1,75 @@
+//! The environment variables logged by `%{FOO}e`.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// An environment variable of the format, read once when the format is parsed, and again
+/// with [`refresh`](Self::refresh).
+#[derive(Debug, Clone)]
+pub(crate) struct EnvVar {
+    name: String,
+    value: Arc<RwLock<Option<String>>>,
+}
+
+impl EnvVar {
+    /// Read the variable `name`.
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: Arc::new(RwLock::new(std::env::var(name).ok())),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Call `f` with the value, `None` if the variable is not set or not unicode.
+    pub(crate) fn with_value<R>(&self, f: impl FnOnce(Option<&str>) -> R) -> R {
+        let value = self.value.read().unwrap_or_else(|err| err.into_inner());
+        f(value.as_deref())
+    }
+
+    /// Read the variable again.
+    pub(crate) fn refresh(&self) {
+        let value = std::env::var(&self.name).ok();
+        *self.value.write().unwrap_or_else(|err| err.into_inner()) = value;
+    }
+}
+
+/// When the variables are read again, see
+/// [`TracingMiddlewareBuilder::refresh_env`](crate::TracingMiddlewareBuilder::refresh_env).
+#[derive(Debug)]
+pub(crate) struct EnvRefresh {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl EnvRefresh {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Whether the variables are due to be read again at `now`, the first request starts the
+    /// interval. Requests racing with the one reading them do not wait for it.
+    pub(crate) fn is_due(&self, now: Instant) -> bool {
+        let mut next = match self.next.try_lock() {
+            Ok(next) => next,
+            Err(_) => return false,
+        };
+        match *next {
+            Some(at) if now < at => false,
+            Some(_) => {
+                *next = Some(now + self.interval);
+                true
+            }
+            None => {
+                *next = Some(now + self.interval);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Resolution of the client address behind reverse proxies.
+
+use std::net::{IpAddr, SocketAddr};
+
+use tide::Request;
+
+/// What the trusted proxies in front of the server forwarded about the original request,
+/// see [`TracingMiddlewareBuilder::trusted_proxy`](crate::TracingMiddlewareBuilder::trusted_proxy).
+///
+/// It is read from the RFC 7239 `Forwarded` header, or else from `X-Forwarded-For`,
+/// `X-Forwarded-Proto` and `X-Forwarded-Host`, and stored in the request extensions for
+/// requests from trusted proxies, so custom replacements and handlers can use it:
+///
+/// ```
+/// use tide::Request;
+/// use tide_tracing_middleware::ForwardedInfo;
+///
+/// fn scheme<State>(req: &Request<State>) -> String {
+///     req.ext::<ForwardedInfo>()
+///         .and_then(ForwardedInfo::proto)
+///         .unwrap_or_else(|| req.url().scheme())
+///         .to_owned()
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedInfo {
+    client: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl ForwardedInfo {
+    /// The address of the client, as `ip` or `ip:port`, or an obfuscated identifier like
+    /// `_hidden` or `unknown` from the `Forwarded` header.
+    pub fn client(&self) -> Option<&str> {
+        self.client.as_deref()
+    }
+
+    /// The protocol the client used, like `https`.
+    pub fn proto(&self) -> Option<&str> {
+        self.proto.as_deref()
+    }
+
+    /// The `Host` the client requested.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+}
+
+/// A range of IP addresses in CIDR notation, like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// The range containing only `addr`.
+    pub(crate) fn single(addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix_len }
+    }
+
+    /// Parse `addr/prefix_len`, or a single address without the prefix length.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let range = Self::single(addr.trim().parse().ok()?);
+        match prefix_len {
+            Some(prefix_len) => {
+                let prefix_len = prefix_len.trim().parse().ok()?;
+                (prefix_len <= range.prefix_len).then_some(Self {
+                    prefix_len,
+                    ..range
+                })
+            }
+            None => Some(range),
+        }
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4-mapped IPv6 addresses, as reported by dual-stack listeners, are IPv4 clients
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            addr => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The proxies whose forwarding headers are believed, see
+/// [`TracingMiddlewareBuilder::trusted_proxies`](crate::TracingMiddlewareBuilder::trusted_proxies).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrustedProxies(Vec<IpRange>);
+
+impl TrustedProxies {
+    pub(crate) fn insert(&mut self, range: IpRange) {
+        self.0.push(range);
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(addr))
+    }
+
+    /// Whether the address rendered as `ip`, `ip:port` or `[ip]:port` is a trusted proxy.
+    fn contains_addr(&self, addr: &str) -> bool {
+        parse_addr(addr).is_some_and(|ip| self.contains(ip))
+    }
+
+    /// Returns what the proxies forwarded about `req`, `None` if the peer is not a trusted
+    /// proxy or nothing was forwarded.
+    ///
+    /// The hops of `Forwarded`, or else `X-Forwarded-For`, are walked from right to left,
+    /// skipping trusted proxies, and the first other hop is the client. Without such a hop,
+    /// the client is the leftmost hop of `Forwarded` or `X-Real-IP`.
+    pub(crate) fn resolve<State>(&self, req: &Request<State>) -> Option<ForwardedInfo> {
+        if !req.peer_addr().is_some_and(|peer| self.contains_addr(peer)) {
+            return None;
+        }
+        let forwarded = header_values(req, "forwarded")
+            .map(parse_forwarded_element)
+            .collect::<Vec<_>>();
+        if !forwarded.is_empty() {
+            let hop = forwarded
+                .iter()
+                .rev()
+                .find(|hop| !hop.client.as_deref().is_some_and(|c| self.contains_addr(c)))
+                .unwrap_or(&forwarded[0]);
+            return Some(hop.clone());
+        }
+
+        let client = header_values(req, "x-forwarded-for")
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .find(|hop| !self.contains_addr(hop))
+            .or_else(|| req.header("x-real-ip").map(|value| value.as_str().trim()));
+        let info = ForwardedInfo {
+            client: client.map(str::to_owned),
+            proto: header_values(req, "x-forwarded-proto")
+                .next()
+                .map(str::to_owned),
+            host: header_values(req, "x-forwarded-host")
+                .next()
+                .map(str::to_owned),
+        };
+        Some(info).filter(|info| *info != ForwardedInfo::default())
+    }
+}
+
+/// The non-empty comma separated values of the header `name`, in order.
+fn header_values<'a, State>(
+    req: &'a Request<State>,
+    name: &'static str,
+) -> impl Iterator<Item = &'a str> {
+    req.header(name)
+        .into_iter()
+        .flatten()
+        .flat_map(|value| value.as_str().split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Parse an element of the `Forwarded` header, like `for=192.0.2.60;proto=http`.
+fn parse_forwarded_element(element: &str) -> ForwardedInfo {
+    let mut info = ForwardedInfo::default();
+    for pair in element.split(';') {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+            None => continue,
+        };
+        let field = match key.to_ascii_lowercase().as_str() {
+            "for" => &mut info.client,
+            "proto" => &mut info.proto,
+            "host" => &mut info.host,
+            _ => continue,
+        };
+        *field = Some(value.to_owned());
+    }
+    info
+}
+
+/// Parse an address rendered as `ip`, `ip:port`, `[ip]` or `[ip]:port`.
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
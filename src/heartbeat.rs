@@ -0,0 +1,59 @@
+//! Periodic events for requests that are still in progress.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{select, Either};
+use tracing::{Level, Span};
+
+use crate::dynamic;
+use crate::Clock;
+
+/// When the events are emitted, see
+/// [`TracingMiddlewareBuilder::heartbeat`](crate::TracingMiddlewareBuilder::heartbeat).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Heartbeat {
+    pub(crate) after: Duration,
+    pub(crate) every: Duration,
+}
+
+impl Heartbeat {
+    /// Emit the events for a request in the background until the guard is dropped.
+    pub(crate) fn start(
+        self,
+        clock: Arc<dyn Clock>,
+        span: Span,
+        method: String,
+        path: String,
+        request_id: Option<String>,
+    ) -> HeartbeatGuard {
+        let (stop, mut stopped) = oneshot::channel::<()>();
+        let start = clock.now();
+        async_std::task::spawn(async move {
+            let mut delay = self.after;
+            loop {
+                let tick = Box::pin(async_std::task::sleep(delay));
+                if let Either::Left(_) = select(&mut stopped, tick).await {
+                    return;
+                }
+                let elapsed = clock.now().saturating_duration_since(start).as_secs_f64();
+                let fields: [(&str, Option<&dyn tracing::field::Value>); 5] = [
+                    ("message", Some(&"request still in progress")),
+                    ("method", Some(&method)),
+                    ("path", Some(&path)),
+                    ("elapsed", Some(&elapsed)),
+                    ("request_id", request_id.as_ref().map(|id| id as _)),
+                ];
+                dynamic::emit(Level::WARN, &span, &fields);
+                delay = self.every;
+            }
+        });
+        HeartbeatGuard { _stop: stop }
+    }
+}
+
+/// Stops the events of a request when dropped, once the response body is finished.
+pub(crate) struct HeartbeatGuard {
+    _stop: oneshot::Sender<()>,
+}
@@ -0,0 +1,100 @@
+//! A Kafka sink for the access records.
+
+use std::fmt;
+use std::time::Duration;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaResult;
+use rdkafka::producer::{BaseRecord, DefaultProducerContext, Producer, ThreadedProducer};
+use tracing::error;
+
+use crate::{LogRecord, LogSink};
+
+/// A sink publishing each access record as a message to a Kafka topic, enabled with the
+/// `kafka` feature, for pipelines consuming the records as events. Use
+/// [`OutputFormat::Json`](crate::OutputFormat::Json) to publish structured records.
+///
+/// ```rust,no_run
+/// use tide_tracing_middleware::{KafkaSink, OutputFormat, TracingMiddlewareBuilder};
+///
+/// # fn main() -> rdkafka::error::KafkaResult<()> {
+/// let sink = KafkaSink::new("kafka-1:9092,kafka-2:9092", "access-log")?.key_field("request_id");
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::new("%t %a %M %U %s %D %{request_id}")
+///     .output(OutputFormat::Json)
+///     .sink(sink)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Messages are queued by librdkafka and delivered from its own thread, so publishing never
+/// blocks. Records are dropped, with an error event, while the queue is full. The messages
+/// still queued are delivered when the sink is dropped, waiting up to 5 seconds.
+pub struct KafkaSink {
+    producer: ThreadedProducer<DefaultProducerContext>,
+    topic: String,
+    key_field: Option<String>,
+}
+
+impl KafkaSink {
+    /// Publish to `topic` of the cluster with the comma separated `brokers`.
+    pub fn new(brokers: &str, topic: &str) -> KafkaResult<Self> {
+        Self::from_config(ClientConfig::new().set("bootstrap.servers", brokers), topic)
+    }
+
+    /// Publish to `topic` with a producer created from `config`, for the settings of TLS,
+    /// SASL or batching.
+    pub fn from_config(config: &ClientConfig, topic: &str) -> KafkaResult<Self> {
+        Ok(Self {
+            producer: config.create()?,
+            topic: topic.to_owned(),
+            key_field: None,
+        })
+    }
+
+    /// Key the messages by the value of the field `key`, like `request_id` or
+    /// `request_headers.x-tenant-id`, so the records with the same value are published to
+    /// the same partition. See [`LogRecord::get`].
+    pub fn key_field(mut self, key: &str) -> Self {
+        self.key_field = Some(key.to_owned());
+        self
+    }
+}
+
+impl LogSink for KafkaSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        let key = self.key_field.as_deref().and_then(|key| record.get(key));
+        for line in record.lines() {
+            let mut message = BaseRecord::<str, str>::to(&self.topic).payload(line);
+            if let Some(key) = &key {
+                message = message.key(key.as_ref());
+            }
+            if let Err((err, _)) = self.producer.send(message) {
+                error!(
+                    "failed to publish access record to Kafka topic {}: {}",
+                    self.topic, err
+                );
+            }
+        }
+    }
+}
+
+impl Drop for KafkaSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.producer.flush(Duration::from_secs(5)) {
+            error!(
+                "failed to deliver the queued access records to Kafka: {}",
+                err
+            );
+        }
+    }
+}
+
+impl fmt::Debug for KafkaSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .field("key_field", &self.key_field)
+            .finish_non_exhaustive()
+    }
+}
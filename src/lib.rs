@@ -1,24 +1,122 @@
-use std::collections::HashSet;
-use std::convert::TryFrom;
-use std::fmt::{self, Display, Error as fmtError, Formatter, Result as fmtResult};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Error as fmtError, Formatter, Result as fmtResult, Write as _};
+use std::hash::{BuildHasher, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use futures::AsyncRead;
+use futures::future::{join_all, BoxFuture};
+use futures::{AsyncRead, Future};
 use pin_project::{pin_project, pinned_drop};
-use regex::{Regex, RegexSet};
-use tide::http::headers::HeaderName;
-use tide::{Body, Middleware, Next, Request, Response};
-use time::OffsetDateTime;
-use tracing::{error, info, Span};
+use serde_json::{Map, Value};
+use smallvec::SmallVec;
+use tide::http::auth::BasicAuth;
+use tide::http::headers::{HeaderName, HeaderValues};
+use tide::http::Mime;
+use tide::{Body, Endpoint, Middleware, Next, Request, Response, StatusCode};
+use time::{OffsetDateTime, UtcOffset};
+use tracing::{debug, error, info, trace, warn, Level, Span};
 use tracing_futures::Instrument;
 
+mod anonymize;
+#[cfg(feature = "tracing-appender")]
+mod appender;
+mod body;
+mod buffer;
+mod clock;
+mod context;
+#[cfg(feature = "dd")]
+mod dd;
+mod dynamic;
+mod env;
+mod forwarded;
+mod heartbeat;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "loki")]
+mod loki;
+mod metrics;
+#[cfg(feature = "nats")]
+mod nats;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod panic;
+mod pattern;
+#[cfg(feature = "pii")]
+mod pii;
+mod propagation;
+mod rate_limit;
+mod record;
+mod request_id;
+mod scope;
+mod scrub;
+#[cfg(feature = "sentry")]
+mod sentry;
+mod sink;
+mod stats;
+mod statsd;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(any(feature = "loki", feature = "nats"))]
+mod worker;
+mod xray;
+
+pub use anonymize::IpAnonymization;
+use anonymize::IpAnonymizer;
+use body::{Captured, RequestSize};
+pub use body::{ContentTypeFilter, RequestBody};
+pub use clock::{Clock, SystemClock};
+pub use context::LogContext;
+use context::RequestSnapshot;
+use env::{EnvRefresh, EnvVar};
+pub use forwarded::ForwardedInfo;
+use forwarded::{IpRange, TrustedProxies};
+use heartbeat::{Heartbeat, HeartbeatGuard};
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+#[cfg(feature = "loki")]
+pub use loki::LokiSink;
+#[cfg(feature = "metrics")]
+pub use metrics::MetricNames;
+use metrics::{InFlight, MetricSinks, RequestMetrics};
+pub use metrics::{Metrics, DEFAULT_BUCKETS};
+#[cfg(feature = "nats")]
+pub use nats::NatsSink;
+pub use panic::OnPanic;
+use pattern::PatternSet;
+#[cfg(feature = "pii")]
+use pii::PiiHasher;
+pub use propagation::{Propagation, TraceContext};
+use rate_limit::RateLimiter;
+pub use record::LogRecord;
+pub use request_id::RequestId;
+use request_id::RequestIdConfig;
+pub use scope::PrefixConfig;
+use scope::Scopes;
+use scrub::QueryScrubber;
+pub use sink::{FileSink, LogSink, Rotation, TracingSink};
+use stats::Dropped;
+pub use stats::LogStats;
+pub use statsd::StatsdSink;
+#[cfg(feature = "syslog")]
+pub use syslog::{Facility, SyslogFormat, SyslogSink};
+use xray::XRayConfig;
+pub use xray::XRayTraceHeader;
+
 /// `TracingMiddleware` for logging request and response info to the terminal.
 ///
 /// ## Usage
 ///
-/// Create `TracingMiddleware` middleware with the specified `format`.
+/// Create `TracingMiddleware` middleware with the specified `format`, or configure it with
+/// [`TracingMiddlewareBuilder`].
 /// Default `TracingMiddleware` could be created with `default` method, it uses the
 /// default format:
 ///
@@ -26,7 +124,7 @@ use tracing_futures::Instrument;
 /// %a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T
 /// ```
 ///
-/// ```rust
+/// ```rust,no_run
 /// use tide::{Request, Response, StatusCode};
 /// use tide_tracing_middleware::TracingMiddleware;
 /// use tracing::Level;
@@ -55,500 +153,4767 @@ use tracing_futures::Instrument;
 ///
 /// - `%%`: The percent sign
 /// - `%a`: Remote IP-address (IP-address of proxy if using reverse proxy)
-/// - `%t`: Time when the request was started to process (in rfc3339 format)
+/// - `%h`: Remote IP-address without the port
+/// - `%t`: Time when the request was started to process (in rfc3339 format), see
+///   [`time_format`](TracingMiddlewareBuilder::time_format)
+/// - `%{clf}t`: Time when the request was started to process (in Common Log Format, e.g.
+///   `[10/Oct/2000:13:55:36 +0000]`)
+/// - `%{s}t`, `%{ms}t`, `%{us}t`: Time when the request was started to process, in seconds,
+///   milliseconds or microseconds since the Unix epoch
+/// - `%{iso}t`: Time when the request was started to process, in UTC with microseconds (e.g.
+///   `2024-01-01T00:00:00.123456Z`) regardless of the `time_format`
 /// - `%r`: First line of request
+/// - `%p`: Port of the server the request was received on
+/// - `%H`: Scheme of the request, `http` or `https`, as forwarded by
+///   [trusted proxies](TracingMiddlewareBuilder::trusted_proxy)
+/// - `%v`: Host the request was sent to without the port, from the `Host` header or the URL,
+///   as forwarded by [trusted proxies](TracingMiddlewareBuilder::trusted_proxy)
+/// - `%u`: User name of the Basic authentication, or of the
+///   [user extractor](TracingMiddlewareBuilder::user_extractor)
 /// - `%s`: Response status code
+/// - `%{color}s`: Response status code, colored by class with ANSI escape codes
 /// - `%b`: Size of response body in bytes, not including HTTP headers
+/// - `%C`: Content type of the response, like `application/json`. Note that `%Cn` is always
+///   the number of requests in flight, not `%C` followed by `n`
+/// - `%I`: Size of request body in bytes, its `Content-Length` or the number of bytes read by
+///   the handler
+/// - `%{clf}b`: Size of response body in bytes, `-` if no bytes were sent
+/// - `%{human}b`: Size of response body for humans, like `512 B` or `1.4 KiB`
+/// - `%{declared}b`: Length of the response body declared by the handler, `-` for streamed
+///   bodies of unknown length, whose `%b` is the number of bytes sent
 /// - `%T`: Time taken to serve the request, in seconds with floating fraction in .06f format
 /// - `%D`: Time taken to serve the request, in milliseconds
+/// - `%{ttfb}T`: Time until the handler returned the response, before the body is sent, in
+///   seconds. Unlike `%T`, it does not include the transfer of the body.
+/// - `%{ttfb}D`: Same as `%{ttfb}T`, in milliseconds
+/// - `%{s}T`, `%{ms}T`, `%{us}T`, `%{ns}T`: Time taken to serve the request, in seconds,
+///   milliseconds, or whole microseconds or nanoseconds. The number of fractional digits of
+///   seconds and milliseconds is set by
+///   [`duration_precision`](TracingMiddlewareBuilder::duration_precision)
+/// - `%Cn`: Number of requests in flight when the request started, including it
 /// - `%U`: Request URL
+/// - `%R`: Route template of the request, like `/users/:id`, see
+///   [`route`](TracingMiddlewareBuilder::route)
 /// - `%M`: Request method
 /// - `%V`: Request HTTP version
 /// - `%Q`: Request URL's query string
-/// - `%{r}a`: Real IP remote address **\***
-/// - `%{FOO}i`: request.headers['FOO']
+/// - `%E`: Error of the handler stored on the response, with its causes, like
+///   `failed to load user: connection refused`, see
+///   [`format_error`](TracingMiddlewareBuilder::format_error)
+/// - `%X`: `X` if the response body was not sent completely, because the client disconnected
+///   or the body failed to stream, `-` otherwise
+/// - `%{r}a`: Real IP remote address of the client behind
+///   [trusted proxies](TracingMiddlewareBuilder::trusted_proxy) **\***
+/// - `%{trace_id}`: Trace ID from the W3C `traceparent` (or B3, X-Ray) request headers, see
+///   [`TraceContext`]
+/// - `%{span_id}`: ID of the caller's span from the W3C `traceparent` (or B3, X-Ray) request headers
+/// - `%{request_id}`: [Request ID](TracingMiddlewareBuilder::request_id)
+/// - `%{xray_root}`: Root trace ID of the [AWS X-Ray trace header](TracingMiddlewareBuilder::xray)
+/// - `%{xray_self}`: Self segment of the [AWS X-Ray trace header](TracingMiddlewareBuilder::xray)
+/// - `%{body}ri`: [Captured request body](TracingMiddlewareBuilder::capture_request_body)
+/// - `%{body}ro`: [Captured response body](TracingMiddlewareBuilder::capture_response_body)
+/// - `%{FOO}i`: request.headers['FOO'], see
+///   [`join_header_values`](TracingMiddlewareBuilder::join_header_values) for headers with
+///   several values
 /// - `%{FOO}o`: response.headers['FOO']
-/// - `%{FOO}e`: os.environ['FOO']
-/// - `%{FOO}xi`: [custom request replacement](TracingMiddleware::custom_request_replace) labelled "FOO"
-/// - `%{FOO}xo`: [custom response replacement](TracingMiddleware::custom_response_replace) labelled "FOO"
+/// - `%{FOO}e`: os.environ['FOO'], read when the format is parsed, see
+///   [`refresh_env`](TracingMiddlewareBuilder::refresh_env)
+/// - `%{FOO}P`: Value of the route parameter `FOO`, like `42` for `:FOO` in `/users/:FOO`
+/// - `%{FOO}C`: Value of the request cookie `FOO`, see
+///   [`redact_cookie`](TracingMiddlewareBuilder::redact_cookie)
+/// - `%{FOO}xi`: [custom request replacement](TracingMiddlewareBuilder::custom_request_replace) labelled "FOO"
+/// - `%{FOO}xo`: [custom response replacement](TracingMiddlewareBuilder::custom_response_replace) labelled "FOO"
+/// - `%{FOO}xe`: [request extension](TracingMiddlewareBuilder::request_ext) labelled "FOO"
+/// - `%{FOO}xoe`: [response extension](TracingMiddlewareBuilder::response_ext) labelled "FOO"
+/// - `%{FOO}xc`: [custom replacement](TracingMiddlewareBuilder::custom_replace) labelled "FOO",
+///   with the request, response and timing
+///
+/// Headers, cookies, route parameters and extensions that are missing are logged as `-`, a
+/// fallback after a `?` in the key replaces it for that token, like `none` in `%{X-Request-Id?none}i`.
+///
+/// The format can also be composed in code with [`FormatBuilder`], or checked at compile time
+/// with [`tracing_format!`].
+///
+/// ## Output
+///
+/// By default every record is rendered as a single line of text following the format.
+/// With [`OutputFormat::Json`] each token of the format becomes a field of a single JSON
+/// object instead, and literal text between the tokens is ignored:
+///
+/// ```plain
+/// {"duration_ms":0.274,"method":"GET","path":"/index","request_headers":{"user-agent":"curl/7.64.1"},"status":200,"size":12}
+/// ```
+///
+/// Missing values (rendered as `-` in text mode) become `null`.
+///
+/// With [`OutputFormat::Fields`] the same keys are recorded as fields of the tracing event,
+/// headers are named like `request_headers.user-agent` and missing values are left out:
+///
+/// ```plain
+/// duration_ms=0.274 method="GET" path="/index" request_headers.user-agent="curl/7.64.1" status=200 size=12
+/// ```
+///
+/// With [`OutputFormat::Gelf`] the record is a GELF message, to be shipped to Graylog by a
+/// [sink](TracingMiddlewareBuilder::sink). The untruncated line is the `full_message` when
+/// [`max_line_len`](TracingMiddlewareBuilder::max_line_len) cut the `short_message`:
 ///
+/// ```plain
+/// {"version":"1.1","host":"web-1","short_message":"GET /index 200","timestamp":1706706309.123,"level":6,"_method":"GET","_path":"/index","_request_headers.user-agent":"curl/7.64.1","_status":200}
+/// ```
+///
+#[derive(Clone)]
 pub struct TracingMiddleware<State: Clone + Send + Sync + 'static> {
     inner: Arc<Inner<State>>,
 }
 
+#[derive(Clone)]
 struct Inner<State: Clone + Send + Sync + 'static> {
-    format: Format<State>,
+    format: Format,
     exclude: HashSet<String>,
-    exclude_regex: RegexSet,
+    exclude_regex: PatternSet,
+    include: HashSet<String>,
+    include_regex: PatternSet,
+    exclude_if: Vec<Arc<RequestPredicate<State>>>,
+    exclude_status: Vec<Range<u16>>,
+    scopes: Scopes,
+    redact_headers: HashSet<HeaderName>,
+    redact_cookies: HashSet<String>,
+    log_only_headers: Option<HashSet<HeaderName>>,
+    header_separator: Option<String>,
+    scrub_query: QueryScrubber,
+    trusted_proxies: TrustedProxies,
+    anonymize_ip: Option<IpAnonymizer>,
+    #[cfg(feature = "pii")]
+    hash_pii: Vec<FieldKey>,
+    #[cfg(feature = "pii")]
+    pii_hasher: PiiHasher,
+    escape_control_chars: bool,
+    max_line_len: Option<usize>,
+    capture_request_body: Option<usize>,
+    capture_response_body: Option<usize>,
+    capture_bodies_on_error: bool,
+    capture_content_types: Vec<ContentTypeFilter>,
+    sample_rate: f64,
+    max_logs_per_second: Option<u32>,
+    /// The limiter of `max_logs_per_second`, created by `build` with the final clock.
+    rate_limit: Option<Arc<RateLimiter>>,
+    slow_threshold: Option<Duration>,
+    only_slow: bool,
+    only_errors: bool,
     gen_tracing_span: Option<fn(&Request<State>) -> Span>,
+    #[cfg(feature = "opentelemetry")]
+    otel_span: bool,
+    #[cfg(feature = "sentry")]
+    sentry: bool,
+    propagation: Propagation,
+    request_id: Option<RequestIdConfig>,
+    trace_header: Option<HeaderName>,
+    xray: Option<XRayConfig>,
+    in_flight: Arc<AtomicUsize>,
+    w3c_directives_written: Arc<AtomicBool>,
+    metrics: MetricSinks,
+    route: Option<Arc<RouteFn<State>>>,
+    output: OutputFormat,
+    field_naming: FieldNaming,
+    gelf_host: Option<String>,
+    level: Level,
+    level_for_status: Option<Arc<StatusLevelFn>>,
+    on_log: Option<Arc<OnLogFn>>,
+    sinks: Vec<Arc<dyn LogSink>>,
+    format_error: Option<Arc<ErrorFn>>,
+    catch_panics: Option<OnPanic>,
+    log_request_start: bool,
+    heartbeat: Option<Heartbeat>,
+    log_point: LogPoint,
+    wrap_body: bool,
+    field_names: Vec<String>,
+    time_format: Option<Arc<TimeFn>>,
+    duration_precision: usize,
+    refresh_env: Option<Arc<EnvRefresh>>,
+    user_extractor: Option<Arc<UserFn<State>>>,
+    request_exts: HashMap<String, Arc<ExtFn<State>>>,
+    response_exts: HashMap<String, Arc<ResponseExtFn>>,
+    request_replacements: HashMap<String, RequestReplacement<State>>,
+    response_replacements: HashMap<String, ResponseReplacement>,
+    context_replacements: HashMap<String, CustomContextFn<State>>,
+    static_fields: Vec<(String, String)>,
+    field_fns: Vec<(String, Arc<FieldFn<State>>)>,
+    clock: Arc<dyn Clock>,
+    stats: LogStats,
 }
 
-impl<State> TracingMiddleware<State>
-where
-    State: Clone + Send + Sync + 'static,
-{
-    /// Create `TracingMiddleware` middleware with the specified `format`.
-    pub fn new(s: &str) -> Self {
+/// The paths excluded by [`TracingMiddlewareBuilder::exclude_health_checks`].
+const HEALTH_CHECK_PATHS: &[&str] = &["/healthz", "/livez", "/readyz", "/ping", "/metrics"];
+/// The user agent prefixes excluded by [`TracingMiddlewareBuilder::exclude_health_checks`].
+const HEALTH_CHECK_USER_AGENTS: &[&str] = &["kube-probe/", "ELB-HealthChecker/"];
+
+/// The headers redacted by default, see [`TracingMiddlewareBuilder::redact_header`].
+const DEFAULT_REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "set-cookie", "x-api-key"];
+/// The value logged in place of redacted headers.
+const REDACTED: &str = "[REDACTED]";
+
+type StatusLevelFn = dyn Fn(StatusCode) -> Level + Send + Sync;
+type OnLogFn = dyn Fn(&mut LogRecord<'_>) -> bool + Send + Sync;
+type ErrorFn = dyn Fn(&tide::Error) -> String + Send + Sync;
+type TimeFn = dyn Fn(OffsetDateTime) -> String + Send + Sync;
+type RouteFn<State> = dyn Fn(&Request<State>) -> String + Send + Sync;
+type UserFn<State> = dyn Fn(&Request<State>) -> Option<String> + Send + Sync;
+type ExtFn<State> = dyn Fn(&Request<State>) -> Option<String> + Send + Sync;
+type ResponseExtFn = dyn Fn(&Response) -> Option<String> + Send + Sync;
+type FieldFn<State> = dyn Fn(&Request<State>) -> Option<String> + Send + Sync;
+type RequestPredicate<State> = dyn Fn(&Request<State>) -> bool + Send + Sync;
+
+impl<State: Clone + Send + Sync + 'static> Inner<State> {
+    fn new(format: Format) -> Self {
         Self {
-            inner: Arc::new(Inner {
-                format: Format::new(s),
-                exclude: HashSet::new(),
-                exclude_regex: RegexSet::empty(),
-                gen_tracing_span: None,
-            }),
+            format,
+            exclude: HashSet::new(),
+            exclude_regex: PatternSet::default(),
+            include: HashSet::new(),
+            include_regex: PatternSet::default(),
+            exclude_if: Vec::new(),
+            exclude_status: Vec::new(),
+            scopes: Scopes::default(),
+            redact_cookies: HashSet::new(),
+            redact_headers: DEFAULT_REDACTED_HEADERS
+                .iter()
+                .map(|name| HeaderName::from(*name))
+                .collect(),
+            log_only_headers: None,
+            header_separator: None,
+            scrub_query: QueryScrubber::default(),
+            trusted_proxies: TrustedProxies::default(),
+            anonymize_ip: None,
+            #[cfg(feature = "pii")]
+            hash_pii: Vec::new(),
+            #[cfg(feature = "pii")]
+            pii_hasher: PiiHasher::random(),
+            escape_control_chars: true,
+            max_line_len: None,
+            capture_request_body: None,
+            capture_response_body: None,
+            capture_bodies_on_error: false,
+            capture_content_types: Vec::new(),
+            sample_rate: 1.0,
+            max_logs_per_second: None,
+            rate_limit: None,
+            slow_threshold: None,
+            only_slow: false,
+            only_errors: false,
+            gen_tracing_span: None,
+            #[cfg(feature = "opentelemetry")]
+            otel_span: false,
+            #[cfg(feature = "sentry")]
+            sentry: false,
+            propagation: Propagation::default(),
+            request_id: None,
+            trace_header: None,
+            xray: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            w3c_directives_written: Arc::new(AtomicBool::new(false)),
+            metrics: MetricSinks::default(),
+            route: None,
+            output: OutputFormat::Text,
+            field_naming: FieldNaming::Default,
+            gelf_host: None,
+            level: Level::INFO,
+            level_for_status: None,
+            on_log: None,
+            sinks: Vec::new(),
+            format_error: None,
+            catch_panics: None,
+            log_request_start: false,
+            heartbeat: None,
+            log_point: LogPoint::BodyEnd,
+            wrap_body: true,
+            field_names: Vec::new(),
+            time_format: None,
+            duration_precision: 6,
+            refresh_env: None,
+            user_extractor: None,
+            request_exts: HashMap::new(),
+            response_exts: HashMap::new(),
+            request_replacements: HashMap::new(),
+            response_replacements: HashMap::new(),
+            context_replacements: HashMap::new(),
+            static_fields: Vec::new(),
+            field_fns: Vec::new(),
+            clock: Arc::new(SystemClock),
+            stats: LogStats::default(),
         }
     }
 
-    /// Ignore and do not log access info for specified path.
-    pub fn exclude<T: Into<String>>(mut self, path: T) -> Self {
-        Arc::get_mut(&mut self.inner)
-            .unwrap()
-            .exclude
-            .insert(path.into());
-        self
+    /// Whether the record needs the response body to be sent, for its size, the time taken,
+    /// its content or whether it failed. Otherwise the body is left as is and the record is
+    /// written as soon as the handler returned.
+    fn needs_body(&self) -> bool {
+        self.instruments_body()
+            || self.format.0.iter().any(FormatText::needs_body)
+            || self.capture_response_body.is_some()
+            || self.slow_threshold.is_some()
+            // the hook and the sinks are given the size and the duration
+            || self.on_log.is_some()
+            || !self.sinks.is_empty()
+            // records of failed bodies pass these filters
+            || self.only_errors
+            || self.sample_rate < 1.0
+            || self.scopes.samples()
     }
 
-    /// Ignore and do not log access info for paths that match regex
-    pub fn exclude_regex<T: Into<String>>(mut self, path: T) -> Self {
-        let inner = Arc::get_mut(&mut self.inner).unwrap();
-        let mut patterns = inner.exclude_regex.patterns().to_vec();
-        patterns.push(path.into());
-        let regex_set = RegexSet::new(patterns).unwrap();
-        inner.exclude_regex = regex_set;
-        self
+    /// Read the environment variables of the format again if they are due at `now`, see
+    /// [`TracingMiddlewareBuilder::refresh_env`].
+    fn refresh_env(&self, now: Instant) {
+        if !self
+            .refresh_env
+            .as_ref()
+            .is_some_and(|refresh| refresh.is_due(now))
+        {
+            return;
+        }
+        for unit in &self.format.0 {
+            if let FormatText::EnvironHeader(var) = unit {
+                var.refresh();
+            }
+        }
     }
 
-    /// Register a function that receives a Request and returns a String for use in the
-    /// log line. The label passed as the first argument should match a replacement substring in
-    /// the logger format like `%{label}xi`.
-    ///
-    /// It is convention to print "-" to indicate no output instead of an empty string.
-    pub fn custom_request_replace(
-        mut self,
-        label: &str,
-        f: impl Fn(&Request<State>) -> String + Send + Sync + 'static,
-    ) -> Self {
-        let inner = Arc::get_mut(&mut self.inner).unwrap();
-
-        let ft = inner.format.0.iter_mut().find(
-            |ft| matches!(ft, FormatText::CustomRequest(unit_label, _) if label == unit_label),
-        );
-
-        if let Some(FormatText::CustomRequest(_, request_fn)) = ft {
-            // replace into None or previously registered fn using same label
-            request_fn.replace(CustomRequestFn {
-                inner_fn: Arc::new(f),
-            });
-        } else {
-            // non-printed request replacement function diagnostic
-            error!(
-                "Attempted to register custom request logging function for nonexistent label: {}",
-                label
-            );
+    /// The names of all the fields an [`OutputFormat::Fields`] event may have, the same for
+    /// every record so that events of a level share a single callsite.
+    fn event_field_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let units = self.format.0.iter();
+        let keys = units.filter_map(|unit| unit.field_key(self.field_naming));
+        let mut extra = Vec::new();
+        if self.field_naming == FieldNaming::Ecs {
+            extra.extend(["url.original", "ecs.version"]);
         }
-
-        self
+        #[cfg(feature = "dd")]
+        extra.extend(["dd.trace_id", "dd.span_id"]);
+        let added = self.on_log.as_ref().map(|_| EXTRA_FIELD.to_owned());
+        let all = keys
+            .map(FieldKey::flat_name)
+            .chain(extra.into_iter().map(str::to_owned))
+            .chain(self.static_fields.iter().map(|(key, _)| key.clone()))
+            .chain(self.field_fns.iter().map(|(key, _)| key.clone()))
+            .chain(added);
+        for name in all {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
     }
 
-    /// Register a function that receives a Response and returns a String for use in the
-    /// log line. The label passed as the first argument should match a replacement substring in
-    /// the logger format like `%{label}xo`.
-    ///
-    /// It is convention to print "-" to indicate no output instead of an empty string.
-    pub fn custom_response_replace(
-        mut self,
-        label: &str,
-        f: impl Fn(&Response) -> String + Send + Sync + 'static,
-    ) -> Self {
-        let inner = Arc::get_mut(&mut self.inner).unwrap();
-
-        let ft = inner.format.0.iter_mut().find(
-            |ft| matches!(ft, FormatText::CustomResponse(unit_label, _) if label == unit_label),
-        );
-
-        if let Some(FormatText::CustomResponse(_, response_fn)) = ft {
-            // replace into None or previously registered fn using same label
-            response_fn.replace(CustomResponseFn {
-                inner_fn: Arc::new(f),
-            });
-        } else {
-            // non-printed response replacement function diagnostic
-            error!(
-                "Attempted to register custom response logging function for nonexistent label: {}",
-                label
-            );
+    /// Whether the span, the metrics or the heartbeat need the response body to be sent, even
+    /// if no access record is written.
+    fn instruments_body(&self) -> bool {
+        #[cfg(feature = "opentelemetry")]
+        if self.otel_span {
+            return true;
         }
-
-        self
+        #[cfg(feature = "sentry")]
+        if self.sentry {
+            return true;
+        }
+        self.heartbeat.is_some() || self.metrics.is_enabled()
     }
 
-    pub fn gen_tracing_span(mut self, f: fn(&Request<State>) -> Span) -> Self {
-        let inner = Arc::get_mut(&mut self.inner).unwrap();
-        inner.gen_tracing_span.replace(f);
-        self
+    /// Whether no subscriber would record the access record of a request to `scope`, checked
+    /// in its `span` so span filters apply. The record is then neither rendered nor written.
+    /// It is always rendered for the hooks and sinks, which do not depend on the subscriber.
+    fn is_quiet(&self, scope: &PrefixConfig, span: &Span) -> bool {
+        if self.on_log.is_some() || !self.sinks.is_empty() {
+            return false;
+        }
+        #[cfg(feature = "sentry")]
+        if self.sentry {
+            return false;
+        }
+        // the most severe level the record can be written at, as the status is not known yet
+        let mut level = match (scope.get_level(), &self.level_for_status) {
+            (Some(level), _) => level,
+            (None, Some(_)) => Level::ERROR,
+            (None, None) => self.level,
+        };
+        if self.slow_threshold.is_some() {
+            level = level.min(Level::WARN);
+        }
+        !span.in_scope(|| enabled(level))
     }
-}
 
-impl<State: Clone + Send + Sync + 'static> Default for TracingMiddleware<State> {
-    /// Create `TracingMiddleware` middleware with format:
-    ///
-    /// ```ignore
-    /// %a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T
-    /// ```
-    fn default() -> Self {
-        Self {
-            inner: Arc::new(Inner {
-                format: Format::default(),
-                exclude: HashSet::new(),
-                exclude_regex: RegexSet::empty(),
-                gen_tracing_span: None,
-            }),
+    /// Whether the request is not instrumented and logged at all.
+    fn is_excluded(&self, req: &Request<State>) -> bool {
+        let path = req.url().path();
+        if self.exclude.contains(path)
+            || self.exclude_regex.is_match(path)
+            || self.exclude_if.iter().any(|f| f(req))
+        {
+            return true;
         }
+        let has_include = !self.include.is_empty() || !self.include_regex.is_empty();
+        has_include && !self.include.contains(path) && !self.include_regex.is_match(path)
     }
-}
 
-#[tide::utils::async_trait]
-impl<State> Middleware<State> for TracingMiddleware<State>
-where
-    State: Clone + Send + Sync + 'static,
-{
-    async fn handle(&self, request: Request<State>, next: Next<'_, State>) -> tide::Result {
-        let path = request.url().path();
-        if self.inner.exclude.contains(path) || self.inner.exclude_regex.is_match(path) {
-            return Ok(next.run(request).await);
+    /// Replace the rendered values of redacted headers and cookies, and of headers outside the
+    /// allowlist, in `slots`, the values of `self.format`. Missing values are still rendered
+    /// as `-`.
+    fn redact(&self, slots: &mut Slots) {
+        for (i, template) in self.format.0.iter().enumerate() {
+            let hidden = match template {
+                FormatText::RequestHeader(name) | FormatText::ResponseHeader(name) => {
+                    self.redact_headers.contains(name)
+                        || self
+                            .log_only_headers
+                            .as_ref()
+                            .is_some_and(|allowed| !allowed.contains(name))
+                }
+                FormatText::Cookie(name) => self.redact_cookies.contains(name),
+                _ => continue,
+            };
+            let rendered = match slots.get(i) {
+                Slot::Str(_) => slots.str(i) != Some("-"),
+                Slot::Values(_) => true,
+                Slot::Template => false,
+            };
+            if hidden && rendered {
+                slots.set(i, REDACTED);
+            }
         }
+    }
 
-        let now = OffsetDateTime::now_utc();
-        let mut format = self.inner.format.clone();
-        for unit in &mut format.0 {
-            unit.render_request(now, &request);
+    /// Replace the rendered values of the units selected by
+    /// [`TracingMiddlewareBuilder::hash_pii`] in `slots`, the values of `self.format`, with
+    /// their hashes.
+    #[cfg(feature = "pii")]
+    fn hash_pii(&self, slots: &mut Slots) {
+        if self.hash_pii.is_empty() {
+            return;
         }
+        for (i, template) in self.format.0.iter().enumerate() {
+            let selected = template
+                .field_key(FieldNaming::Default)
+                .is_some_and(|key| self.hash_pii.contains(&key));
+            if !selected {
+                continue;
+            }
+            if let Some(values) = slots.values_mut(i) {
+                for value in values {
+                    *value = self.pii_hasher.hash(value);
+                }
+            } else if let Some(value) = slots.str(i).filter(|value| *value != "-") {
+                let hashed = self.pii_hasher.hash(value);
+                slots.set(i, hashed);
+            }
+        }
+    }
 
-        let span = if let Some(f) = self.inner.gen_tracing_span.as_ref() {
-            f(&request)
-        } else {
-            Span::none()
-        };
-        let cloned_span = span.clone();
-
-        let mut resp = next.run(request).instrument(span).await;
+    /// Hash and escape `value`, the rendering of `template` after the other units, like
+    /// [`hash_pii`](Self::hash_pii) and [`escape`](Self::escape) do for them.
+    fn sanitize(&self, template: &FormatText, mut value: String) -> String {
+        #[cfg(feature = "pii")]
+        if value != "-"
+            && template
+                .field_key(FieldNaming::Default)
+                .is_some_and(|key| self.hash_pii.contains(&key))
+        {
+            value = self.pii_hasher.hash(&value);
+        }
+        #[cfg(not(feature = "pii"))]
+        let _ = template;
+        if self.escape_control_chars {
+            if let Cow::Owned(escaped) = escape_control_chars(&value) {
+                value = escaped;
+            }
+        }
+        value
+    }
 
-        for unit in &mut format.0 {
-            unit.render_response(&resp);
+    /// Escape the control characters of the rendered values in `slots`, the values of
+    /// `self.format`. The literal text of the format and `%{color}s` are left alone.
+    fn escape(&self, slots: &mut Slots) {
+        if !self.escape_control_chars {
+            return;
+        }
+        for (i, template) in self.format.0.iter().enumerate() {
+            if matches!(
+                template,
+                FormatText::Str(_) | FormatText::ColoredResponseStatus
+            ) {
+                continue;
+            }
+            if let Some(values) = slots.values_mut(i) {
+                for value in values {
+                    if let Cow::Owned(escaped) = escape_control_chars(value) {
+                        *value = escaped;
+                    }
+                }
+            } else if let Some(Cow::Owned(escaped)) = slots.str(i).map(escape_control_chars) {
+                slots.set(i, escaped);
+            }
         }
+    }
 
-        let body = resp.take_body();
-        let body_len = body.len();
-        let body_mime = body.mime().clone();
-        let mut new_body = Body::from_reader(
-            futures::io::BufReader::new(StreamLog {
-                body,
-                format,
-                size: 0,
-                time: now,
-                span: cloned_span,
-            }),
-            body_len,
-        );
-        new_body.set_mime(body_mime);
+    /// Replace the missing values in `slots`, the values of `self.format`, with the fallback
+    /// following them in the format, like `none` in `%{X-Request-Id?none}i`.
+    fn fallback(&self, slots: &mut Slots) {
+        for (i, template) in self.format.0.iter().enumerate().skip(1) {
+            if let FormatText::Fallback(fallback) = template {
+                if slots.str(i - 1) == Some("-") {
+                    slots.set(i - 1, fallback);
+                }
+            }
+        }
+    }
 
-        resp.set_body(new_body);
-        Ok(resp)
+    /// Evaluate the [per-request fields](TracingMiddlewareBuilder::field_fn) of `req`, the
+    /// missing ones are left out.
+    fn request_fields(&self, req: &Request<State>) -> Vec<(String, String)> {
+        self.field_fns
+            .iter()
+            .filter_map(|(key, f)| {
+                let value = f(req)?;
+                let value = if self.escape_control_chars {
+                    escape_control_chars(&value).into_owned()
+                } else {
+                    value
+                };
+                Some((key.clone(), value))
+            })
+            .collect()
     }
-}
 
-#[doc(hidden)]
-#[derive(Debug, Clone)]
-struct Format<State: Clone + Send + Sync + 'static>(Vec<FormatText<State>>);
+    /// Whether a body with `content_type` is captured, see
+    /// [`TracingMiddlewareBuilder::capture_body_if`].
+    fn captures_body(&self, content_type: Option<&Mime>) -> bool {
+        if self.capture_content_types.is_empty() {
+            return true;
+        }
+        content_type.is_some_and(|mime| {
+            self.capture_content_types
+                .iter()
+                .any(|filter| filter.matches(mime))
+        })
+    }
 
-impl<State: Clone + Send + Sync + 'static> Format<State> {
-    /// Create a `Format` from a format string.
-    ///
-    /// Returns `None` if the format string syntax is incorrect.
-    fn new(s: &str) -> Format<State> {
-        let fmt = Regex::new(r"%(\{([A-Za-z0-9\-_]+)\}([aioe]|xi|xo)|[atPrUsbTDMVQ]?)").unwrap();
+    /// The user of `req` for `%u`, see [`TracingMiddlewareBuilder::user_extractor`].
+    fn user(&self, req: &Request<State>) -> Option<String> {
+        if let Some(user) = self.user_extractor.as_ref().and_then(|f| f(req)) {
+            return Some(user);
+        }
+        BasicAuth::from_headers(req)
+            .ok()
+            .flatten()
+            .map(|auth| auth.username().to_owned())
+            .filter(|user| !user.is_empty())
+    }
 
-        let mut idx = 0;
-        let mut results = Vec::new();
-        for cap in fmt.captures_iter(s) {
-            let m = cap.get(0).unwrap();
-            let pos = m.start();
-            if idx != pos {
-                results.push(FormatText::Str(s[idx..pos].to_owned()));
+    /// Anonymize the rendered client addresses in `slots`, the values of `self.format`.
+    fn anonymize_ips(&self, slots: &mut Slots) {
+        let anonymizer = match &self.anonymize_ip {
+            Some(anonymizer) => anonymizer,
+            None => return,
+        };
+        for (i, template) in self.format.0.iter().enumerate() {
+            if let FormatText::RemoteAddr | FormatText::RemoteHost | FormatText::RealIPRemoteAddr =
+                template
+            {
+                if let Some(addr) = slots.str(i).filter(|addr| *addr != "-") {
+                    let addr = anonymizer.addr(addr);
+                    slots.set(i, addr);
+                }
             }
-            idx = m.end();
+        }
+    }
 
-            if let Some(key) = cap.get(2) {
-                results.push(match cap.get(3).unwrap().as_str() {
-                    "a" => {
-                        if key.as_str() == "r" {
-                            FormatText::RealIPRemoteAddr
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    "i" => FormatText::RequestHeader(HeaderName::try_from(key.as_str()).unwrap()),
-                    "o" => FormatText::ResponseHeader(HeaderName::try_from(key.as_str()).unwrap()),
-                    "e" => FormatText::EnvironHeader(key.as_str().to_owned()),
-                    "xi" => FormatText::CustomRequest(key.as_str().to_owned(), None),
-                    "xo" => FormatText::CustomResponse(key.as_str().to_owned(), None),
-                    _ => unreachable!(),
-                })
-            } else {
-                let m = cap.get(1).unwrap();
-                results.push(match m.as_str() {
-                    "%" => FormatText::Percent,
-                    "a" => FormatText::RemoteAddr,
-                    "t" => FormatText::RequestTime,
-                    "r" => FormatText::RequestLine,
-                    "s" => FormatText::ResponseStatus,
-                    "b" => FormatText::ResponseSize,
-                    "M" => FormatText::Method,
-                    "V" => FormatText::Version,
-                    "Q" => FormatText::Query,
-                    "U" => FormatText::UrlPath,
-                    "T" => FormatText::Time,
-                    "D" => FormatText::TimeMillis,
-                    _ => FormatText::Str(m.as_str().to_owned()),
-                });
+    /// Write `trace_id` to the configured trace header, unless the handler set it already.
+    fn expose_trace_id(&self, resp: &mut Response, trace_id: Option<String>) {
+        if let (Some(name), Some(trace_id)) = (&self.trace_header, trace_id) {
+            if resp.header(name).is_none() {
+                resp.insert_header(name, trace_id);
             }
         }
-        if idx != s.len() {
-            results.push(FormatText::Str(s[idx..].to_owned()));
-        }
-
-        Format(results)
     }
 }
 
-impl<State: Clone + Send + Sync + 'static> Default for Format<State> {
-    /// Return the default formatting style for the `TracingMiddleware`:
-    fn default() -> Self {
-        Format::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
-    }
+/// How the keys of structured output are named, see [`OutputFormat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldNaming {
+    /// Short names like `method`, `status` and `request_headers.user-agent`.
+    Default,
+    /// Names following the OpenTelemetry HTTP semantic conventions like
+    /// `http.request.method`, `http.response.status_code`, `url.path`, `client.address` and
+    /// `http.request.header.<name>`. The `Host` and `User-Agent` request headers become
+    /// `server.address` and `user_agent.original`.
+    ///
+    /// Tokens without such an attribute keep their default name.
+    OpenTelemetry,
+    /// Names following the Elastic Common Schema like `http.request.method`,
+    /// `http.response.status_code`, `url.path`, `source.ip` for `%{r}a`, without the port,
+    /// and `event.duration` for `%{ns}T`, in nanoseconds as ECS expects. `%t` becomes `@timestamp`, and the record
+    /// gets `url.original` from the path and query, and `ecs.version`.
+    ///
+    /// Tokens without such a field keep their default name.
+    Ecs,
 }
 
-/// A string of text to be logged. This is either one of the data
-/// fields supported by the `TracingMiddleware`, or a custom `String`.
-#[doc(hidden)]
-#[non_exhaustive]
-#[derive(Debug, Clone)]
-enum FormatText<State: Clone + Send + Sync + 'static> {
-    Str(String),
-    Percent,
-    RequestLine,
-    RequestTime,
-    ResponseStatus,
-    ResponseSize,
-    Time,
-    TimeMillis,
-    RemoteAddr,
-    RealIPRemoteAddr,
-    Method,
-    Version,
-    UrlPath,
-    Query,
-    RequestHeader(HeaderName),
-    ResponseHeader(HeaderName),
-    EnvironHeader(String),
-    CustomRequest(String, Option<CustomRequestFn<State>>),
-    CustomResponse(String, Option<CustomResponseFn>),
+/// The default mapping for [`TracingMiddlewareBuilder::level_for_status`]: `ERROR` for 5xx,
+/// `WARN` for 4xx and `INFO` otherwise.
+pub fn default_status_level(status: StatusCode) -> Level {
+    if status.is_server_error() {
+        Level::ERROR
+    } else if status.is_client_error() {
+        Level::WARN
+    } else {
+        Level::INFO
+    }
 }
 
-#[doc(hidden)]
-#[derive(Clone)]
-pub struct CustomRequestFn<State: Clone + Send + Sync + 'static> {
-    inner_fn: Arc<dyn Fn(&Request<State>) -> String + Sync + Send>,
+/// How an access record is written to the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A single line of text rendered from the format string.
+    Text,
+    /// A single JSON object with one field per format token.
+    Json,
+    /// A tracing event with one field per format token, named like the keys of
+    /// [`Json`](Self::Json), so subscribers and filters see real fields.
+    ///
+    /// The fields of an event are fixed when the middleware is built, so the fields that
+    /// [`on_log`](TracingMiddlewareBuilder::on_log) adds with [`LogRecord::set`] are written
+    /// together as a JSON object in the `extra` field.
+    Fields,
+    /// A GELF 1.1 message for Graylog: the text line is the `short_message`, the tokens are
+    /// additional fields named like in [`Fields`](Self::Fields) with a `_` prefix, and the
+    /// level is the syslog severity of the record's level.
+    Gelf,
+    /// A line of the W3C extended log format, one space separated column per format token,
+    /// like `cs-method cs-uri-stem sc-status time-taken`, where `%D` is converted to seconds.
+    /// The `#Version` and `#Fields` directives naming the columns are written as events of
+    /// their own before the first record, and given to the sinks with
+    /// [`LogRecord::directives`]. The sinks of the crate write each directive line before
+    /// the first record, as a message of its own for the syslog, Loki, Kafka and NATS sinks,
+    /// and the [`FileSink`] repeats them at the top of every new file.
+    W3c,
 }
 
-impl<State> CustomRequestFn<State>
-where
-    State: Clone + Send + Sync + 'static,
-{
-    fn call(&self, req: &Request<State>) -> String {
-        (self.inner_fn)(req)
-    }
+/// When an access record is written to the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogPoint {
+    /// Once the response body is finished or dropped, the default.
+    ///
+    /// When nothing logged depends on the body, no `%b`, `%T`, `%D`, `%X` or response body
+    /// and no metrics or slow threshold, the body is not wrapped and the record is written as
+    /// soon as the handler returned.
+    BodyEnd,
+    /// As soon as the handler returned the response, before the body is sent, for streaming
+    /// responses like server-sent events whose body may never finish.
+    ///
+    /// `%b` is the length of the body if it is known and `0` otherwise, which `%{declared}b`
+    /// tells apart, `%T` and `%D` are the time until the handler returned, and the response
+    /// body is not captured.
+    ResponseHead,
 }
 
-impl<State> fmt::Debug for CustomRequestFn<State>
+impl<State> TracingMiddleware<State>
 where
     State: Clone + Send + Sync + 'static,
 {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
-        f.write_str("custom_request_fn")
+    /// Create `TracingMiddleware` middleware with the specified `format`.
+    ///
+    /// Panics if the format is invalid, see [`try_new`](Self::try_new).
+    pub fn new(s: &str) -> Self {
+        TracingMiddlewareBuilder::new(s).build()
     }
-}
-
-#[doc(hidden)]
-#[derive(Clone)]
-pub struct CustomResponseFn {
-    inner_fn: Arc<dyn Fn(&Response) -> String + Sync + Send>,
-}
 
-impl CustomResponseFn {
-    fn call(&self, resp: &Response) -> String {
-        (self.inner_fn)(resp)
+    /// Create `TracingMiddleware` middleware with the specified `format`.
+    ///
+    /// Returns an error describing the first unknown or malformed token if the format is
+    /// invalid.
+    pub fn try_new(s: &str) -> Result<Self, FormatParseError> {
+        TracingMiddlewareBuilder::try_new(s).map(TracingMiddlewareBuilder::build)
     }
-}
 
-impl fmt::Debug for CustomResponseFn {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
-        f.write_str("custom_response_fn")
+    /// Create `TracingMiddleware` middleware which logs every request as a JSON object with
+    /// the format:
+    ///
+    /// ```plain
+    /// %t %a %M %U %Q %V %s %b %D %{Referer}i %{User-Agent}i
+    /// ```
+    ///
+    /// Use [`TracingMiddlewareBuilder::output`] to log other fields as JSON.
+    pub fn json() -> Self {
+        TracingMiddlewareBuilder::new("%t %a %M %U %Q %V %s %b %D %{Referer}i %{User-Agent}i")
+            .output(OutputFormat::Json)
+            .build()
     }
-}
 
-impl<State> FormatText<State>
-where
-    State: Clone + Send + Sync + 'static,
-{
-    fn render_request(&mut self, now: OffsetDateTime, req: &Request<State>) {
-        match &*self {
-            FormatText::RequestLine => {
-                *self = if let Some(query_str) = req.url().query() {
-                    FormatText::Str(format!(
-                        "{} {}?{} {}",
-                        req.method(),
-                        req.url().path(),
-                        query_str,
-                        req.version().as_ref().map_or("?", |v| v.as_ref())
-                    ))
-                } else {
-                    FormatText::Str(format!(
-                        "{} {} {}",
-                        req.method(),
-                        req.url().path(),
-                        req.version().as_ref().map_or("?", |v| v.as_ref())
-                    ))
-                };
-            }
-            FormatText::Method => *self = FormatText::Str(req.method().to_string()),
-            FormatText::Version => {
-                *self = FormatText::Str(
-                    req.version()
-                        .as_ref()
-                        .map_or("?".to_owned(), |v| v.to_string()),
-                )
-            }
-            FormatText::Query => {
-                *self = FormatText::Str(req.url().query().map_or("-".to_owned(), |v| v.to_string()))
-            }
-            FormatText::UrlPath => *self = FormatText::Str(req.url().path().to_string()),
-            FormatText::RequestTime => *self = FormatText::Str(now.format("%Y-%m-%dT%H:%M:%S")),
-            FormatText::RequestHeader(ref name) => {
-                let s = if let Some(val) = req.header(name) {
-                    if let Some(v) = val.get(0) {
-                        v.as_str()
-                    } else {
-                        "_"
-                    }
-                } else {
-                    "-"
-                };
-                *self = FormatText::Str(s.to_string());
-            }
-            FormatText::RemoteAddr => {
-                *self = if let Some(addr) = req.remote() {
-                    FormatText::Str(addr.to_string())
-                } else {
-                    FormatText::Str("-".to_string())
-                };
-            }
-            FormatText::RealIPRemoteAddr => {
-                *self = if let Some(remote) = req.peer_addr() {
-                    FormatText::Str(remote.to_string())
-                } else {
-                    FormatText::Str("-".to_string())
-                };
-            }
-            FormatText::CustomRequest(_, request_fn) => {
-                *self = match request_fn {
-                    Some(f) => FormatText::Str(f.call(req)),
-                    None => FormatText::Str("-".to_owned()),
-                };
-            }
-            _ => (),
-        }
+    /// Create `TracingMiddleware` middleware which logs every request as a JSON object with
+    /// [Elastic Common Schema](FieldNaming::Ecs) field names, for Elasticsearch and Kibana,
+    /// with the format:
+    ///
+    /// ```plain
+    /// %t %{r}a %M %U %Q %s %b %{ns}T %{Referer}i %{User-Agent}i
+    /// ```
+    pub fn ecs() -> Self {
+        TracingMiddlewareBuilder::new("%t %{r}a %M %U %Q %s %b %{ns}T %{Referer}i %{User-Agent}i")
+            .output(OutputFormat::Json)
+            .field_naming(FieldNaming::Ecs)
+            .build()
     }
 
-    fn render_response(&mut self, resp: &Response) {
-        match &*self {
-            FormatText::ResponseStatus => {
-                *self = FormatText::Str(format!("{}", resp.status() as u16))
-            }
-            FormatText::ResponseHeader(name) => {
-                let s = if let Some(val) = resp.header(name) {
-                    if let Some(v) = val.get(0) {
-                        v.as_str()
-                    } else {
-                        "-"
+    /// Create `TracingMiddleware` middleware which logs in the Apache Common Log Format:
+    ///
+    /// ```plain
+    /// %h - %u %{clf}t "%r" %s %{clf}b
+    /// ```
+    pub fn common_log() -> Self {
+        Self::new(r#"%h - %u %{clf}t "%r" %s %{clf}b"#)
+    }
+
+    /// Create `TracingMiddleware` middleware which logs in the Apache Combined Log Format:
+    ///
+    /// ```plain
+    /// %h - %u %{clf}t "%r" %s %{clf}b "%{Referer}i" "%{User-Agent}i"
+    /// ```
+    pub fn combined_log() -> Self {
+        Self::new(r#"%h - %u %{clf}t "%r" %s %{clf}b "%{Referer}i" "%{User-Agent}i""#)
+    }
+
+    /// Create `TracingMiddleware` middleware which logs like nginx's default `combined`
+    /// format followed by `$request_time` and `$upstream_response_time`, in seconds with a
+    /// millisecond resolution:
+    ///
+    /// ```plain
+    /// %h - %u %{clf}t "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{ttfb}T
+    /// ```
+    ///
+    /// The handler plays the upstream, so the last column is the time until it returned the
+    /// response.
+    pub fn nginx() -> Self {
+        TracingMiddlewareBuilder::new(
+            r#"%h - %u %{clf}t "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{ttfb}T"#,
+        )
+        .duration_precision(3)
+        .build()
+    }
+
+    /// Create `TracingMiddleware` middleware which logs in the column layout of AWS
+    /// Application Load Balancer access logs, so the Athena tables and queries written for
+    /// them can read the records:
+    ///
+    /// ```plain
+    /// %H %{iso}t - %a %v:%p 0.000 %{ttfb}T 0.000 %s %s %I %b "%r" "%{User-Agent}i" - - - "%{X-Amzn-Trace-Id}i" "-" "-" 0 %{iso}t "forward" "-" "-" "%v:%p" "%s" "-" "-" -
+    /// ```
+    ///
+    /// The service is both the load balancer and the target: the target processing time is
+    /// the time until the handler returned the response, the processing times of the load
+    /// balancer are 0, and the columns about TLS, certificates and target groups are `-`.
+    pub fn alb() -> Self {
+        TracingMiddlewareBuilder::new(concat!(
+            r#"%H %{iso}t - %a %v:%p 0.000 %{ttfb}T 0.000 %s %s %I %b "%r" "%{User-Agent}i" - - - "#,
+            r#""%{X-Amzn-Trace-Id}i" "-" "-" 0 %{iso}t "forward" "-" "-" "%v:%p" "%s" "-" "-" -"#,
+        ))
+        .duration_precision(3)
+        .build()
+    }
+
+    /// Create `TracingMiddleware` middleware like morgan's `dev` format, a terse output with
+    /// the status colored by class for local development:
+    ///
+    /// ```plain
+    /// %M %U %{color}s %D ms - %b
+    /// ```
+    pub fn dev() -> Self {
+        Self::new("%M %U %{color}s %D ms - %b")
+    }
+
+    /// Create `TracingMiddleware` middleware like morgan's `tiny` format:
+    ///
+    /// ```plain
+    /// %M %U %s %b - %D ms
+    /// ```
+    pub fn tiny() -> Self {
+        Self::new("%M %U %s %b - %D ms")
+    }
+
+    /// Create `TracingMiddleware` middleware like morgan's `short` format:
+    ///
+    /// ```plain
+    /// %h - %M %U %V %s %b - %D ms
+    /// ```
+    pub fn short() -> Self {
+        Self::new("%h - %M %U %V %s %b - %D ms")
+    }
+
+    /// A tide endpoint serving the [`Metrics`] of the middleware in the Prometheus text
+    /// exposition format. It serves no metrics if none are configured with
+    /// [`TracingMiddlewareBuilder::metrics`].
+    ///
+    /// ```rust
+    /// use tide_tracing_middleware::{Metrics, TracingMiddlewareBuilder};
+    ///
+    /// let tracing_middleware = TracingMiddlewareBuilder::default()
+    ///     .metrics(Metrics::new())
+    ///     .exclude("/metrics")
+    ///     .build();
+    /// let mut app = tide::new();
+    /// app.at("/metrics").get(tracing_middleware.metrics_endpoint());
+    /// app.with(tracing_middleware);
+    /// ```
+    pub fn metrics_endpoint(&self) -> impl Endpoint<State> {
+        let metrics = self.inner.metrics.prometheus.clone();
+        move |_req: Request<State>| {
+            let text = metrics.as_ref().map(Metrics::render).unwrap_or_default();
+            async move {
+                Ok(Response::builder(StatusCode::Ok)
+                    .body(text)
+                    .content_type("text/plain; version=0.0.4; charset=utf-8")
+                    .build())
+            }
+        }
+    }
+
+    /// A handle to the counters of the access records written and dropped by the middleware,
+    /// see [`LogStats`].
+    pub fn stats(&self) -> LogStats {
+        self.inner.stats.clone()
+    }
+
+    /// Turn the middleware back into a builder to change its configuration.
+    ///
+    /// The configuration is copied if the middleware has been cloned.
+    pub fn into_builder(self) -> TracingMiddlewareBuilder<State> {
+        TracingMiddlewareBuilder {
+            inner: Arc::try_unwrap(self.inner).unwrap_or_else(|inner| (*inner).clone()),
+        }
+    }
+
+    /// Set how access records are written, see [`OutputFormat`].
+    #[deprecated(note = "use `TracingMiddlewareBuilder::output` instead")]
+    pub fn output(self, output: OutputFormat) -> Self {
+        self.into_builder().output(output).build()
+    }
+
+    /// Ignore and do not log access info for specified path.
+    #[deprecated(note = "use `TracingMiddlewareBuilder::exclude` instead")]
+    pub fn exclude<T: Into<String>>(self, path: T) -> Self {
+        self.into_builder().exclude(path).build()
+    }
+
+    /// Ignore and do not log access info for paths that match regex
+    ///
+    /// Panics if the regex is invalid.
+    #[cfg(feature = "regex")]
+    #[deprecated(note = "use `TracingMiddlewareBuilder::exclude_regex` instead")]
+    pub fn exclude_regex<T: Into<String>>(self, path: T) -> Self {
+        self.into_builder().exclude_regex(path).build()
+    }
+
+    /// Ignore and do not log access info for paths that match regex
+    ///
+    /// Returns an error if the regex is invalid.
+    #[cfg(feature = "regex")]
+    #[deprecated(note = "use `TracingMiddlewareBuilder::try_exclude_regex` instead")]
+    pub fn try_exclude_regex<T: Into<String>>(self, path: T) -> Result<Self, regex::Error> {
+        self.into_builder()
+            .try_exclude_regex(path)
+            .map(TracingMiddlewareBuilder::build)
+    }
+
+    /// Register a function that receives a Request and returns a String for use in the
+    /// log line, see [`TracingMiddlewareBuilder::custom_request_replace`].
+    #[deprecated(note = "use `TracingMiddlewareBuilder::custom_request_replace` instead")]
+    pub fn custom_request_replace(
+        self,
+        label: &str,
+        f: impl Fn(&Request<State>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.into_builder().custom_request_replace(label, f).build()
+    }
+
+    /// Register a function that receives a Response and returns a String for use in the
+    /// log line, see [`TracingMiddlewareBuilder::custom_response_replace`].
+    #[deprecated(note = "use `TracingMiddlewareBuilder::custom_response_replace` instead")]
+    pub fn custom_response_replace(
+        self,
+        label: &str,
+        f: impl Fn(&Response) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.into_builder()
+            .custom_response_replace(label, f)
+            .build()
+    }
+
+    #[deprecated(note = "use `TracingMiddlewareBuilder::gen_tracing_span` instead")]
+    pub fn gen_tracing_span(self, f: fn(&Request<State>) -> Span) -> Self {
+        self.into_builder().gen_tracing_span(f).build()
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> Default for TracingMiddleware<State> {
+    /// Create `TracingMiddleware` middleware with format:
+    ///
+    /// ```ignore
+    /// %a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T
+    /// ```
+    fn default() -> Self {
+        TracingMiddlewareBuilder::default().build()
+    }
+}
+
+/// Builder for [`TracingMiddleware`].
+///
+/// The builder owns the configuration and is only turned into a shareable middleware by
+/// [`build`](Self::build), so it can be passed around and configured conditionally:
+///
+/// ```rust
+/// use tide_tracing_middleware::{TracingMiddleware, TracingMiddlewareBuilder};
+///
+/// fn configure(
+///     builder: TracingMiddlewareBuilder<()>,
+///     quiet: bool,
+/// ) -> TracingMiddlewareBuilder<()> {
+///     if quiet {
+///         builder.exclude("/health")
+///     } else {
+///         builder
+///     }
+/// }
+///
+/// let middleware: TracingMiddleware<()> =
+///     configure(TracingMiddlewareBuilder::new("%a %r %s %T"), true).build();
+/// ```
+pub struct TracingMiddlewareBuilder<State: Clone + Send + Sync + 'static> {
+    inner: Inner<State>,
+}
+
+impl<State> TracingMiddlewareBuilder<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    /// Create a builder with the specified `format`.
+    ///
+    /// Panics if the format is invalid, see [`try_new`](Self::try_new).
+    pub fn new(s: &str) -> Self {
+        Self::try_new(s).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Create a builder with the specified `format`.
+    ///
+    /// Returns an error describing the first unknown or malformed token if the format is
+    /// invalid.
+    pub fn try_new(s: &str) -> Result<Self, FormatParseError> {
+        Ok(Self {
+            inner: Inner::new(Format::try_new(s)?),
+        })
+    }
+
+    /// Create a builder with a format composed in code, see [`FormatBuilder`].
+    pub fn from_format(format: FormatBuilder<State>) -> Self {
+        Self {
+            inner: Inner::new(Format(format.0)),
+        }
+    }
+
+    /// Build the middleware.
+    pub fn build(mut self) -> TracingMiddleware<State> {
+        self.inner.wrap_body = self.inner.needs_body();
+        self.inner.field_names = self.inner.event_field_names();
+        let clock = &self.inner.clock;
+        self.inner.rate_limit = self
+            .inner
+            .max_logs_per_second
+            .map(|rate| Arc::new(RateLimiter::new(rate, clock.clone())));
+        TracingMiddleware {
+            inner: Arc::new(self.inner),
+        }
+    }
+
+    /// Set when access records are written, see [`LogPoint`].
+    pub fn log_at(mut self, point: LogPoint) -> Self {
+        self.inner.log_point = point;
+        self
+    }
+
+    /// Set how access records are written, see [`OutputFormat`].
+    pub fn output(mut self, output: OutputFormat) -> Self {
+        self.inner.output = output;
+        self
+    }
+
+    /// Ignore and do not log access info for specified path.
+    pub fn exclude<T: Into<String>>(mut self, path: T) -> Self {
+        self.inner.exclude.insert(path.into());
+        self
+    }
+
+    /// Ignore and do not log access info for paths that match regex, enabled with the `regex`
+    /// feature.
+    ///
+    /// Panics if the regex is invalid, see [`try_exclude_regex`](Self::try_exclude_regex).
+    #[cfg(feature = "regex")]
+    pub fn exclude_regex<T: Into<String>>(self, path: T) -> Self {
+        let path = path.into();
+        match self.try_exclude_regex(path.as_str()) {
+            Ok(this) => this,
+            Err(err) => panic!("invalid exclude regex `{}`: {}", path, err),
+        }
+    }
+
+    /// Ignore and do not log access info for paths that match regex, enabled with the `regex`
+    /// feature.
+    ///
+    /// Returns an error if the regex is invalid.
+    #[cfg(feature = "regex")]
+    pub fn try_exclude_regex<T: Into<String>>(mut self, path: T) -> Result<Self, regex::Error> {
+        self.inner.exclude_regex.push(path.into())?;
+        Ok(self)
+    }
+
+    /// Ignore and do not log access info for requests `f` returns `true` for, so the decision
+    /// can look at headers, query parameters or the state:
+    ///
+    /// ```rust
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .exclude_if(|req| req.header("X-Internal-Token").is_some());
+    /// ```
+    pub fn exclude_if(
+        mut self,
+        f: impl Fn(&Request<State>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.exclude_if.push(Arc::new(f));
+        self
+    }
+
+    /// Ignore and do not log access info for common health checks: the paths `/healthz`,
+    /// `/livez`, `/readyz`, `/ping` and `/metrics`, and requests from the Kubernetes and AWS
+    /// ELB probes (user agents `kube-probe/*` and `ELB-HealthChecker/*`).
+    pub fn exclude_health_checks(mut self) -> Self {
+        for path in HEALTH_CHECK_PATHS {
+            self = self.exclude(*path);
+        }
+        self.exclude_if(|req| {
+            req.header("User-Agent").is_some_and(|user_agent| {
+                HEALTH_CHECK_USER_AGENTS
+                    .iter()
+                    .any(|prefix| user_agent.as_str().starts_with(prefix))
+            })
+        })
+    }
+
+    /// Override the level, sampling or exclusion for the requests under the URL `prefix`,
+    /// like `/static` or `/static/*`, which matches `/static` and `/static/app.js` but not
+    /// `/statics`.
+    ///
+    /// When several prefixes match, each setting is taken from the longest prefix that sets
+    /// it, and then from the middleware.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::{PrefixConfig, TracingMiddlewareBuilder};
+    /// use tracing::Level;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .prefix_config("/static/*", PrefixConfig::new().sample_rate(0.01))
+    ///     .prefix_config("/api", PrefixConfig::new().level(Level::DEBUG))
+    ///     .prefix_config("/api/internal", PrefixConfig::new().exclude(true));
+    /// ```
+    pub fn prefix_config(mut self, prefix: &str, config: PrefixConfig) -> Self {
+        self.inner.scopes.insert(prefix, config);
+        self
+    }
+
+    /// Log `[REDACTED]` instead of the value of the request or response header `name`.
+    ///
+    /// `Authorization`, `Cookie`, `Set-Cookie` and `X-Api-Key` are redacted by default, see
+    /// [`unredact_header`](Self::unredact_header).
+    pub fn redact_header(mut self, name: &str) -> Self {
+        self.inner.redact_headers.insert(HeaderName::from(name));
+        self
+    }
+
+    /// Log `[REDACTED]` instead of the value of the request cookie `name` in `%{name}C`.
+    ///
+    /// Cookies named in the format are logged even though the `Cookie` header is redacted by
+    /// default, this hides them in formats shared between deployments. Use
+    /// [`hash_pii`](Self::hash_pii) to correlate requests by a session cookie without logging
+    /// it.
+    pub fn redact_cookie(mut self, name: &str) -> Self {
+        self.inner.redact_cookies.insert(name.to_owned());
+        self
+    }
+
+    /// Log the value of the header `name`, even if it is redacted by default.
+    pub fn unredact_header(mut self, name: &str) -> Self {
+        self.inner.redact_headers.remove(&HeaderName::from(name));
+        self
+    }
+
+    /// Only log the values of the headers in `names`, the other headers are logged as
+    /// `[REDACTED]` like [redacted](Self::redact_header) ones, whatever the format asks for.
+    /// Redacted headers stay redacted even if they are listed.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .log_only_headers(["content-type", "accept", "x-request-id"]);
+    /// ```
+    pub fn log_only_headers<I, T>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        self.inner
+            .log_only_headers
+            .get_or_insert_with(HashSet::new)
+            .extend(
+                names
+                    .into_iter()
+                    .map(|name| HeaderName::from(name.as_ref())),
+            );
+        self
+    }
+
+    /// Log all the values of headers sent several times, like `Accept`, `Via` or
+    /// `Set-Cookie`, joined with `separator` in `%{FOO}i` and `%{FOO}o`. Only the first value
+    /// is logged by default.
+    ///
+    /// The JSON output logs the values as an array instead.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %{Via}i")
+    ///     .join_header_values(", ");
+    /// ```
+    pub fn join_header_values<T: Into<String>>(mut self, separator: T) -> Self {
+        self.inner.header_separator = Some(separator.into());
+        self
+    }
+
+    /// Log `***` instead of the value of the query parameter `name`, like `token`, in `%Q`,
+    /// `%r` and the OpenTelemetry span.
+    pub fn scrub_query_param<T: Into<String>>(mut self, name: T) -> Self {
+        self.inner.scrub_query.names.insert(name.into());
+        self
+    }
+
+    /// Log `***` instead of the values of the query parameters whose name matches regex, like
+    /// `(?i)token|secret`, see [`scrub_query_param`](Self::scrub_query_param). Enabled with
+    /// the `regex` feature.
+    ///
+    /// Panics if the regex is invalid, see
+    /// [`try_scrub_query_param_regex`](Self::try_scrub_query_param_regex).
+    #[cfg(feature = "regex")]
+    pub fn scrub_query_param_regex<T: Into<String>>(self, name: T) -> Self {
+        let name = name.into();
+        match self.try_scrub_query_param_regex(name.as_str()) {
+            Ok(this) => this,
+            Err(err) => panic!("invalid query parameter regex `{}`: {}", name, err),
+        }
+    }
+
+    /// Log `***` instead of the values of the query parameters whose name matches regex, see
+    /// [`scrub_query_param`](Self::scrub_query_param). Enabled with the `regex` feature.
+    ///
+    /// Returns an error if the regex is invalid.
+    #[cfg(feature = "regex")]
+    pub fn try_scrub_query_param_regex<T: Into<String>>(
+        mut self,
+        name: T,
+    ) -> Result<Self, regex::Error> {
+        self.inner.scrub_query.regex.push(name.into())?;
+        Ok(self)
+    }
+
+    /// Believe the forwarding headers of requests from the reverse proxy or load balancer at
+    /// `addr` when resolving `%{r}a`, and store them as [`ForwardedInfo`] in the request
+    /// extensions.
+    ///
+    /// The RFC 7239 `Forwarded` header, or else `X-Forwarded-For`, is walked from right to
+    /// left, skipping trusted proxies, and the first other hop is logged as the client.
+    /// Without such a hop the leftmost hop of `Forwarded` or `X-Real-IP` is logged, if
+    /// present. Requests from other peers log the peer address, so clients can not spoof
+    /// their address by sending these headers themselves.
+    pub fn trusted_proxy(mut self, addr: IpAddr) -> Self {
+        self.inner.trusted_proxies.insert(IpRange::single(addr));
+        self
+    }
+
+    /// Trust the proxies in the address ranges in CIDR notation, like `10.0.0.0/8`, or single
+    /// addresses, see [`trusted_proxy`](Self::trusted_proxy).
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .trusted_proxies(["10.0.0.0/8", "192.168.0.0/16", "fd00::/8"]);
+    /// ```
+    ///
+    /// Panics if a range is invalid.
+    pub fn trusted_proxies<I, T>(mut self, ranges: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        for range in ranges {
+            let range = range.as_ref();
+            match IpRange::parse(range) {
+                Some(parsed) => self.inner.trusted_proxies.insert(parsed),
+                None => panic!("invalid trusted proxy range `{}`", range),
+            }
+        }
+        self
+    }
+
+    /// Zero the last part of the client addresses in `%a`, `%h`, `%{r}a` and the
+    /// OpenTelemetry span, so access logs can be kept without storing full personal data.
+    ///
+    /// Both IPv4 and IPv6 addresses are anonymized, by default the last octet of IPv4 and the
+    /// last 80 bits of IPv6 addresses are zeroed, call this once per family to change it.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::{IpAnonymization, TracingMiddlewareBuilder};
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .anonymize_ip(IpAnonymization::TruncateV4Octets(1))
+    ///     .anonymize_ip(IpAnonymization::TruncateV6Bits(80));
+    /// ```
+    pub fn anonymize_ip(mut self, mode: IpAnonymization) -> Self {
+        self.inner
+            .anonymize_ip
+            .get_or_insert_with(Default::default)
+            .set(mode);
+        self
+    }
+
+    /// Log a salted hash instead of the value of the format token `token`, like `%a`,
+    /// `%{X-User-Id}i` or `%{user}xi`, so requests of the same client or user can still be
+    /// correlated without storing the identifier itself.
+    ///
+    /// The hash is the first 8 bytes of the HMAC-SHA256 of the value keyed with the
+    /// [`pii_salt`](Self::pii_salt), as hex.
+    ///
+    /// Panics if `token` is not a single format token. Enabled with the `pii` feature.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%a %{X-User-Id}i %r %s")
+    ///     .hash_pii("%a")
+    ///     .hash_pii("%{X-User-Id}i")
+    ///     .pii_salt("change me");
+    /// ```
+    #[cfg(feature = "pii")]
+    pub fn hash_pii(mut self, token: &str) -> Self {
+        let format = Format::new(token);
+        let key = match format.0.as_slice() {
+            [unit] => unit.field_key(FieldNaming::Default),
+            _ => None,
+        };
+        match key {
+            Some(key) => self.inner.hash_pii.push(key),
+            None => panic!("`{}` is not a single format token", token),
+        }
+        self
+    }
+
+    /// Set the salt of the hashes of [`hash_pii`](Self::hash_pii). Without a salt a random
+    /// one is used, and the hashes of the same value differ between processes.
+    #[cfg(feature = "pii")]
+    pub fn pii_salt<T: Into<Vec<u8>>>(mut self, salt: T) -> Self {
+        self.inner.pii_hasher = PiiHasher::new(salt.into());
+        self
+    }
+
+    /// Whether control characters in the logged values are escaped, enabled by default.
+    ///
+    /// Header values, URLs and custom replacements can contain line breaks or ANSI escape
+    /// sequences that forge log lines or rewrite the terminal. `\n`, `\r`, `\t` and
+    /// backslashes are escaped like in Rust strings, and the other control characters,
+    /// including the Unicode bidirectional overrides, as `\u{..}`.
+    pub fn escape_control_chars(mut self, enabled: bool) -> Self {
+        self.inner.escape_control_chars = enabled;
+        self
+    }
+
+    /// Truncate access records longer than `len` bytes, so a pathological URL or header
+    /// can't produce huge log lines. The truncated record ends with a marker like
+    /// `...[truncated 1234 bytes]`.
+    ///
+    /// The JSON and fields outputs truncate each value instead, to keep the records valid.
+    pub fn max_line_len(mut self, len: usize) -> Self {
+        self.inner.max_line_len = Some(len);
+        self
+    }
+
+    /// Buffer up to `max_bytes` of each request body for the `%{body}ri` token and custom
+    /// replacements, see [`RequestBody`]. The handler still receives the whole body.
+    ///
+    /// The bytes are read before the handler runs, so only enable it while debugging
+    /// payloads or with a small limit.
+    pub fn capture_request_body(mut self, max_bytes: usize) -> Self {
+        self.inner.capture_request_body = Some(max_bytes);
+        self
+    }
+
+    /// Copy up to `max_bytes` of each response body for the `%{body}ro` token while it is
+    /// sent to the client.
+    pub fn capture_response_body(mut self, max_bytes: usize) -> Self {
+        self.inner.capture_response_body = Some(max_bytes);
+        self
+    }
+
+    /// Only log the captured request and response bodies for responses with a status of 400
+    /// or above, and for response bodies that fail to stream, `-` otherwise.
+    ///
+    /// The bodies are still captured for every request, since the status is only known once
+    /// the handler is done.
+    pub fn capture_bodies_on_error(mut self, enabled: bool) -> Self {
+        self.inner.capture_bodies_on_error = enabled;
+        self
+    }
+
+    /// Only capture request and response bodies with a content type matching `filter`, and
+    /// the other filters, so binary uploads and images never end up in the logs. Bodies
+    /// without a content type are not captured once any filter is set.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::{ContentTypeFilter, TracingMiddlewareBuilder};
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s %{body}ri %{body}ro")
+    ///     .capture_request_body(4096)
+    ///     .capture_response_body(4096)
+    ///     .capture_body_if(ContentTypeFilter::Json)
+    ///     .capture_body_if(ContentTypeFilter::Form);
+    /// ```
+    pub fn capture_body_if(mut self, filter: ContentTypeFilter) -> Self {
+        self.inner.capture_content_types.push(filter);
+        self
+    }
+
+    /// Only log access info for the specified path, and the other included paths.
+    ///
+    /// Once any path is included, all paths that are not included are ignored like excluded
+    /// paths. Exclusions still apply to included paths.
+    pub fn include<T: Into<String>>(mut self, path: T) -> Self {
+        self.inner.include.insert(path.into());
+        self
+    }
+
+    /// Only log access info for paths that match regex, and the other included paths, see
+    /// [`include`](Self::include). Enabled with the `regex` feature.
+    ///
+    /// Panics if the regex is invalid, see [`try_include_regex`](Self::try_include_regex).
+    #[cfg(feature = "regex")]
+    pub fn include_regex<T: Into<String>>(self, path: T) -> Self {
+        let path = path.into();
+        match self.try_include_regex(path.as_str()) {
+            Ok(this) => this,
+            Err(err) => panic!("invalid include regex `{}`: {}", path, err),
+        }
+    }
+
+    /// Only log access info for paths that match regex, and the other included paths, see
+    /// [`include`](Self::include). Enabled with the `regex` feature.
+    ///
+    /// Returns an error if the regex is invalid.
+    #[cfg(feature = "regex")]
+    pub fn try_include_regex<T: Into<String>>(mut self, path: T) -> Result<Self, regex::Error> {
+        self.inner.include_regex.push(path.into())?;
+        Ok(self)
+    }
+
+    /// Do not log access info for responses with the status `status`.
+    ///
+    /// Unlike excluded paths, the requests are still instrumented and counted in the metrics.
+    pub fn exclude_status(self, status: StatusCode) -> Self {
+        let status = status as u16;
+        self.exclude_status_range(status..status + 1)
+    }
+
+    /// Do not log access info for responses with a status in `range`, like `200..300`.
+    ///
+    /// Unlike excluded paths, the requests are still instrumented and counted in the metrics.
+    pub fn exclude_status_range(mut self, range: Range<u16>) -> Self {
+        self.inner.exclude_status.push(range);
+        self
+    }
+
+    /// Only log access info for a random fraction `rate` of the successful requests, like
+    /// `0.01` for 1%. Responses with a status of 400 or above, a body that fails to stream,
+    /// or a duration above the [`slow_request_threshold`](Self::slow_request_threshold) are
+    /// always logged, since the decision is only made once the response is finished.
+    ///
+    /// The requests are still instrumented and counted in the metrics.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.inner.sample_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Log at most `rate` access records per second, with bursts of up to one second worth of
+    /// records. Excess records are dropped, and a warning with the number of suppressed
+    /// records is logged with the next record that is let through, at most once a second.
+    /// If no record is let through, a timer logs the warning a second after the first
+    /// suppressed record, outside of any request span. Records suppressed less than a second
+    /// before the process exits are not reported.
+    ///
+    /// The requests are still instrumented and counted in the metrics.
+    pub fn max_logs_per_second(mut self, rate: u32) -> Self {
+        self.inner.max_logs_per_second = Some(rate);
+        self
+    }
+
+    /// Log the access records of requests taking at least `threshold` at `WARN`, unless
+    /// their level is more severe already.
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.inner.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Only log access info for requests taking at least `threshold`, which turns the access
+    /// log into a slow request log. This sets the
+    /// [`slow_request_threshold`](Self::slow_request_threshold) too.
+    ///
+    /// The requests are still instrumented and counted in the metrics.
+    pub fn only_slow_requests(mut self, threshold: Duration) -> Self {
+        self.inner.slow_threshold = Some(threshold);
+        self.inner.only_slow = true;
+        self
+    }
+
+    /// Only log access info for responses with a status of 400 or above, and for response
+    /// bodies that fail to stream.
+    ///
+    /// The requests are still instrumented and counted in the metrics.
+    pub fn only_errors(mut self, enabled: bool) -> Self {
+        self.inner.only_errors = enabled;
+        self
+    }
+
+    /// Register a function that receives a Request and returns a String for use in the
+    /// log line. The label passed as the first argument should match a replacement substring in
+    /// the logger format like `%{label}xi`.
+    ///
+    /// Functions are looked up by label when the record is rendered, labels that are not in
+    /// the format are ignored. It is convention to print "-" to indicate no output instead of
+    /// an empty string, unregistered labels are logged as "-".
+    pub fn custom_request_replace(
+        mut self,
+        label: &str,
+        f: impl Fn(&Request<State>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let f = CustomRequestFn {
+            inner_fn: Arc::new(f),
+        };
+        self.inner
+            .request_replacements
+            .insert(label.to_owned(), RequestReplacement::Sync(f));
+        self
+    }
+
+    /// Register a function that receives a Response and returns a String for use in the
+    /// log line. The label passed as the first argument should match a replacement substring in
+    /// the logger format like `%{label}xo`.
+    ///
+    /// Functions are looked up by label when the record is rendered, labels that are not in
+    /// the format are ignored. It is convention to print "-" to indicate no output instead of
+    /// an empty string, unregistered labels are logged as "-".
+    pub fn custom_response_replace(
+        mut self,
+        label: &str,
+        f: impl Fn(&Response) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let f = CustomResponseFn {
+            inner_fn: Arc::new(f),
+        };
+        self.inner
+            .response_replacements
+            .insert(label.to_owned(), ResponseReplacement::Sync(f));
+        self
+    }
+
+    /// Like [`custom_request_replace`](Self::custom_request_replace), for functions that
+    /// need async state like a session store. The future returned by `f` is awaited before
+    /// the handler runs, and can't borrow the request: `f` has to copy what it needs.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// async fn lookup_session(id: Option<String>) -> String {
+    ///     id.unwrap_or_else(|| "-".to_owned())
+    /// }
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s %{session}xi")
+    ///     .custom_request_replace_async("session", |req| {
+    ///         let id = req.header("x-session-id").map(|id| id.as_str().to_owned());
+    ///         lookup_session(id)
+    ///     });
+    /// ```
+    pub fn custom_request_replace_async<F, Fut>(mut self, label: &str, f: F) -> Self
+    where
+        F: Fn(&Request<State>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let f = AsyncRequestFn {
+            inner_fn: Arc::new(move |req: &Request<State>| -> BoxFuture<'static, String> {
+                Box::pin(f(req))
+            }),
+        };
+        self.inner
+            .request_replacements
+            .insert(label.to_owned(), RequestReplacement::Async(f));
+        self
+    }
+
+    /// Like [`custom_response_replace`](Self::custom_response_replace), for functions that
+    /// need async state. The future returned by `f` is awaited after the handler returned the
+    /// response, and can't borrow the response: `f` has to copy what it needs.
+    pub fn custom_response_replace_async<F, Fut>(mut self, label: &str, f: F) -> Self
+    where
+        F: Fn(&Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + 'static,
+    {
+        let f = AsyncResponseFn {
+            inner_fn: Arc::new(move |resp: &Response| -> BoxFuture<'static, String> {
+                Box::pin(f(resp))
+            }),
+        };
+        self.inner
+            .response_replacements
+            .insert(label.to_owned(), ResponseReplacement::Async(f));
+        self
+    }
+
+    /// Register a function that receives the [`LogContext`] of the request, with the request,
+    /// the response and the timing together, and returns a String for use in the log line, in
+    /// place of `%{label}xc`. It runs when the record is logged, for tokens like the transfer
+    /// rate or a composite of the status and the path.
+    ///
+    /// It is convention to print "-" to indicate no output instead of an empty string.
+    pub fn custom_replace(
+        mut self,
+        label: &str,
+        f: impl Fn(&LogContext<State>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let f = CustomContextFn {
+            inner_fn: Arc::new(f),
+        };
+        self.inner.context_replacements.insert(label.to_owned(), f);
+        self
+    }
+
+    /// Log the request extension of type `T` in `%{label}xe`, like the tenant or the id of
+    /// the authenticated user stored by an earlier middleware with [`Request::set_ext`].
+    /// Requests without the extension are logged as `-`.
+    ///
+    /// The extensions are read before the handler runs, so only the ones set by middleware
+    /// registered before this one are logged.
+    ///
+    /// ```
+    /// use std::fmt;
+    ///
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// struct TenantId(u64);
+    ///
+    /// impl fmt::Display for TenantId {
+    ///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    ///         self.0.fmt(f)
+    ///     }
+    /// }
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s tenant=%{tenant}xe")
+    ///     .request_ext::<TenantId>("tenant");
+    /// ```
+    pub fn request_ext<T: Display + Send + Sync + 'static>(self, label: &str) -> Self {
+        self.request_ext_with(label, |req| req.ext::<T>().map(T::to_string))
+    }
+
+    /// Log the value `f` reads from the request extensions in `%{label}xe`, for extensions
+    /// that don't implement [`Display`]. Requests for which `f` returns `None` are logged as
+    /// `-`.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// struct Session {
+    ///     user_id: String,
+    ///     roles: Vec<String>,
+    /// }
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s roles=%{roles}xe")
+    ///     .request_ext_with("roles", |req| {
+    ///         req.ext::<Session>().map(|session| session.roles.join(","))
+    ///     });
+    /// ```
+    pub fn request_ext_with(
+        mut self,
+        label: &str,
+        f: impl Fn(&Request<State>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.inner
+            .request_exts
+            .insert(label.to_owned(), Arc::new(f));
+        self
+    }
+
+    /// Log the response extension of type `T` in `%{label}xoe`, like a cache hit flag or the
+    /// number of database queries attached by the handler with [`Response::insert_ext`].
+    /// Responses without the extension are logged as `-`.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s queries=%{queries}xoe")
+    ///     .response_ext::<usize>("queries");
+    /// ```
+    pub fn response_ext<T: Display + Send + Sync + 'static>(self, label: &str) -> Self {
+        self.response_ext_with(label, |resp| resp.ext::<T>().map(T::to_string))
+    }
+
+    /// Log the value `f` reads from the response extensions in `%{label}xoe`, for extensions
+    /// that don't implement [`Display`]. Responses for which `f` returns `None` are logged as
+    /// `-`.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// struct CacheHit(bool);
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s cache=%{cache}xoe")
+    ///     .response_ext_with("cache", |resp| {
+    ///         let hit = resp.ext::<CacheHit>()?.0;
+    ///         Some(if hit { "hit" } else { "miss" }.to_owned())
+    ///     });
+    /// ```
+    pub fn response_ext_with(
+        mut self,
+        label: &str,
+        f: impl Fn(&Response) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.inner
+            .response_exts
+            .insert(label.to_owned(), Arc::new(f));
+        self
+    }
+
+    /// Add the constant field `key` with `value` to every access record, like the name and
+    /// version of the service. The text output ends with `key=value`, the structured outputs
+    /// have a top-level field `key`.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .with_field("service", "checkout")
+    ///     .with_field("version", env!("CARGO_PKG_VERSION"));
+    /// ```
+    pub fn with_field<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.inner.static_fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Add the field `key` with the value returned by `f` to the access record of every
+    /// request, like the tenant, without a token in the format. Like
+    /// [`with_field`](Self::with_field), the text output ends with `key=value` and the
+    /// structured outputs have a top-level field `key`. The field is left out if `f` returns
+    /// `None`.
+    ///
+    /// The value is also recorded in the span of the request, if the span created by
+    /// [`gen_tracing_span`](Self::gen_tracing_span) declares the field `key`.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default().field_fn("tenant", |req| {
+    ///     req.header("x-tenant-id").map(|value| value.as_str().to_owned())
+    /// });
+    /// ```
+    pub fn field_fn<K: Into<String>>(
+        mut self,
+        key: K,
+        f: impl Fn(&Request<State>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.field_fns.push((key.into(), Arc::new(f)));
+        self
+    }
+
+    /// Set how the keys of [`Json`](OutputFormat::Json), [`Fields`](OutputFormat::Fields)
+    /// and [`Gelf`](OutputFormat::Gelf) output are named, see [`FieldNaming`].
+    pub fn field_naming(mut self, naming: FieldNaming) -> Self {
+        self.inner.field_naming = naming;
+        self
+    }
+
+    /// Set the `host` of [`Gelf`](OutputFormat::Gelf) messages, the name of this host by
+    /// default.
+    pub fn gelf_host<T: Into<String>>(mut self, host: T) -> Self {
+        self.inner.gelf_host = Some(host.into());
+        self
+    }
+
+    /// Set the level access records are logged at, `INFO` by default.
+    ///
+    /// Nothing is rendered for the requests whose records the subscriber would not record at
+    /// this level, unless [`on_log`](Self::on_log) or a [`sink`](Self::sink) is set.
+    pub fn level(mut self, level: Level) -> Self {
+        self.inner.level = level;
+        self
+    }
+
+    /// Choose the level of each access record from the response status, instead of the fixed
+    /// [`level`](Self::level).
+    ///
+    /// [`default_status_level`] logs server errors at `ERROR` and client errors at `WARN`:
+    ///
+    /// ```rust
+    /// use tide_tracing_middleware::{default_status_level, TracingMiddlewareBuilder};
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default().level_for_status(default_status_level);
+    /// ```
+    pub fn level_for_status(
+        mut self,
+        f: impl Fn(StatusCode) -> Level + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.level_for_status = Some(Arc::new(f));
+        self
+    }
+
+    /// Register a hook called just before each access record is written, with the status,
+    /// the duration and the size together. It can add or replace fields and change the level
+    /// of the [`LogRecord`], and the record is dropped if it returns `false`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    /// use tracing::Level;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s %b %{X-Debug-Token}i")
+    ///     .on_log(|record| {
+    ///         // successful health checks are noise
+    ///         if record.get("path").as_deref() == Some("/health") && record.status().is_success()
+    ///         {
+    ///             return false;
+    ///         }
+    ///         if record.body_size() > 10 << 20 {
+    ///             record.set("large_body", "true");
+    ///             record.set_level(Level::WARN);
+    ///         }
+    ///         if record.duration() < Duration::from_secs(1) {
+    ///             record.set("request_headers.x-debug-token", "[REDACTED]");
+    ///         }
+    ///         true
+    ///     });
+    /// ```
+    pub fn on_log(
+        mut self,
+        f: impl Fn(&mut LogRecord<'_>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.on_log = Some(Arc::new(f));
+        self
+    }
+
+    /// Write the access records to `sink`, like an audit service, instead of emitting them
+    /// as tracing events. Add [`TracingSink`] to emit them too.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::{LogRecord, LogSink, TracingMiddlewareBuilder, TracingSink};
+    ///
+    /// struct StderrSink;
+    ///
+    /// impl LogSink for StderrSink {
+    ///     fn emit(&self, record: &LogRecord<'_>) {
+    ///         eprintln!("{}", record.message().unwrap_or_default());
+    ///     }
+    /// }
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::default()
+    ///     .sink(StderrSink)
+    ///     .sink(TracingSink);
+    /// ```
+    pub fn sink(mut self, sink: impl LogSink + 'static) -> Self {
+        self.inner.sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Write the access records, one per line, to `writer` from a `tracing-appender` worker
+    /// thread, so that requests never wait for the disk. Like [`sink`](Self::sink), the
+    /// records are no longer emitted as tracing events.
+    ///
+    /// The returned guard writes the records still queued when it is dropped, keep it until
+    /// the server exits. Records are dropped while the queue of the worker is full.
+    ///
+    /// ```no_run
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let access_log = tracing_appender::rolling::daily("/var/log/app", "access.log");
+    /// let (builder, _guard) = TracingMiddlewareBuilder::<()>::default().non_blocking(access_log);
+    /// let mut app = tide::new();
+    /// app.with(builder.build());
+    /// ```
+    #[cfg(feature = "tracing-appender")]
+    pub fn non_blocking<W: std::io::Write + Send + 'static>(
+        self,
+        writer: W,
+    ) -> (Self, tracing_appender::non_blocking::WorkerGuard) {
+        let (writer, guard) = tracing_appender::non_blocking(writer);
+        (self.sink(appender::NonBlockingSink(writer)), guard)
+    }
+
+    /// Set the number of fractional digits of the durations in seconds and milliseconds, like
+    /// `%T`, `%D` and `%{ttfb}T`, 6 by default. Use `%{us}T` or `%{ns}T` for whole
+    /// microseconds or nanoseconds.
+    pub fn duration_precision(mut self, digits: usize) -> Self {
+        self.inner.duration_precision = digits;
+        self
+    }
+
+    /// Read the environment variables of `%{FOO}e` again every `interval`, for variables that
+    /// change while the server is running. They are read once when the format is parsed by
+    /// default.
+    pub fn refresh_env(mut self, interval: Duration) -> Self {
+        self.inner.refresh_env = Some(Arc::new(EnvRefresh::new(interval)));
+        self
+    }
+
+    /// Render the time of `%t` with a [`time` format string] instead of `%Y-%m-%dT%H:%M:%S`, for
+    /// example `[%d/%b/%Y:%H:%M:%S %z]` for the Apache style. The time is in UTC.
+    ///
+    /// [`time` format string]: https://docs.rs/time/0.2/time/index.html#formatting
+    pub fn time_format(self, format: impl Into<String>) -> Self {
+        let format = format.into();
+        self.time_format_with(move |time| time.format(&format))
+    }
+
+    /// Render the time of `%t` with `f`, for formats a format string can not express, like
+    /// RFC 3339 with milliseconds.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%t %r %s").time_format_with(|time| {
+    ///     format!("{}.{:03}Z", time.format("%Y-%m-%dT%H:%M:%S"), time.millisecond())
+    /// });
+    /// ```
+    pub fn time_format_with(
+        mut self,
+        f: impl Fn(OffsetDateTime) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.time_format = Some(Arc::new(f));
+        self
+    }
+
+    /// Log the user returned by `f` as `%u`, for apps that identify users with tokens or
+    /// sessions instead of Basic authentication. The user name of the Basic authentication is
+    /// logged if `f` returns `None`.
+    ///
+    /// `f` is called before the handler, so the identity has to be available from the request
+    /// itself, or from extensions set by middleware that runs before this one.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// #[derive(Clone)]
+    /// struct Session {
+    ///     user_id: String,
+    /// }
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%h %u %r %s")
+    ///     .user_extractor(|req| req.ext::<Session>().map(|session| session.user_id.clone()));
+    /// ```
+    pub fn user_extractor(
+        mut self,
+        f: impl Fn(&Request<State>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.user_extractor = Some(Arc::new(f));
+        self
+    }
+
+    /// Render the handler error of `%E` with `f` instead of its `Display` chain, for example
+    /// to downcast it to the error type of the application and log an error code.
+    ///
+    /// ```
+    /// use tide_tracing_middleware::TracingMiddlewareBuilder;
+    ///
+    /// let builder = TracingMiddlewareBuilder::<()>::new("%r %s %E").format_error(|err| {
+    ///     match err.downcast_ref::<std::io::Error>() {
+    ///         Some(err) => format!("io:{:?}", err.kind()),
+    ///         None => err.to_string(),
+    ///     }
+    /// });
+    /// ```
+    pub fn format_error(
+        mut self,
+        f: impl Fn(&tide::Error) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.format_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Catch panics of the handler and log them as errors with the method and path of the
+    /// request, then resume unwinding or respond with a 500, see [`OnPanic`].
+    ///
+    /// Without it, a panicking handler produces no access record at all.
+    pub fn catch_panics(mut self, on_panic: OnPanic) -> Self {
+        self.inner.catch_panics = Some(on_panic);
+        self
+    }
+
+    /// Also log a `request started` event with the `method`, `path` and `request_id` fields
+    /// when a request arrives, so requests whose handler hangs still show up.
+    pub fn log_request_start(mut self, enabled: bool) -> Self {
+        self.inner.log_request_start = enabled;
+        self
+    }
+
+    /// Log a `request still in progress` warning with the `method`, `path`, `request_id` and
+    /// `elapsed` (in seconds) fields for requests that take longer than `after`, and then
+    /// every `every` until the response body is finished, so stuck requests show up before
+    /// they complete or time out.
+    ///
+    /// Panics if `every` is zero.
+    pub fn heartbeat(mut self, after: Duration, every: Duration) -> Self {
+        assert!(
+            every > Duration::ZERO,
+            "the heartbeat interval must not be zero"
+        );
+        self.inner.heartbeat = Some(Heartbeat { after, every });
+        self
+    }
+
+    /// Set the source of the time, the system clock by default. Tests can use a mock
+    /// [`Clock`] to assert the rendered timestamps and durations exactly, and to move the
+    /// [`max_logs_per_second`](Self::max_logs_per_second) limit forward without sleeping.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.inner.clock = Arc::new(clock);
+        self
+    }
+
+    /// Set the headers the trace context of the caller is read from, W3C Trace Context by
+    /// default. See [`TraceContext`].
+    pub fn propagation(mut self, propagation: Propagation) -> Self {
+        self.inner.propagation = propagation;
+        self
+    }
+
+    /// Write the trace ID of each request to the response header `name`, unless the handler
+    /// already set that header, so clients can quote it when reporting issues.
+    ///
+    /// The trace ID is the one of the caller's [`TraceContext`]. With the `opentelemetry`
+    /// feature it is the one of the request span, which is also set for requests without a
+    /// trace context if an OpenTelemetry layer is installed. Nothing is written if there is
+    /// no trace ID.
+    pub fn expose_trace_header(mut self, name: &str) -> Self {
+        self.inner.trace_header = Some(HeaderName::from(name));
+        self
+    }
+
+    /// Read the ID of each request from the `X-Request-Id` header, or generate one, see
+    /// [`RequestId`].
+    pub fn request_id(mut self) -> Self {
+        self.inner.request_id.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// Read the ID of each request from the header `name` instead of `X-Request-Id`.
+    ///
+    /// Enables [`request_id`](Self::request_id).
+    pub fn request_id_header(mut self, name: &str) -> Self {
+        self.inner
+            .request_id
+            .get_or_insert_with(Default::default)
+            .header = HeaderName::from(name);
+        self
+    }
+
+    /// Send the request ID back in the response header it was read from, unless the handler
+    /// already set that header.
+    ///
+    /// Enables [`request_id`](Self::request_id).
+    pub fn echo_request_id(mut self) -> Self {
+        self.inner
+            .request_id
+            .get_or_insert_with(Default::default)
+            .echo = true;
+        self
+    }
+
+    /// Read the AWS X-Ray trace header `X-Amzn-Trace-Id` of each request, see
+    /// [`XRayTraceHeader`].
+    ///
+    /// To also parent the spans on the caller's X-Ray segment, use
+    /// [`propagation(Propagation::XRay)`](Self::propagation).
+    pub fn xray(mut self) -> Self {
+        self.inner.xray.get_or_insert_with(Default::default);
+        self
+    }
+
+    /// Generate an AWS X-Ray trace header for requests without one, and add it to the request
+    /// headers so handlers pass it on to downstream calls.
+    ///
+    /// Enables [`xray`](Self::xray).
+    pub fn generate_xray_trace_header(mut self) -> Self {
+        self.inner
+            .xray
+            .get_or_insert_with(Default::default)
+            .generate = true;
+        self
+    }
+
+    /// Record request counts and durations into `metrics`, see [`Metrics`].
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.inner.metrics.prometheus = Some(metrics);
+        self
+    }
+
+    /// Enable or disable emitting the request metrics through the `metrics` crate, so the
+    /// installed recorder picks them up. They have the same names and labels as [`Metrics`],
+    /// the names can be changed with [`metrics_facade_names`](Self::metrics_facade_names).
+    #[cfg(feature = "metrics")]
+    pub fn metrics_facade(mut self, enabled: bool) -> Self {
+        self.inner.metrics.facade = if enabled {
+            Some(self.inner.metrics.facade.unwrap_or_default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Emit the request metrics through the `metrics` crate with the given names.
+    ///
+    /// Enables [`metrics_facade`](Self::metrics_facade).
+    #[cfg(feature = "metrics")]
+    pub fn metrics_facade_names(mut self, names: MetricNames) -> Self {
+        self.inner.metrics.facade = Some(Arc::new(names));
+        self
+    }
+
+    /// Send a count and a timing per request to a StatsD server, see [`StatsdSink`].
+    pub fn statsd(mut self, sink: StatsdSink) -> Self {
+        self.inner.metrics.statsd = Some(Arc::new(sink));
+        self
+    }
+
+    /// Set the `route` label of the request metrics with `f`, which should map requests to
+    /// a small set of values, like `/users/:id` for `/users/42`. Without it the label is
+    /// empty.
+    ///
+    /// The raw path is a bad label: every ID in it creates new counters and histograms.
+    ///
+    /// Same as [`route`](Self::route), which also sets `%R`.
+    pub fn metrics_route(
+        self,
+        f: impl Fn(&Request<State>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.route(f)
+    }
+
+    /// Set the `route` label of the request metrics to `template`, same as
+    /// [`route_template`](Self::route_template).
+    pub fn metrics_route_template(self, template: &str) -> Self {
+        self.route_template(template)
+    }
+
+    /// Classify requests by route with `f`, which should map requests to their route
+    /// template, like `/users/:id` for `/users/42`. It is logged as `%R` and used as the
+    /// `route` label of the request metrics, so logs and metrics can be grouped per endpoint.
+    ///
+    /// tide does not expose the route that matched a request, `f` can use the path
+    /// parameters with `req.param(name)` or the path itself. Without it `%R` is `-`.
+    pub fn route(mut self, f: impl Fn(&Request<State>) -> String + Send + Sync + 'static) -> Self {
+        self.inner.route = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the route of all requests to `template`, for a middleware added to a single
+    /// route:
+    ///
+    /// ```rust
+    /// use tide_tracing_middleware::{Metrics, TracingMiddlewareBuilder};
+    ///
+    /// let metrics = Metrics::new();
+    /// let mut app = tide::new();
+    /// app.at("/users/:id")
+    ///     .with(
+    ///         TracingMiddlewareBuilder::new("%R %s %D")
+    ///             .metrics(metrics.clone())
+    ///             .route_template("/users/:id")
+    ///             .build(),
+    ///     )
+    ///     .get(|_| async { Ok("") });
+    /// ```
+    pub fn route_template(self, template: &str) -> Self {
+        let template = template.to_owned();
+        self.route(move |_| template.clone())
+    }
+
+    /// Register a function that creates the tracing span each request is instrumented with.
+    /// The access record is emitted inside this span, too.
+    pub fn gen_tracing_span(mut self, f: fn(&Request<State>) -> Span) -> Self {
+        self.inner.gen_tracing_span.replace(f);
+        self
+    }
+
+    /// Instrument each request with a span wired for `tracing-opentelemetry`, instead of a
+    /// span from [`gen_tracing_span`](Self::gen_tracing_span).
+    ///
+    /// The span has kind SERVER and the OpenTelemetry HTTP semantic attributes, the status
+    /// code and body size are recorded when the response body is finished and 5xx responses
+    /// set the span status to Error.
+    #[cfg(feature = "opentelemetry")]
+    pub fn opentelemetry_span(mut self) -> Self {
+        self.inner
+            .gen_tracing_span
+            .replace(otel::server_span::<State>);
+        self.inner.otel_span = true;
+        self
+    }
+
+    /// Record each request as a Sentry transaction, continuing the trace of the
+    /// `sentry-trace` request header, and add the access record as a breadcrumb with the
+    /// status and duration. 5xx responses also capture an error event.
+    ///
+    /// The events go to the hub current when the request starts, so the application has to
+    /// initialize the `sentry` crate.
+    #[cfg(feature = "sentry")]
+    pub fn sentry(mut self) -> Self {
+        self.inner.sentry = true;
+        self
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> Default for TracingMiddlewareBuilder<State> {
+    /// Create a builder with format:
+    ///
+    /// ```ignore
+    /// %a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T
+    /// ```
+    fn default() -> Self {
+        Self {
+            inner: Inner::new(Format::default()),
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State> Middleware<State> for TracingMiddleware<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, mut request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if let Some(cx) = TraceContext::from_request(&request, self.inner.propagation) {
+            request.set_ext(cx);
+        }
+        if let Some(forwarded) = self.inner.trusted_proxies.resolve(&request) {
+            request.set_ext(forwarded);
+        }
+
+        let request_id = self
+            .inner
+            .request_id
+            .as_ref()
+            .map(|config| (config, config.apply(&mut request)));
+        if let Some(config) = &self.inner.xray {
+            config.apply(&mut request);
+        }
+
+        let scope = self.inner.scopes.resolve(request.url().path());
+        if scope.is_excluded() || self.inner.is_excluded(&request) {
+            self.inner.stats.record_dropped(Dropped::Excluded);
+            let trace_id = request
+                .ext::<TraceContext>()
+                .map(TraceContext::trace_id_hex);
+            let mut resp = panic::run(next, request, self.inner.catch_panics).await;
+            if let Some((config, id)) = &request_id {
+                config.echo(&mut resp, id);
+            }
+            self.inner.expose_trace_id(&mut resp, trace_id);
+            return Ok(resp);
+        }
+
+        let now = self.inner.clock.now_utc();
+        let start = self.inner.clock.now();
+        let (in_flight, in_flight_count) = InFlight::start(&self.inner.in_flight);
+        let query = request
+            .url()
+            .query()
+            .map(|query| self.inner.scrub_query.scrub(query).into_owned());
+        let client_addr = request
+            .ext::<ForwardedInfo>()
+            .and_then(ForwardedInfo::client)
+            .or_else(|| request.peer_addr())
+            .map(str::to_owned);
+        let route = self.inner.route.as_ref().map(|f| f(&request));
+        let span = if let Some(f) = self.inner.gen_tracing_span.as_ref() {
+            f(&request)
+        } else {
+            Span::none()
+        };
+        let fields = self.inner.request_fields(&request);
+        for (key, value) in &fields {
+            span.record(key.as_str(), value.as_str());
+        }
+        #[cfg(feature = "opentelemetry")]
+        if self.inner.otel_span {
+            if let Some(query) = &query {
+                span.record("url.query", query.as_str());
+            }
+            if let Some(addr) = request.remote() {
+                let addr = match &self.inner.anonymize_ip {
+                    Some(anonymizer) => anonymizer.addr(addr),
+                    None => addr.to_owned(),
+                };
+                span.record("client.address", addr.as_str());
+            }
+        }
+        #[cfg(feature = "opentelemetry")]
+        if let Some(cx) = request.ext::<TraceContext>() {
+            otel::set_parent(&span, cx);
+        }
+        #[cfg(feature = "opentelemetry")]
+        let trace_id = otel::trace_id(&span);
+        #[cfg(not(feature = "opentelemetry"))]
+        let trace_id = None;
+        let trace_id = trace_id.or_else(|| {
+            request
+                .ext::<TraceContext>()
+                .map(TraceContext::trace_id_hex)
+        });
+        let quiet = self.inner.is_quiet(&scope, &span);
+        if quiet {
+            self.inner.stats.record_dropped(Dropped::Disabled);
+        }
+        if let Some(max) = self.inner.capture_request_body.filter(|_| !quiet) {
+            if self.inner.captures_body(request.content_type().as_ref()) {
+                RequestBody::capture(&mut request, max).await;
+            }
+        }
+        let request_size = (!quiet
+            && self
+                .inner
+                .format
+                .0
+                .iter()
+                .any(|unit| matches!(unit, FormatText::RequestSize)))
+        .then(|| RequestSize::start(&mut request));
+        let snapshot = (!quiet
+            && self
+                .inner
+                .format
+                .0
+                .iter()
+                .any(|unit| matches!(unit, FormatText::CustomContext(..))))
+        .then(|| RequestSnapshot::take(&request, client_addr.clone(), route.clone()));
+        let mut slots = Slots::new(&self.inner.format);
+        if !quiet {
+            self.inner.refresh_env(start);
+            for (i, unit) in self.inner.format.0.iter().enumerate() {
+                match (&self.inner.time_format, unit) {
+                    (Some(f), FormatText::RequestTime) => slots.set(i, f(now)),
+                    (_, FormatText::Route) => slots.set(i, route.as_deref().unwrap_or("-")),
+                    (_, FormatText::User) => {
+                        let user = self.inner.user(&request);
+                        slots.set(i, user.as_deref().unwrap_or("-"))
+                    }
+                    (_, FormatText::CustomRequest(label)) => {
+                        match self.inner.request_replacements.get(label) {
+                            Some(RequestReplacement::Sync(f)) => slots.set(i, f.call(&request)),
+                            // awaited below
+                            Some(RequestReplacement::Async(_)) => {}
+                            None => slots.set(i, "-"),
+                        }
+                    }
+                    (_, FormatText::RequestExt(label)) => {
+                        let value = self.inner.request_exts.get(label).and_then(|f| f(&request));
+                        slots.set(i, value.as_deref().unwrap_or("-"))
+                    }
+                    (_, FormatText::RequestHeader(name)) => {
+                        slots.set_header(i, request.header(name), "_")
+                    }
+                    _ => slots.render(i, |out| {
+                        unit.render_request(
+                            out,
+                            now,
+                            in_flight_count,
+                            query.as_deref(),
+                            client_addr.as_deref(),
+                            &request,
+                        )
+                    }),
+                }
+            }
+            let pending =
+                self.inner
+                    .format
+                    .0
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, unit)| match unit {
+                        FormatText::CustomRequest(label) => {
+                            match self.inner.request_replacements.get(label) {
+                                Some(RequestReplacement::Async(f)) => Some((i, f.call(&request))),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    });
+            let (indices, futures): (Vec<_>, Vec<_>) = pending.unzip();
+            for (i, value) in indices.into_iter().zip(join_all(futures).await) {
+                slots.set(i, value);
+            }
+        }
+
+        let metrics = self.inner.metrics.is_enabled().then(|| {
+            RequestMetrics::start(
+                self.inner.metrics.clone(),
+                request.method().to_string(),
+                route.unwrap_or_default(),
+            )
+        });
+        #[cfg(feature = "dd")]
+        let dd = dd::DatadogIds::new(&span, request.ext::<TraceContext>());
+        #[cfg(feature = "sentry")]
+        let sentry = self
+            .inner
+            .sentry
+            .then(|| sentry::SentryRequest::start(&request));
+        let cloned_span = span.clone();
+
+        if self.inner.log_request_start {
+            let level = scope.get_level().unwrap_or(self.inner.level);
+            let method = request.method().to_string();
+            let path = request.url().path();
+            let request_id = request_id.as_ref().map(|(_, id)| id.as_str());
+            let fields: [(&str, Option<&dyn tracing::field::Value>); 4] = [
+                ("message", Some(&"request started")),
+                ("method", Some(&method)),
+                ("path", Some(&path)),
+                ("request_id", request_id.as_ref().map(|id| id as _)),
+            ];
+            dynamic::emit(level, &span, &fields);
+        }
+
+        let is_head = request.method() == tide::http::Method::Head;
+        let heartbeat = self.inner.heartbeat.map(|heartbeat| {
+            heartbeat.start(
+                self.inner.clock.clone(),
+                span.clone(),
+                request.method().to_string(),
+                request.url().path().to_owned(),
+                request_id.as_ref().map(|(_, id)| id.as_str().to_owned()),
+            )
+        });
+
+        let mut resp = panic::run(next, request, self.inner.catch_panics)
+            .instrument(span)
+            .await;
+        if let Some((config, id)) = &request_id {
+            config.echo(&mut resp, id);
+        }
+        self.inner.expose_trace_id(&mut resp, trace_id);
+
+        let first_byte_time = self.inner.clock.now().saturating_duration_since(start);
+        let context = snapshot.map(|request| LogContext::new(request, &resp));
+        // servers do not send the body of HEAD responses
+        let expected_size = if is_head { Some(0) } else { resp.len() };
+        if !quiet {
+            let pending =
+                self.inner
+                    .format
+                    .0
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, unit)| match unit {
+                        FormatText::CustomResponse(label) => {
+                            match self.inner.response_replacements.get(label) {
+                                Some(ResponseReplacement::Async(f)) => Some((i, f.call(&resp))),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    });
+            let (indices, futures): (Vec<_>, Vec<_>) = pending.unzip();
+            for (i, value) in indices.into_iter().zip(join_all(futures).await) {
+                slots.set(i, value);
+            }
+            let error = resp.error().map(|err| match &self.inner.format_error {
+                Some(f) => f(err),
+                None => format!("{:#}", err),
+            });
+            for (i, unit) in self.inner.format.0.iter().enumerate() {
+                match (&request_size, unit) {
+                    (Some(size), FormatText::RequestSize) => slots.set(i, size.get()),
+                    (_, FormatText::DeclaredResponseSize) => match expected_size {
+                        Some(len) => slots.set(i, len),
+                        None => slots.set(i, "-"),
+                    },
+                    (_, FormatText::CustomResponse(label)) => {
+                        match self.inner.response_replacements.get(label) {
+                            Some(ResponseReplacement::Sync(f)) => slots.set(i, f.call(&resp)),
+                            // awaited above
+                            Some(ResponseReplacement::Async(_)) => {}
+                            None => slots.set(i, "-"),
+                        }
+                    }
+                    (_, FormatText::ResponseExt(label)) => {
+                        let value = self.inner.response_exts.get(label).and_then(|f| f(&resp));
+                        slots.set(i, value.as_deref().unwrap_or("-"))
                     }
+                    (_, FormatText::ResponseHeader(name)) => {
+                        slots.set_header(i, resp.header(name), "-")
+                    }
+                    _ => slots.render(i, |out| {
+                        unit.render_response(
+                            out,
+                            &resp,
+                            error.as_deref(),
+                            first_byte_time,
+                            self.inner.duration_precision,
+                        )
+                    }),
+                }
+            }
+            #[cfg(feature = "pii")]
+            self.inner.hash_pii(&mut slots);
+            self.inner.anonymize_ips(&mut slots);
+            self.inner.redact(&mut slots);
+            self.inner.escape(&mut slots);
+            self.inner.fallback(&mut slots);
+        }
+
+        let body = resp.take_body();
+        let body_len = body.len();
+        let body_mime = body.mime().clone();
+        let at_head = self.inner.log_point == LogPoint::ResponseHead;
+        let response_body = self.inner.capture_response_body.filter(|_| {
+            if at_head || quiet {
+                return false;
+            }
+            let content_type = resp.content_type().unwrap_or_else(|| body_mime.clone());
+            self.inner.captures_body(Some(&content_type))
+        });
+        let mut stream_log = StreamLog {
+            body,
+            slots,
+            size: 0,
+            expected_size,
+            finished: false,
+            logged: quiet,
+            fields,
+            context,
+            body_error: false,
+            start,
+            duration: None,
+            span: cloned_span,
+            status: resp.status(),
+            inner: self.inner.clone(),
+            scope,
+            metrics,
+            response_body: response_body.map(Captured::new),
+            _in_flight: in_flight,
+            _heartbeat: heartbeat,
+            #[cfg(feature = "dd")]
+            dd,
+            #[cfg(feature = "sentry")]
+            sentry,
+        };
+        let wrap_body = if quiet {
+            self.inner.instruments_body()
+        } else {
+            self.inner.wrap_body
+        };
+        if !quiet && (at_head || !wrap_body) {
+            let mut stream_log = Pin::new(&mut stream_log);
+            stream_log.as_mut().render_bodies();
+            stream_log.as_mut().render_connection_status();
+            stream_log.log(first_byte_time);
+        }
+        if !wrap_body {
+            // nothing left to log once the body is sent
+            let body = mem::replace(&mut stream_log.body, Body::empty());
+            drop(stream_log);
+            resp.set_body(body);
+            return Ok(resp);
+        }
+        // a body of unknown length is still sent chunked, `%b` counts the bytes read from it
+        let mut new_body = Body::from_reader(futures::io::BufReader::new(stream_log), body_len);
+        new_body.set_mime(body_mime);
+
+        resp.set_body(new_body);
+        Ok(resp)
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+struct Format(Vec<FormatText>);
+
+impl Format {
+    /// Create a `Format` from a format string.
+    ///
+    /// Panics if the format string syntax is incorrect.
+    fn new(s: &str) -> Format {
+        Self::try_new(s).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Create a `Format` from a format string.
+    ///
+    /// Returns an error if the format string syntax is incorrect.
+    fn try_new(s: &str) -> Result<Format, FormatParseError> {
+        let mut idx = 0;
+        let mut results = Vec::new();
+        while let Some(offset) = s[idx..].find('%') {
+            let pos = idx + offset;
+            if idx != pos {
+                results.push(FormatText::Str(s[idx..pos].to_owned()));
+            }
+            idx = parse_token(s, pos, &mut results)?;
+        }
+        if idx != s.len() {
+            results.push(FormatText::Str(s[idx..].to_owned()));
+        }
+
+        Ok(Format(results))
+    }
+}
+
+/// The keys of the `%{key}` tokens without a kind, like `%{trace_id}`.
+const RESERVED_KEYS: &[&str] = &[
+    "trace_id",
+    "span_id",
+    "request_id",
+    "xray_root",
+    "xray_self",
+];
+
+/// Parse the token starting with the `%` at `pos` in `s` into `units`, and return the offset
+/// following it.
+///
+/// A token is `%` followed by one character, `%Cn`, or `%{key}` followed by the kind of the
+/// key, like `i` for request headers. Keys of headers, cookies, route parameters and
+/// extensions can have a fallback for missing values, like `%{X-Request-Id?none}i`.
+///
+/// The [`RESERVED_KEYS`] have no kind, so `%{request_id}id` is the request ID followed by
+/// `id`, not the `request_id` header followed by `d`.
+fn parse_token(
+    s: &str,
+    pos: usize,
+    units: &mut Vec<FormatText>,
+) -> Result<usize, FormatParseError> {
+    let rest = &s[pos + 1..];
+    let group = match rest.strip_prefix('{') {
+        Some(group) => group,
+        None if rest.starts_with("Cn") => {
+            units.push(FormatText::InFlight);
+            return Ok(pos + 3);
+        }
+        None => {
+            units.push(match rest.as_bytes().first() {
+                Some(b'%') => FormatText::Percent,
+                Some(b'a') => FormatText::RemoteAddr,
+                Some(b'h') => FormatText::RemoteHost,
+                Some(b't') => FormatText::RequestTime,
+                Some(b'r') => FormatText::RequestLine,
+                Some(b'u') => FormatText::User,
+                Some(b'p') => FormatText::ServerPort,
+                Some(b'H') => FormatText::Scheme,
+                Some(b'v') => FormatText::Host,
+                Some(b's') => FormatText::ResponseStatus,
+                Some(b'b') => FormatText::ResponseSize,
+                Some(b'I') => FormatText::RequestSize,
+                Some(b'C') => FormatText::ContentType,
+                Some(b'M') => FormatText::Method,
+                Some(b'V') => FormatText::Version,
+                Some(b'Q') => FormatText::Query,
+                Some(b'U') => FormatText::UrlPath,
+                Some(b'R') => FormatText::Route,
+                Some(b'T') => FormatText::Time,
+                Some(b'D') => FormatText::TimeMillis,
+                Some(b'E') => FormatText::Error,
+                Some(b'X') => FormatText::ConnectionStatus,
+                _ => return Err(invalid_token(s, pos)),
+            });
+            return Ok(pos + 2);
+        }
+    };
+
+    let key_len = group
+        .bytes()
+        .take_while(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        .count();
+    if key_len == 0 {
+        return Err(invalid_token(s, pos));
+    }
+    let key = &group[..key_len];
+    let (fallback, after) = match group[key_len..].strip_prefix('?') {
+        Some(fallback) => match fallback.find('}') {
+            Some(close) => (Some(&fallback[..close]), &fallback[close + 1..]),
+            None => return Err(invalid_token(s, pos)),
+        },
+        None => match group[key_len..].strip_prefix('}') {
+            Some(after) => (None, after),
+            None => return Err(invalid_token(s, pos)),
+        },
+    };
+    let kind = match after.as_bytes().first() {
+        _ if fallback.is_none() && RESERVED_KEYS.contains(&key) => "",
+        Some(b'a' | b'i' | b'o' | b'e' | b't' | b'b' | b's' | b'T' | b'D' | b'C' | b'P') => {
+            &after[..1]
+        }
+        _ => ["xoe", "xi", "xo", "xe", "xc", "ri", "ro"]
+            .iter()
+            .copied()
+            .find(|kind| after.starts_with(kind))
+            .unwrap_or(""),
+    };
+    let end = s.len() - after.len() + kind.len();
+    let invalid = || FormatParseError::new(pos, &s[pos..end]);
+    units.push(match (kind, key) {
+        ("", "trace_id") => FormatText::TraceId,
+        ("", "span_id") => FormatText::SpanId,
+        ("", "request_id") => FormatText::RequestId,
+        ("", "xray_root") => FormatText::XRayRoot,
+        ("", "xray_self") => FormatText::XRaySelf,
+        ("a", "r") => FormatText::RealIPRemoteAddr,
+        ("i", key) => FormatText::RequestHeader(HeaderName::from(key)),
+        ("o", key) => FormatText::ResponseHeader(HeaderName::from(key)),
+        ("e", key) => FormatText::EnvironHeader(EnvVar::new(key)),
+        ("C", key) => FormatText::Cookie(key.to_owned()),
+        ("P", key) => FormatText::RouteParam(key.to_owned()),
+        ("t", "clf") => FormatText::ClfRequestTime,
+        ("t", "s") => FormatText::RequestTimeSecs,
+        ("t", "ms") => FormatText::RequestTimeMillis,
+        ("t", "us") => FormatText::RequestTimeMicros,
+        ("t", "iso") => FormatText::IsoRequestTime,
+        ("b", "clf") => FormatText::ClfResponseSize,
+        ("b", "human") => FormatText::HumanResponseSize,
+        ("b", "declared") => FormatText::DeclaredResponseSize,
+        ("s", "color") => FormatText::ColoredResponseStatus,
+        ("T", "ttfb") => FormatText::FirstByteTime,
+        ("T", "s") => FormatText::Time,
+        ("T", "ms") => FormatText::TimeMillis,
+        ("T", "us") => FormatText::TimeMicros,
+        ("T", "ns") => FormatText::TimeNanos,
+        ("D", "ttfb") => FormatText::FirstByteTimeMillis,
+        ("xi", key) => FormatText::CustomRequest(key.to_owned()),
+        ("xo", key) => FormatText::CustomResponse(key.to_owned()),
+        ("xe", key) => FormatText::RequestExt(key.to_owned()),
+        ("xoe", key) => FormatText::ResponseExt(key.to_owned()),
+        ("xc", key) => FormatText::CustomContext(key.to_owned()),
+        ("ri", "body") => FormatText::RequestBody,
+        ("ro", "body") => FormatText::ResponseBody,
+        // an unknown kind, reported with the character following the group
+        ("", _) => return Err(invalid_token(s, pos)),
+        _ => return Err(invalid()),
+    });
+    if let Some(fallback) = fallback {
+        if !matches!(kind, "i" | "o" | "C" | "P" | "xe" | "xoe") {
+            return Err(invalid());
+        }
+        units.push(FormatText::Fallback(fallback.to_owned()));
+    }
+    Ok(end)
+}
+
+/// Composes a format in code instead of parsing a format string.
+///
+/// Every method appends one unit and corresponds to one token of the format string:
+///
+/// ```rust
+/// use tide_tracing_middleware::{FormatBuilder, TracingMiddleware, TracingMiddlewareBuilder};
+///
+/// // equivalent to `%a %s %Dms "%{User-Agent}i"`
+/// let format = FormatBuilder::new()
+///     .remote_addr()
+///     .literal(" ")
+///     .status()
+///     .literal(" ")
+///     .duration_ms()
+///     .literal("ms \"")
+///     .request_header("User-Agent")
+///     .literal("\"");
+/// let middleware: TracingMiddleware<()> = TracingMiddlewareBuilder::from_format(format).build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormatBuilder<State: Clone + Send + Sync + 'static>(Vec<FormatText>, PhantomData<State>);
+
+impl<State> FormatBuilder<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    /// Create an empty format.
+    pub fn new() -> Self {
+        Self(Vec::new(), PhantomData)
+    }
+
+    fn push(mut self, unit: FormatText) -> Self {
+        self.0.push(unit);
+        self
+    }
+
+    /// Literal text, `%` needs no escaping.
+    pub fn literal<T: Into<String>>(self, s: T) -> Self {
+        self.push(FormatText::Str(s.into()))
+    }
+
+    /// `%a`: Remote IP-address (IP-address of proxy if using reverse proxy)
+    pub fn remote_addr(self) -> Self {
+        self.push(FormatText::RemoteAddr)
+    }
+
+    /// `%h`: Remote IP-address without the port
+    pub fn remote_host(self) -> Self {
+        self.push(FormatText::RemoteHost)
+    }
+
+    /// `%{r}a`: Real IP remote address
+    pub fn real_ip(self) -> Self {
+        self.push(FormatText::RealIPRemoteAddr)
+    }
+
+    /// `%t`: Time when the request was started to process
+    pub fn request_time(self) -> Self {
+        self.push(FormatText::RequestTime)
+    }
+
+    /// `%{clf}t`: Time when the request was started to process, in Common Log Format
+    pub fn clf_request_time(self) -> Self {
+        self.push(FormatText::ClfRequestTime)
+    }
+
+    /// `%{s}t`: Time when the request was started to process, in seconds since the Unix epoch
+    pub fn request_time_secs(self) -> Self {
+        self.push(FormatText::RequestTimeSecs)
+    }
+
+    /// `%{ms}t`: Time when the request was started to process, in milliseconds since the Unix
+    /// epoch
+    pub fn request_time_millis(self) -> Self {
+        self.push(FormatText::RequestTimeMillis)
+    }
+
+    /// `%{us}t`: Time when the request was started to process, in microseconds since the Unix
+    /// epoch
+    pub fn request_time_micros(self) -> Self {
+        self.push(FormatText::RequestTimeMicros)
+    }
+
+    /// `%{iso}t`: Time when the request was started to process, in UTC with microseconds,
+    /// regardless of the [`time_format`](TracingMiddlewareBuilder::time_format)
+    pub fn iso_request_time(self) -> Self {
+        self.push(FormatText::IsoRequestTime)
+    }
+
+    /// `%r`: First line of request
+    pub fn request_line(self) -> Self {
+        self.push(FormatText::RequestLine)
+    }
+
+    /// `%p`: Port of the server the request was received on
+    pub fn server_port(self) -> Self {
+        self.push(FormatText::ServerPort)
+    }
+
+    /// `%H`: Scheme of the request, `http` or `https`
+    pub fn scheme(self) -> Self {
+        self.push(FormatText::Scheme)
+    }
+
+    /// `%v`: Host the request was sent to without the port, from the `Host` header or the URL
+    pub fn host(self) -> Self {
+        self.push(FormatText::Host)
+    }
+
+    /// `%u`: User name of the Basic authentication, or of the
+    /// [user extractor](TracingMiddlewareBuilder::user_extractor)
+    pub fn user(self) -> Self {
+        self.push(FormatText::User)
+    }
+
+    /// `%s`: Response status code
+    pub fn status(self) -> Self {
+        self.push(FormatText::ResponseStatus)
+    }
+
+    /// `%{color}s`: Response status code, colored by class
+    pub fn colored_status(self) -> Self {
+        self.push(FormatText::ColoredResponseStatus)
+    }
+
+    /// `%b`: Size of response body in bytes
+    pub fn size(self) -> Self {
+        self.push(FormatText::ResponseSize)
+    }
+
+    /// `%I`: Size of request body in bytes, its `Content-Length` or the number of bytes read
+    /// by the handler
+    pub fn request_size(self) -> Self {
+        self.push(FormatText::RequestSize)
+    }
+
+    /// `%C`: Content type of the response
+    pub fn content_type(self) -> Self {
+        self.push(FormatText::ContentType)
+    }
+
+    /// `%{clf}b`: Size of response body in bytes, `-` if no bytes were sent
+    pub fn clf_size(self) -> Self {
+        self.push(FormatText::ClfResponseSize)
+    }
+
+    /// `%{human}b`: Size of response body for humans, like `512 B` or `1.4 KiB`
+    pub fn human_size(self) -> Self {
+        self.push(FormatText::HumanResponseSize)
+    }
+
+    /// `%{declared}b`: Length of the response body declared by the handler, `-` for streamed
+    /// bodies of unknown length
+    pub fn declared_size(self) -> Self {
+        self.push(FormatText::DeclaredResponseSize)
+    }
+
+    /// `%T`: Time taken to serve the request, in seconds
+    pub fn duration(self) -> Self {
+        self.push(FormatText::Time)
+    }
+
+    /// `%D`: Time taken to serve the request, in milliseconds
+    pub fn duration_ms(self) -> Self {
+        self.push(FormatText::TimeMillis)
+    }
+
+    /// `%{us}T`: Time taken to serve the request, in whole microseconds
+    pub fn duration_us(self) -> Self {
+        self.push(FormatText::TimeMicros)
+    }
+
+    /// `%{ns}T`: Time taken to serve the request, in whole nanoseconds
+    pub fn duration_ns(self) -> Self {
+        self.push(FormatText::TimeNanos)
+    }
+
+    /// `%{ttfb}T`: Time until the handler returned the response, before the body is sent, in
+    /// seconds
+    pub fn first_byte_duration(self) -> Self {
+        self.push(FormatText::FirstByteTime)
+    }
+
+    /// `%{ttfb}D`: Time until the handler returned the response, before the body is sent, in
+    /// milliseconds
+    pub fn first_byte_duration_ms(self) -> Self {
+        self.push(FormatText::FirstByteTimeMillis)
+    }
+
+    /// `%Cn`: Number of requests in flight when the request started, including it
+    pub fn in_flight(self) -> Self {
+        self.push(FormatText::InFlight)
+    }
+
+    /// `%U`: Request URL
+    pub fn url_path(self) -> Self {
+        self.push(FormatText::UrlPath)
+    }
+
+    /// `%R`: Route template of the request, see [`TracingMiddlewareBuilder::route`]
+    pub fn route(self) -> Self {
+        self.push(FormatText::Route)
+    }
+
+    /// `%M`: Request method
+    pub fn method(self) -> Self {
+        self.push(FormatText::Method)
+    }
+
+    /// `%V`: Request HTTP version
+    pub fn version(self) -> Self {
+        self.push(FormatText::Version)
+    }
+
+    /// `%Q`: Request URL's query string
+    pub fn query(self) -> Self {
+        self.push(FormatText::Query)
+    }
+
+    /// `%E`: Error of the handler, with its causes
+    pub fn error(self) -> Self {
+        self.push(FormatText::Error)
+    }
+
+    /// `%X`: `X` if the response body was not sent completely, `-` otherwise
+    pub fn connection_status(self) -> Self {
+        self.push(FormatText::ConnectionStatus)
+    }
+
+    /// `%{trace_id}`: Trace ID from the trace context request headers
+    pub fn trace_id(self) -> Self {
+        self.push(FormatText::TraceId)
+    }
+
+    /// `%{span_id}`: ID of the caller's span from the trace context request headers
+    pub fn span_id(self) -> Self {
+        self.push(FormatText::SpanId)
+    }
+
+    /// `%{request_id}`: Request ID, see [`TracingMiddlewareBuilder::request_id`]
+    pub fn request_id(self) -> Self {
+        self.push(FormatText::RequestId)
+    }
+
+    /// `%{xray_root}`: Root trace ID of the AWS X-Ray trace header, see
+    /// [`TracingMiddlewareBuilder::xray`]
+    pub fn xray_root(self) -> Self {
+        self.push(FormatText::XRayRoot)
+    }
+
+    /// `%{xray_self}`: Self segment of the AWS X-Ray trace header, see
+    /// [`TracingMiddlewareBuilder::xray`]
+    pub fn xray_self(self) -> Self {
+        self.push(FormatText::XRaySelf)
+    }
+
+    /// `%{body}ri`: Captured request body, see [`TracingMiddlewareBuilder::capture_request_body`]
+    pub fn request_body(self) -> Self {
+        self.push(FormatText::RequestBody)
+    }
+
+    /// `%{body}ro`: Captured response body, see
+    /// [`TracingMiddlewareBuilder::capture_response_body`]
+    pub fn response_body(self) -> Self {
+        self.push(FormatText::ResponseBody)
+    }
+
+    /// `%{FOO}i`: request.headers['FOO']
+    pub fn request_header(self, name: &str) -> Self {
+        self.push(FormatText::RequestHeader(HeaderName::from(name)))
+    }
+
+    /// `%{FOO}o`: response.headers['FOO']
+    pub fn response_header(self, name: &str) -> Self {
+        self.push(FormatText::ResponseHeader(HeaderName::from(name)))
+    }
+
+    /// `%{FOO}e`: os.environ['FOO']
+    pub fn env(self, name: &str) -> Self {
+        self.push(FormatText::EnvironHeader(EnvVar::new(name)))
+    }
+
+    /// `%{FOO}C`: Value of the request cookie `FOO`
+    pub fn cookie(self, name: &str) -> Self {
+        self.push(FormatText::Cookie(name.to_owned()))
+    }
+
+    /// `%{FOO}P`: Value of the route parameter `FOO`
+    pub fn route_param(self, name: &str) -> Self {
+        self.push(FormatText::RouteParam(name.to_owned()))
+    }
+
+    /// `?fallback` in `%{FOO?fallback}i`: Log `fallback` instead of `-` if the previous unit,
+    /// a header, cookie, route parameter or extension, is missing
+    pub fn fallback<T: Into<String>>(self, fallback: T) -> Self {
+        self.push(FormatText::Fallback(fallback.into()))
+    }
+
+    /// `%{FOO}xi`: custom request replacement labelled "FOO", see
+    /// [`TracingMiddlewareBuilder::custom_request_replace`].
+    pub fn custom_request(self, label: &str) -> Self {
+        self.push(FormatText::CustomRequest(label.to_owned()))
+    }
+
+    /// `%{FOO}xo`: custom response replacement labelled "FOO", see
+    /// [`TracingMiddlewareBuilder::custom_response_replace`].
+    pub fn custom_response(self, label: &str) -> Self {
+        self.push(FormatText::CustomResponse(label.to_owned()))
+    }
+
+    /// `%{FOO}xc`: custom replacement labelled "FOO", see
+    /// [`TracingMiddlewareBuilder::custom_replace`].
+    pub fn custom(self, label: &str) -> Self {
+        self.push(FormatText::CustomContext(label.to_owned()))
+    }
+
+    /// `%{FOO}xe`: request extension labelled "FOO", see
+    /// [`TracingMiddlewareBuilder::request_ext`].
+    pub fn request_ext(self, label: &str) -> Self {
+        self.push(FormatText::RequestExt(label.to_owned()))
+    }
+
+    /// `%{FOO}xoe`: response extension labelled "FOO", see
+    /// [`TracingMiddlewareBuilder::response_ext`].
+    pub fn response_ext(self, label: &str) -> Self {
+        self.push(FormatText::ResponseExt(label.to_owned()))
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> Default for FormatBuilder<State> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks a format string at compile time, producing the same `&'static str`.
+///
+/// The build fails if the format contains an unknown token or a malformed `%{...}` group,
+/// so the result can be passed to [`TracingMiddleware::new`] without it ever panicking:
+///
+/// ```rust
+/// use tide_tracing_middleware::{tracing_format, TracingMiddleware};
+///
+/// let middleware: TracingMiddleware<()> =
+///     TracingMiddleware::new(tracing_format!(r#"%a "%r" %s %b "%{Referer}i" %T"#));
+/// ```
+///
+/// ```compile_fail
+/// use tide_tracing_middleware::tracing_format;
+///
+/// let format = tracing_format!("%a %Z");
+/// ```
+#[macro_export]
+macro_rules! tracing_format {
+    ($fmt:literal) => {{
+        const _: () = $crate::validate_format($fmt);
+        $fmt
+    }};
+}
+
+/// Panics if the format string is invalid. Used by [`tracing_format!`] in const context.
+#[doc(hidden)]
+pub const fn validate_format(s: &str) {
+    let s = s.as_bytes();
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] != b'%' {
+            i += 1;
+            continue;
+        }
+        i += 1;
+        if i == s.len() {
+            panic!("invalid format: `%` at the end of the format");
+        }
+        if s[i] != b'{' {
+            match s[i] {
+                b'%' | b'a' | b'h' | b't' | b'r' | b'p' | b'H' | b'v' | b'U' | b'R' | b'u'
+                | b's' | b'b' | b'I' | b'T' | b'D' | b'M' | b'V' | b'Q' | b'E' | b'X' => {}
+                b'C' if i + 1 < s.len() && s[i + 1] == b'n' => i += 1,
+                b'C' => {}
+                _ => panic!("invalid format: unknown `%` token"),
+            }
+            i += 1;
+            continue;
+        }
+        let key_start = i + 1;
+        i = key_start;
+        while i < s.len() && (s[i].is_ascii_alphanumeric() || s[i] == b'-' || s[i] == b'_') {
+            i += 1;
+        }
+        let key_end = i;
+        let has_fallback = i < s.len() && s[i] == b'?';
+        if has_fallback {
+            while i < s.len() && s[i] != b'}' {
+                i += 1;
+            }
+        }
+        if key_end == key_start || i == s.len() || s[i] != b'}' {
+            panic!("invalid format: malformed key group");
+        }
+        i += 1;
+        let reserved = bytes_eq(s, key_start, key_end, b"trace_id")
+            || bytes_eq(s, key_start, key_end, b"span_id")
+            || bytes_eq(s, key_start, key_end, b"request_id")
+            || bytes_eq(s, key_start, key_end, b"xray_root")
+            || bytes_eq(s, key_start, key_end, b"xray_self");
+        // see `RESERVED_KEYS`
+        let typed = (!reserved || has_fallback)
+            && i < s.len()
+            && match s[i] {
+                b'i' | b'o' | b'e' | b'C' | b'P' | b'a' | b't' | b'b' | b's' | b'T' | b'D' => true,
+                b'x' => i + 1 < s.len() && matches!(s[i + 1], b'i' | b'o' | b'e' | b'c'),
+                b'r' => i + 1 < s.len() && (s[i + 1] == b'i' || s[i + 1] == b'o'),
+                _ => false,
+            };
+        let missing = typed
+            && match s[i] {
+                b'i' | b'o' | b'C' | b'P' => true,
+                b'x' => {
+                    s[i + 1] == b'e' || (s[i + 1] == b'o' && i + 2 < s.len() && s[i + 2] == b'e')
+                }
+                _ => false,
+            };
+        if has_fallback && !missing {
+            panic!("invalid format: fallback on a token that is never missing");
+        }
+        if !typed {
+            if !reserved {
+                panic!("invalid format: unknown token type after key group");
+            }
+            continue;
+        }
+        let valid = match s[i] {
+            b'i' | b'o' | b'e' | b'C' | b'P' => true,
+            b'a' => bytes_eq(s, key_start, key_end, b"r"),
+            b't' => {
+                bytes_eq(s, key_start, key_end, b"clf")
+                    || bytes_eq(s, key_start, key_end, b"s")
+                    || bytes_eq(s, key_start, key_end, b"ms")
+                    || bytes_eq(s, key_start, key_end, b"us")
+                    || bytes_eq(s, key_start, key_end, b"iso")
+            }
+            b'b' => {
+                bytes_eq(s, key_start, key_end, b"clf")
+                    || bytes_eq(s, key_start, key_end, b"human")
+                    || bytes_eq(s, key_start, key_end, b"declared")
+            }
+            b'T' => {
+                bytes_eq(s, key_start, key_end, b"ttfb")
+                    || bytes_eq(s, key_start, key_end, b"s")
+                    || bytes_eq(s, key_start, key_end, b"ms")
+                    || bytes_eq(s, key_start, key_end, b"us")
+                    || bytes_eq(s, key_start, key_end, b"ns")
+            }
+            b'D' => bytes_eq(s, key_start, key_end, b"ttfb"),
+            b's' => bytes_eq(s, key_start, key_end, b"color"),
+            b'r' => {
+                // `ri` or `ro`
+                i += 1;
+                bytes_eq(s, key_start, key_end, b"body")
+            }
+            _ => {
+                // `xi`, `xo`, `xe`, `xoe` or `xc`
+                i += 1;
+                if s[i] == b'o' && i + 1 < s.len() && s[i + 1] == b'e' {
+                    i += 1;
+                }
+                true
+            }
+        };
+        if !valid {
+            panic!("invalid format: unsupported key for token type");
+        }
+        i += 1;
+    }
+}
+
+/// `s[start..end] == expected` in const context.
+const fn bytes_eq(s: &[u8], start: usize, end: usize, expected: &[u8]) -> bool {
+    if end - start != expected.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < expected.len() {
+        if s[start + i] != expected[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The error for the `%` at `pos` in `s`, which starts no valid token.
+fn invalid_token(s: &str, pos: usize) -> FormatParseError {
+    let rest = &s[pos..];
+    let token = if rest[1..].starts_with('{') {
+        // the whole `%{...}` group plus the character following it
+        match rest.find('}') {
+            Some(close) => {
+                let len = rest[close + 1..].chars().next().map_or(0, char::len_utf8);
+                &rest[..close + 1 + len]
+            }
+            None => rest,
+        }
+    } else {
+        let len = rest[1..].chars().next().map_or(0, char::len_utf8);
+        &rest[..1 + len]
+    };
+    FormatParseError::new(pos, token)
+}
+
+/// An error returned by [`TracingMiddleware::try_new`] when the format string is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatParseError {
+    offset: usize,
+    token: String,
+}
+
+impl FormatParseError {
+    fn new(offset: usize, token: &str) -> Self {
+        Self {
+            offset,
+            token: token.to_owned(),
+        }
+    }
+
+    /// The byte offset of the offending token in the format string.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The offending token.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+impl Display for FormatParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        write!(
+            f,
+            "invalid format token `{}` at byte offset {}",
+            self.token, self.offset
+        )
+    }
+}
+
+impl std::error::Error for FormatParseError {}
+
+impl Default for Format {
+    /// Return the default formatting style for the `TracingMiddleware`:
+    fn default() -> Self {
+        Format::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+    }
+}
+
+/// A string of text to be logged. This is either one of the data
+/// fields supported by the `TracingMiddleware`, or a custom `String`.
+#[doc(hidden)]
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+enum FormatText {
+    Str(String),
+    Fallback(String),
+    Percent,
+    RequestLine,
+    ServerPort,
+    Scheme,
+    Host,
+    User,
+    RequestTime,
+    ClfRequestTime,
+    RequestTimeSecs,
+    RequestTimeMillis,
+    RequestTimeMicros,
+    IsoRequestTime,
+    ResponseStatus,
+    ColoredResponseStatus,
+    ResponseSize,
+    ClfResponseSize,
+    HumanResponseSize,
+    DeclaredResponseSize,
+    RequestSize,
+    ContentType,
+    Time,
+    TimeMillis,
+    TimeMicros,
+    TimeNanos,
+    FirstByteTime,
+    FirstByteTimeMillis,
+    InFlight,
+    RemoteAddr,
+    RemoteHost,
+    RealIPRemoteAddr,
+    Method,
+    Version,
+    UrlPath,
+    Route,
+    Query,
+    TraceId,
+    SpanId,
+    RequestId,
+    XRayRoot,
+    XRaySelf,
+    RequestBody,
+    ResponseBody,
+    Error,
+    ConnectionStatus,
+    RequestHeader(HeaderName),
+    ResponseHeader(HeaderName),
+    EnvironHeader(EnvVar),
+    Cookie(String),
+    RouteParam(String),
+    CustomRequest(String),
+    RequestExt(String),
+    ResponseExt(String),
+    CustomResponse(String),
+    CustomContext(String),
+}
+
+type RequestFn<State> = dyn Fn(&Request<State>) -> String + Sync + Send;
+
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct CustomRequestFn<State: Clone + Send + Sync + 'static> {
+    inner_fn: Arc<RequestFn<State>>,
+}
+
+impl<State> CustomRequestFn<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    fn call(&self, req: &Request<State>) -> String {
+        (self.inner_fn)(req)
+    }
+}
+
+impl<State> fmt::Debug for CustomRequestFn<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.write_str("custom_request_fn")
+    }
+}
+
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct CustomResponseFn {
+    inner_fn: Arc<dyn Fn(&Response) -> String + Sync + Send>,
+}
+
+impl CustomResponseFn {
+    fn call(&self, resp: &Response) -> String {
+        (self.inner_fn)(resp)
+    }
+}
+
+impl fmt::Debug for CustomResponseFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.write_str("custom_response_fn")
+    }
+}
+
+type RequestFutureFn<State> = dyn Fn(&Request<State>) -> BoxFuture<'static, String> + Sync + Send;
+
+#[derive(Clone)]
+struct AsyncRequestFn<State> {
+    inner_fn: Arc<RequestFutureFn<State>>,
+}
+
+impl<State> AsyncRequestFn<State> {
+    fn call(&self, req: &Request<State>) -> BoxFuture<'static, String> {
+        (self.inner_fn)(req)
+    }
+}
+
+impl<State> fmt::Debug for AsyncRequestFn<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.write_str("async_request_fn")
+    }
+}
+
+type ResponseFutureFn = dyn Fn(&Response) -> BoxFuture<'static, String> + Sync + Send;
+
+#[derive(Clone)]
+struct AsyncResponseFn {
+    inner_fn: Arc<ResponseFutureFn>,
+}
+
+impl AsyncResponseFn {
+    fn call(&self, resp: &Response) -> BoxFuture<'static, String> {
+        (self.inner_fn)(resp)
+    }
+}
+
+impl fmt::Debug for AsyncResponseFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.write_str("async_response_fn")
+    }
+}
+
+/// A function registered for `%{label}xi`.
+#[derive(Clone)]
+enum RequestReplacement<State: Clone + Send + Sync + 'static> {
+    Sync(CustomRequestFn<State>),
+    Async(AsyncRequestFn<State>),
+}
+
+/// A function registered for `%{label}xo`.
+#[derive(Clone)]
+enum ResponseReplacement {
+    Sync(CustomResponseFn),
+    Async(AsyncResponseFn),
+}
+
+type ContextFn<State> = dyn Fn(&LogContext<State>) -> String + Sync + Send;
+
+#[derive(Clone)]
+struct CustomContextFn<State> {
+    inner_fn: Arc<ContextFn<State>>,
+}
+
+impl<State> CustomContextFn<State> {
+    fn call(&self, ctx: &LogContext<State>) -> String {
+        (self.inner_fn)(ctx)
+    }
+}
+
+impl<State> fmt::Debug for CustomContextFn<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.write_str("custom_context_fn")
+    }
+}
+
+/// The value of a unit of the parsed format for a request.
+#[derive(Debug, Clone)]
+enum Slot {
+    /// Rendered from the unit itself when the record is written: the literal text, and the
+    /// values only known then, like `%b` and `%T`.
+    Template,
+    /// A rendered value, `-` if it is missing, at this range of the buffer of the [`Slots`].
+    Str(Range<usize>),
+    /// All the values of a multi-valued header.
+    Values(Vec<String>),
+}
+
+/// The values of a request, one per unit of the parsed format, which is shared by all the
+/// requests and never modified.
+///
+/// The rendered values are written one after the other to a single buffer, taken from a
+/// pool and returned once the request is logged, so most requests allocate no string at all.
+#[derive(Debug)]
+struct Slots {
+    slots: SmallVec<[Slot; 16]>,
+    buf: String,
+    /// The values whose `Display` implementation failed.
+    errors: u64,
+}
+
+impl Slots {
+    /// The values of a request logged with `format`, none rendered yet.
+    fn new(format: &Format) -> Self {
+        Self {
+            slots: SmallVec::from_elem(Slot::Template, format.0.len()),
+            buf: buffer::take(),
+            errors: 0,
+        }
+    }
+
+    /// The value of the unit `i`.
+    fn get(&self, i: usize) -> &Slot {
+        &self.slots[i]
+    }
+
+    /// The rendered value of the unit `i`, `None` if it is not rendered yet or has several
+    /// values.
+    fn str(&self, i: usize) -> Option<&str> {
+        match &self.slots[i] {
+            Slot::Str(range) => Some(&self.buf[range.clone()]),
+            _ => None,
+        }
+    }
+
+    /// The values of the unit `i` if it is a multi-valued header.
+    fn values_mut(&mut self, i: usize) -> Option<&mut Vec<String>> {
+        match &mut self.slots[i] {
+            Slot::Values(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Set the value of the unit `i` to `value`, which is rendered as `-` if it fails.
+    fn set(&mut self, i: usize, value: impl Display) {
+        let start = self.buf.len();
+        if write!(self.buf, "{}", value).is_err() {
+            self.buf.truncate(start);
+            self.buf.push('-');
+            self.errors += 1;
+        }
+        self.slots[i] = Slot::Str(start..self.buf.len());
+    }
+
+    /// Render the value of the unit `i` with `f`, which writes it to the end of the buffer and
+    /// returns `false` if the unit is rendered when the record is written instead.
+    fn render(&mut self, i: usize, f: impl FnOnce(&mut String) -> bool) {
+        let start = self.buf.len();
+        if f(&mut self.buf) {
+            self.slots[i] = Slot::Str(start..self.buf.len());
+        } else {
+            self.buf.truncate(start);
+        }
+    }
+
+    /// Set the value of the unit `i` to the values of a header, `empty` if it has none. All
+    /// the values are kept if there are several, see
+    /// [`TracingMiddlewareBuilder::join_header_values`].
+    fn set_header(&mut self, i: usize, values: Option<&HeaderValues>, empty: &str) {
+        match values.map(|values| &values[..]) {
+            None => self.set(i, "-"),
+            Some([]) => self.set(i, empty),
+            Some([value]) => self.set(i, value.as_str()),
+            Some(values) => {
+                self.slots[i] = Slot::Values(values.iter().map(|v| v.as_str().to_owned()).collect())
+            }
+        }
+    }
+}
+
+impl Drop for Slots {
+    fn drop(&mut self) {
+        buffer::recycle(mem::take(&mut self.buf));
+    }
+}
+
+impl FormatText {
+    /// Render the request parts to `out`, `query` is the scrubbed query string. Returns
+    /// `false` if the unit is not a request part.
+    fn render_request<State>(
+        &self,
+        out: &mut String,
+        now: OffsetDateTime,
+        in_flight: usize,
+        query: Option<&str>,
+        client_addr: Option<&str>,
+        req: &Request<State>,
+    ) -> bool {
+        let version = req.version();
+        let version = version.as_ref().map_or("?", |v| v.as_ref());
+        let _ = match self {
+            FormatText::InFlight => write!(out, "{}", in_flight),
+            FormatText::RequestLine => match query {
+                Some(query_str) => write!(
+                    out,
+                    "{} {}?{} {}",
+                    req.method(),
+                    req.url().path(),
+                    query_str,
+                    version
+                ),
+                None => write!(out, "{} {} {}", req.method(), req.url().path(), version),
+            },
+            FormatText::Method => write!(out, "{}", req.method()),
+            FormatText::Version => match req.version() {
+                Some(v) => write!(out, "{}", v),
+                None => out.write_str("?"),
+            },
+            FormatText::Query => out.write_str(query.unwrap_or("-")),
+            FormatText::UrlPath => out.write_str(req.url().path()),
+            FormatText::TraceId => out.write_str(
+                &req.ext::<TraceContext>()
+                    .map_or("-".to_owned(), TraceContext::trace_id_hex),
+            ),
+            FormatText::SpanId => out.write_str(
+                &req.ext::<TraceContext>()
+                    .map_or("-".to_owned(), TraceContext::span_id_hex),
+            ),
+            FormatText::RequestId => {
+                out.write_str(req.ext::<RequestId>().map_or("-", |id| id.as_str()))
+            }
+            FormatText::XRayRoot => {
+                out.write_str(req.ext::<XRayTraceHeader>().map_or("-", |h| h.root()))
+            }
+            FormatText::XRaySelf => out.write_str(
+                req.ext::<XRayTraceHeader>()
+                    .and_then(XRayTraceHeader::self_id)
+                    .unwrap_or("-"),
+            ),
+            FormatText::RequestBody => match req
+                .ext::<RequestBody>()
+                .filter(|body| !body.as_bytes().is_empty())
+            {
+                Some(body) => write!(out, "{}", body),
+                None => out.write_str("-"),
+            },
+            FormatText::RequestTime => write!(out, "{}", now.lazy_format("%Y-%m-%dT%H:%M:%S")),
+            FormatText::ClfRequestTime => {
+                write!(out, "{}", now.lazy_format("[%d/%b/%Y:%H:%M:%S %z]"))
+            }
+            FormatText::RequestTimeSecs => write!(out, "{}", now.unix_timestamp()),
+            FormatText::RequestTimeMillis => {
+                write!(out, "{}", now.unix_timestamp_nanos() / 1_000_000)
+            }
+            FormatText::RequestTimeMicros => write!(out, "{}", now.unix_timestamp_nanos() / 1_000),
+            FormatText::IsoRequestTime => {
+                let now = now.to_offset(UtcOffset::UTC);
+                write!(
+                    out,
+                    "{}.{:06}Z",
+                    now.lazy_format("%Y-%m-%dT%H:%M:%S"),
+                    now.microsecond()
+                )
+            }
+            FormatText::ServerPort => {
+                let port = req
+                    .local_addr()
+                    .and_then(|addr| addr.rsplit_once(':'))
+                    .and_then(|(_, port)| port.parse().ok())
+                    .or_else(|| req.url().port_or_known_default());
+                match port {
+                    Some(port) => write!(out, "{}", port),
+                    None => out.write_str("-"),
+                }
+            }
+            FormatText::Scheme => {
+                let scheme = req
+                    .ext::<ForwardedInfo>()
+                    .and_then(ForwardedInfo::proto)
+                    .unwrap_or_else(|| req.url().scheme());
+                out.write_str(scheme)
+            }
+            FormatText::Host => {
+                let host = req
+                    .ext::<ForwardedInfo>()
+                    .and_then(ForwardedInfo::host)
+                    .or_else(|| req.header("host").map(|host| host.as_str()))
+                    .or_else(|| req.url().host_str())
+                    .map_or("-", without_port);
+                out.write_str(host)
+            }
+            FormatText::RouteParam(ref name) => out.write_str(req.param(name).unwrap_or("-")),
+            FormatText::Cookie(ref name) => {
+                let value = req
+                    .header("cookie")
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|value| value.as_str().split(';'))
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key == name)
+                    .map_or("-", |(_, value)| value);
+                out.write_str(value)
+            }
+            FormatText::RemoteAddr => out.write_str(req.remote().unwrap_or("-")),
+            FormatText::RemoteHost => match req.remote() {
+                // strip the port, if any, from "ip:port" and "[ipv6]:port"
+                Some(addr) => match addr.parse::<SocketAddr>() {
+                    Ok(addr) => write!(out, "{}", addr.ip()),
+                    Err(_) => out.write_str(addr),
+                },
+                None => out.write_str("-"),
+            },
+            FormatText::RealIPRemoteAddr => out.write_str(client_addr.unwrap_or("-")),
+            _ => return false,
+        };
+        true
+    }
+
+    /// Render the response parts to `out`, `error` is the formatted handler error,
+    /// `first_byte_time` the time until the handler returned and `precision` the number of
+    /// fractional digits of durations. Returns `false` if the unit is not a response part.
+    fn render_response(
+        &self,
+        out: &mut String,
+        resp: &Response,
+        error: Option<&str>,
+        first_byte_time: Duration,
+        precision: usize,
+    ) -> bool {
+        let _ = match self {
+            FormatText::ResponseStatus => write!(out, "{}", resp.status() as u16),
+            FormatText::ColoredResponseStatus => {
+                let status = resp.status();
+                let color = if status.is_server_error() {
+                    31 // red
+                } else if status.is_client_error() {
+                    33 // yellow
+                } else if status.is_redirection() {
+                    36 // cyan
+                } else if status.is_success() {
+                    32 // green
                 } else {
-                    "-"
+                    0 // no color
                 };
-                *self = FormatText::Str(s.to_string())
+                write!(out, "\x1b[{}m{}\x1b[0m", color, status as u16)
             }
-            FormatText::CustomResponse(_, response_fn) => {
-                *self = match response_fn {
-                    Some(f) => FormatText::Str(f.call(resp)),
-                    None => FormatText::Str("-".to_owned()),
-                };
+            FormatText::Error => out.write_str(error.unwrap_or("-")),
+            FormatText::ContentType => match resp.content_type() {
+                Some(mime) => write!(out, "{}", mime),
+                None => out.write_str("-"),
+            },
+            FormatText::FirstByteTime => {
+                let secs = first_byte_time.as_secs_f64();
+                write!(out, "{:.*}", precision, secs)
             }
-            _ => (),
-        }
+            FormatText::FirstByteTimeMillis => {
+                let ms = (first_byte_time.as_nanos() as f64) / 1_000_000.0;
+                write!(out, "{:.*}", precision, ms)
+            }
+            _ => return false,
+        };
+        true
     }
 
     fn render(
         &self,
         fmt: &mut Formatter<'_>,
         size: usize,
-        entry_time: OffsetDateTime,
+        elapsed: Duration,
+        precision: usize,
     ) -> Result<(), fmtError> {
         match *self {
             FormatText::Str(ref string) => fmt.write_str(string),
             FormatText::Percent => "%".fmt(fmt),
             FormatText::ResponseSize => size.fmt(fmt),
+            FormatText::ClfResponseSize => {
+                if size == 0 {
+                    "-".fmt(fmt)
+                } else {
+                    size.fmt(fmt)
+                }
+            }
+            FormatText::HumanResponseSize => {
+                const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+                if size < 1024 {
+                    return write!(fmt, "{} B", size);
+                }
+                let mut value = size as f64 / 1024.0;
+                let mut unit = 0;
+                while value >= 1024.0 && unit + 1 < UNITS.len() {
+                    value /= 1024.0;
+                    unit += 1;
+                }
+                write!(fmt, "{:.1} {}", value, UNITS[unit])
+            }
             FormatText::Time => {
-                let rt = OffsetDateTime::now_utc() - entry_time;
-                let rt = rt.as_seconds_f64();
-                fmt.write_fmt(format_args!("{:.6}", rt))
+                let rt = elapsed.as_secs_f64();
+                fmt.write_fmt(format_args!("{:.*}", precision, rt))
             }
             FormatText::TimeMillis => {
-                let rt = OffsetDateTime::now_utc() - entry_time;
-                let rt = (rt.whole_nanoseconds() as f64) / 1_000_000.0;
-                fmt.write_fmt(format_args!("{:.6}", rt))
+                let rt = (elapsed.as_nanos() as f64) / 1_000_000.0;
+                fmt.write_fmt(format_args!("{:.*}", precision, rt))
             }
-            FormatText::EnvironHeader(ref name) => {
-                if let Ok(val) = std::env::var(name) {
-                    fmt.write_fmt(format_args!("{}", val))
-                } else {
-                    "-".fmt(fmt)
-                }
+            FormatText::TimeMicros => elapsed.as_micros().fmt(fmt),
+            FormatText::TimeNanos => elapsed.as_nanos().fmt(fmt),
+            FormatText::EnvironHeader(ref var) => {
+                var.with_value(|value| fmt.write_str(value.unwrap_or("-")))
             }
             _ => Ok(()),
         }
     }
 }
 
+/// Where the value of a format unit is placed in structured output.
+#[derive(Clone, PartialEq)]
+enum FieldKey {
+    Field(&'static str),
+    Number(&'static str),
+    Label(String),
+    RequestHeader(String),
+    ResponseHeader(String),
+    Environ(String),
+    Cookie(String),
+    RouteParam(String),
+}
+
+impl FormatText {
+    /// Whether the unit is only known once the response body is sent.
+    fn needs_body(&self) -> bool {
+        matches!(
+            self,
+            FormatText::ResponseSize
+                | FormatText::ClfResponseSize
+                | FormatText::HumanResponseSize
+                | FormatText::Time
+                | FormatText::TimeMillis
+                | FormatText::TimeMicros
+                | FormatText::TimeNanos
+                | FormatText::ConnectionStatus
+                | FormatText::ResponseBody
+                | FormatText::CustomContext(_)
+                // requests stay in flight until their body is sent
+                | FormatText::InFlight
+        )
+    }
+
+    /// Returns the structured output key of this unit, `None` for literal text.
+    ///
+    /// Must be called on the parsed format, before the unit is rendered.
+    fn field_key(&self, naming: FieldNaming) -> Option<FieldKey> {
+        let key = match naming {
+            FieldNaming::Default => None,
+            FieldNaming::OpenTelemetry => self.otel_field_key(),
+            FieldNaming::Ecs => self.ecs_field_key(),
+        };
+        if key.is_some() {
+            return key;
+        }
+        Some(match self {
+            FormatText::Str(_) | FormatText::Fallback(_) | FormatText::Percent => return None,
+            FormatText::RequestLine => FieldKey::Field("request"),
+            FormatText::User => FieldKey::Field("user"),
+            FormatText::ServerPort => FieldKey::Number("server_port"),
+            FormatText::Scheme => FieldKey::Field("scheme"),
+            FormatText::Host => FieldKey::Field("host"),
+            FormatText::RequestTime | FormatText::ClfRequestTime | FormatText::IsoRequestTime => {
+                FieldKey::Field("time")
+            }
+            FormatText::RequestTimeSecs => FieldKey::Number("timestamp"),
+            FormatText::RequestTimeMillis => FieldKey::Number("timestamp_ms"),
+            FormatText::RequestTimeMicros => FieldKey::Number("timestamp_us"),
+            FormatText::ResponseStatus | FormatText::ColoredResponseStatus => {
+                FieldKey::Number("status")
+            }
+            FormatText::ResponseSize | FormatText::ClfResponseSize => FieldKey::Number("size"),
+            FormatText::HumanResponseSize => FieldKey::Field("size_human"),
+            FormatText::DeclaredResponseSize => FieldKey::Number("size_declared"),
+            FormatText::RequestSize => FieldKey::Number("request_size"),
+            FormatText::ContentType => FieldKey::Field("content_type"),
+            FormatText::Time => FieldKey::Number("duration"),
+            FormatText::TimeMillis => FieldKey::Number("duration_ms"),
+            FormatText::TimeMicros => FieldKey::Number("duration_us"),
+            FormatText::TimeNanos => FieldKey::Number("duration_ns"),
+            FormatText::FirstByteTime => FieldKey::Number("ttfb"),
+            FormatText::FirstByteTimeMillis => FieldKey::Number("ttfb_ms"),
+            FormatText::InFlight => FieldKey::Number("in_flight"),
+            FormatText::RemoteAddr => FieldKey::Field("remote_addr"),
+            FormatText::RemoteHost => FieldKey::Field("remote_host"),
+            FormatText::RealIPRemoteAddr => FieldKey::Field("real_ip"),
+            FormatText::Method => FieldKey::Field("method"),
+            FormatText::Version => FieldKey::Field("version"),
+            FormatText::UrlPath => FieldKey::Field("path"),
+            FormatText::Route => FieldKey::Field("route"),
+            FormatText::Query => FieldKey::Field("query"),
+            FormatText::TraceId => FieldKey::Field("trace_id"),
+            FormatText::SpanId => FieldKey::Field("span_id"),
+            FormatText::RequestId => FieldKey::Field("request_id"),
+            FormatText::XRayRoot => FieldKey::Field("xray_root"),
+            FormatText::XRaySelf => FieldKey::Field("xray_self"),
+            FormatText::RequestBody => FieldKey::Field("request_body"),
+            FormatText::ResponseBody => FieldKey::Field("response_body"),
+            FormatText::Error => FieldKey::Field("error"),
+            FormatText::ConnectionStatus => FieldKey::Field("connection_status"),
+            FormatText::RequestHeader(name) => FieldKey::RequestHeader(name.as_str().to_owned()),
+            FormatText::ResponseHeader(name) => FieldKey::ResponseHeader(name.as_str().to_owned()),
+            FormatText::EnvironHeader(var) => FieldKey::Environ(var.name().to_owned()),
+            FormatText::Cookie(name) => FieldKey::Cookie(name.clone()),
+            FormatText::RouteParam(name) => FieldKey::RouteParam(name.clone()),
+            FormatText::CustomRequest(label)
+            | FormatText::CustomResponse(label)
+            | FormatText::CustomContext(label)
+            | FormatText::RequestExt(label)
+            | FormatText::ResponseExt(label) => FieldKey::Label(label.clone()),
+        })
+    }
+}
+
+impl FormatText {
+    /// Returns the key of this unit following the OpenTelemetry HTTP semantic conventions,
+    /// `None` if there is no such attribute.
+    fn otel_field_key(&self) -> Option<FieldKey> {
+        Some(match self {
+            FormatText::Method => FieldKey::Field("http.request.method"),
+            FormatText::User => FieldKey::Field("enduser.id"),
+            FormatText::ServerPort => FieldKey::Number("server.port"),
+            FormatText::Scheme => FieldKey::Field("url.scheme"),
+            FormatText::Host => FieldKey::Field("server.address"),
+            FormatText::ResponseStatus | FormatText::ColoredResponseStatus => {
+                FieldKey::Number("http.response.status_code")
+            }
+            FormatText::ResponseSize | FormatText::ClfResponseSize => {
+                FieldKey::Number("http.response.body.size")
+            }
+            FormatText::RequestSize => FieldKey::Number("http.request.body.size"),
+            FormatText::ContentType => {
+                FieldKey::Label("http.response.header.content-type".to_owned())
+            }
+            FormatText::Time => FieldKey::Number("http.server.request.duration"),
+            FormatText::InFlight => FieldKey::Number("http.server.active_requests"),
+            FormatText::UrlPath => FieldKey::Field("url.path"),
+            FormatText::Route => FieldKey::Field("http.route"),
+            FormatText::Query => FieldKey::Field("url.query"),
+            FormatText::Version => FieldKey::Field("network.protocol.version"),
+            FormatText::RemoteAddr | FormatText::RemoteHost | FormatText::RealIPRemoteAddr => {
+                FieldKey::Field("client.address")
+            }
+            FormatText::RequestHeader(name) => match name.as_str() {
+                "host" => FieldKey::Field("server.address"),
+                "user-agent" => FieldKey::Field("user_agent.original"),
+                name => FieldKey::Label(format!("http.request.header.{}", name)),
+            },
+            FormatText::ResponseHeader(name) => {
+                FieldKey::Label(format!("http.response.header.{}", name.as_str()))
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl FormatText {
+    /// Returns the key of this unit following the Elastic Common Schema, `None` if there is
+    /// no such field.
+    fn ecs_field_key(&self) -> Option<FieldKey> {
+        Some(match self {
+            FormatText::Method => FieldKey::Field("http.request.method"),
+            FormatText::User => FieldKey::Field("user.name"),
+            FormatText::ServerPort => FieldKey::Number("url.port"),
+            FormatText::Scheme => FieldKey::Field("url.scheme"),
+            FormatText::Host => FieldKey::Field("url.domain"),
+            FormatText::RequestTime => FieldKey::Field("@timestamp"),
+            FormatText::ResponseStatus | FormatText::ColoredResponseStatus => {
+                FieldKey::Number("http.response.status_code")
+            }
+            FormatText::ResponseSize | FormatText::ClfResponseSize => {
+                FieldKey::Number("http.response.body.bytes")
+            }
+            FormatText::RequestSize => FieldKey::Number("http.request.body.bytes"),
+            FormatText::ContentType => FieldKey::Field("http.response.mime_type"),
+            FormatText::TimeNanos => FieldKey::Number("event.duration"),
+            FormatText::UrlPath => FieldKey::Field("url.path"),
+            FormatText::Query => FieldKey::Field("url.query"),
+            FormatText::RemoteAddr | FormatText::RemoteHost => FieldKey::Field("source.address"),
+            FormatText::RealIPRemoteAddr => FieldKey::Field("source.ip"),
+            FormatText::TraceId => FieldKey::Field("trace.id"),
+            FormatText::SpanId => FieldKey::Field("span.id"),
+            FormatText::RequestId => FieldKey::Field("http.request.id"),
+            FormatText::RequestBody => FieldKey::Field("http.request.body.content"),
+            FormatText::ResponseBody => FieldKey::Field("http.response.body.content"),
+            FormatText::Error => FieldKey::Field("error.message"),
+            FormatText::RequestHeader(name) => match name.as_str() {
+                "host" => FieldKey::Field("url.domain"),
+                "user-agent" => FieldKey::Field("user_agent.original"),
+                "referer" => FieldKey::Field("http.request.referrer"),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl FormatText {
+    /// Returns the W3C extended log field identifier of this unit, `None` for literal text.
+    /// Tokens without a standard identifier get an `x-` prefixed one from their default key.
+    fn w3c_field(&self) -> Option<Cow<'static, str>> {
+        Some(Cow::Borrowed(match self {
+            FormatText::RequestTime => "date time",
+            FormatText::RemoteAddr | FormatText::RealIPRemoteAddr => "c-ip",
+            FormatText::RemoteHost => "c-dns",
+            FormatText::User => "cs-username",
+            FormatText::Method => "cs-method",
+            FormatText::UrlPath => "cs-uri-stem",
+            FormatText::Query => "cs-uri-query",
+            FormatText::Version => "cs-version",
+            FormatText::Host => "cs-host",
+            FormatText::ServerPort => "s-port",
+            FormatText::ResponseStatus | FormatText::ColoredResponseStatus => "sc-status",
+            FormatText::ResponseSize | FormatText::ClfResponseSize => "sc-bytes",
+            FormatText::RequestSize => "cs-bytes",
+            FormatText::Time | FormatText::TimeMillis => "time-taken",
+            FormatText::RequestHeader(name) => return Some(format!("cs({})", name).into()),
+            FormatText::ResponseHeader(name) => return Some(format!("sc({})", name).into()),
+            _ => {
+                return Some(match self.field_key(FieldNaming::Default)?.split() {
+                    (Some(group), key) => format!("x-{}.{}", group, key).into(),
+                    (None, key) => format!("x-{}", key).into(),
+                })
+            }
+        }))
+    }
+}
+
+impl FieldKey {
+    /// The name of the key in flat output, like `request_headers.user-agent`.
+    fn flat_name(self) -> String {
+        match self.split() {
+            (Some(group), key) => format!("{}.{}", group, key),
+            (None, key) => key,
+        }
+    }
+
+    /// Returns the group the key is nested in, if any, and the key inside it.
+    fn split(self) -> (Option<&'static str>, String) {
+        match self {
+            FieldKey::Field(key) | FieldKey::Number(key) => (None, key.to_owned()),
+            FieldKey::Label(key) => (None, key),
+            FieldKey::RequestHeader(key) => (Some("request_headers"), key),
+            FieldKey::ResponseHeader(key) => (Some("response_headers"), key),
+            FieldKey::Environ(key) => (Some("env"), key),
+            FieldKey::Cookie(key) => (Some("cookies"), key),
+            FieldKey::RouteParam(key) => (Some("params"), key),
+        }
+    }
+}
+
+/// The version of the Elastic Common Schema of [`FieldNaming::Ecs`].
+const ECS_VERSION: &str = "8.11.0";
+
+/// The field of [`OutputFormat::Fields`] events with the fields added by `on_log`.
+const EXTRA_FIELD: &str = "extra";
+
+/// The value of a format unit in structured output.
+enum FieldValue {
+    Str(String),
+    U64(u64),
+    F64(f64),
+    List(Vec<String>),
+}
+
+impl FieldValue {
+    /// The value as a tracing field, `None` for lists which tracing can't record.
+    fn as_value(&self) -> Option<&dyn tracing::field::Value> {
+        match self {
+            FieldValue::Str(v) => Some(v),
+            FieldValue::U64(v) => Some(v),
+            FieldValue::F64(v) => Some(v),
+            FieldValue::List(_) => None,
+        }
+    }
+}
+
+impl From<FieldValue> for Value {
+    fn from(value: FieldValue) -> Self {
+        match value {
+            FieldValue::Str(v) => Value::from(v),
+            FieldValue::U64(v) => Value::from(v),
+            FieldValue::F64(v) => Value::from(v),
+            FieldValue::List(v) => Value::from(v),
+        }
+    }
+}
+
 #[pin_project(PinnedDrop)]
 struct StreamLog<State: Clone + Send + Sync + 'static> {
     #[pin]
     body: Body,
-    format: Format<State>,
+    slots: Slots,
     size: usize,
-    time: OffsetDateTime,
+    expected_size: Option<usize>,
+    finished: bool,
+    logged: bool,
+    fields: Vec<(String, String)>,
+    context: Option<LogContext<State>>,
+    body_error: bool,
+    start: Instant,
+    duration: Option<Duration>,
     span: Span,
+    status: StatusCode,
+    inner: Arc<Inner<State>>,
+    scope: PrefixConfig,
+    metrics: Option<RequestMetrics>,
+    response_body: Option<Captured>,
+    _in_flight: InFlight,
+    _heartbeat: Option<HeartbeatGuard>,
+    #[cfg(feature = "dd")]
+    dd: Option<dd::DatadogIds>,
+    #[cfg(feature = "sentry")]
+    sentry: Option<sentry::SentryRequest>,
+}
+
+impl<State: Clone + Send + Sync + 'static> StreamLog<State> {
+    /// Render the record as structured fields, keyed by the units of the parsed format.
+    ///
+    /// Missing values (rendered as `-` in text mode) are `None`.
+    fn structured_fields(&self) -> Vec<(FieldKey, Option<FieldValue>)> {
+        let mut fields = Vec::new();
+        for (i, template) in self.inner.format.0.iter().enumerate() {
+            let key = match template.field_key(self.inner.field_naming) {
+                Some(key) => key,
+                None => continue,
+            };
+            if let (Slot::Values(values), Some(_)) =
+                (self.slots.get(i), &self.inner.header_separator)
+            {
+                let mut values = values.clone();
+                if let Some(max) = self.inner.max_line_len {
+                    values.iter_mut().for_each(|value| truncate(value, max));
+                }
+                fields.push((key, Some(FieldValue::List(values))));
+                continue;
+            }
+            let mut s = FormatDisplay(&|fmt| self.render_unit(i, fmt)).to_string();
+            if self.inner.field_naming == FieldNaming::Ecs
+                && matches!(template, FormatText::RealIPRemoteAddr)
+            {
+                // `source.ip` is mapped as an IP address
+                s = without_socket_port(&s).into_owned();
+            }
+            let value = if s == "-" {
+                None
+            } else if let FieldKey::Number(_) = key {
+                Some(
+                    s.parse::<u64>()
+                        .map(FieldValue::U64)
+                        .or_else(|_| s.parse::<f64>().map(FieldValue::F64))
+                        .unwrap_or(FieldValue::Str(s)),
+                )
+            } else {
+                let mut s = s;
+                if let Some(max) = self.inner.max_line_len {
+                    truncate(&mut s, max);
+                }
+                Some(FieldValue::Str(s))
+            };
+            fields.push((key, value));
+        }
+        if self.inner.field_naming == FieldNaming::Ecs {
+            self.push_ecs_fields(&mut fields);
+        }
+        for (key, value) in self.extra_fields() {
+            fields.push((
+                FieldKey::Label(key.clone()),
+                Some(FieldValue::Str(value.clone())),
+            ));
+        }
+        fields
+    }
+
+    /// Add the ECS fields derived from the others: `url.original` from the path and query,
+    /// and the version of the schema.
+    fn push_ecs_fields(&self, fields: &mut Vec<(FieldKey, Option<FieldValue>)>) {
+        let value = |name: &str| {
+            fields.iter().find_map(|(key, value)| match (key, value) {
+                (FieldKey::Field(key), Some(FieldValue::Str(value))) if *key == name => {
+                    Some(value.clone())
+                }
+                _ => None,
+            })
+        };
+        if let Some(mut url) = value("url.path") {
+            if let Some(query) = value("url.query") {
+                url.push('?');
+                url.push_str(&query);
+            }
+            fields.push((FieldKey::Field("url.original"), Some(FieldValue::Str(url))));
+        }
+        fields.push((
+            FieldKey::Field("ecs.version"),
+            Some(FieldValue::Str(ECS_VERSION.to_owned())),
+        ));
+    }
+
+    /// Why the access record is not logged, if it is not, which is only known once the
+    /// response is finished.
+    fn suppressed(&self, slow: bool) -> Option<Dropped> {
+        let status = self.status as u16;
+        if self.inner.only_slow && !slow {
+            return Some(Dropped::Filtered);
+        }
+        if self.inner.only_errors && status < 400 && !self.body_error {
+            return Some(Dropped::Filtered);
+        }
+        if self
+            .inner
+            .exclude_status
+            .iter()
+            .any(|range| range.contains(&status))
+        {
+            return Some(Dropped::Excluded);
+        }
+        let rate = self
+            .scope
+            .get_sample_rate()
+            .unwrap_or(self.inner.sample_rate);
+        (status < 400 && !self.body_error && !slow && !sample(rate)).then_some(Dropped::Sampled)
+    }
+
+    /// Render the record as text.
+    fn render_text(&self, fmt: &mut Formatter<'_>) -> Result<(), fmtError> {
+        for i in 0..self.inner.format.0.len() {
+            self.render_unit(i, fmt)?;
+        }
+        for (key, value) in self.extra_fields() {
+            write!(fmt, " {}={}", key, value)?;
+        }
+        Ok(())
+    }
+
+    /// The [constant](TracingMiddlewareBuilder::with_field) and
+    /// [per-request](TracingMiddlewareBuilder::field_fn) fields added to the record.
+    fn extra_fields(&self) -> impl Iterator<Item = &(String, String)> {
+        self.inner.static_fields.iter().chain(&self.fields)
+    }
+
+    /// Render the captured response body into the `%{body}ro` units once the body is
+    /// finished, and hide the bodies of successful requests if they are only logged on
+    /// errors.
+    fn render_bodies(self: Pin<&mut Self>) {
+        let hidden =
+            self.inner.capture_bodies_on_error && (self.status as u16) < 400 && !self.body_error;
+        let this = self.project();
+        if hidden {
+            for (i, template) in this.inner.format.0.iter().enumerate() {
+                if let FormatText::RequestBody | FormatText::ResponseBody = template {
+                    this.slots.set(i, "-");
+                }
+            }
+            return;
+        }
+        let body = match this.response_body.take() {
+            Some(body) if !body.is_empty() => body.to_string(),
+            _ => "-".to_owned(),
+        };
+        let body = if this.inner.escape_control_chars {
+            escape_control_chars(&body).into_owned()
+        } else {
+            body
+        };
+        for (i, template) in this.inner.format.0.iter().enumerate() {
+            if let FormatText::ResponseBody = template {
+                this.slots.set(i, &body);
+            }
+        }
+    }
+
+    /// Render the value of the unit `i` of the format.
+    fn render_unit(&self, i: usize, fmt: &mut Formatter<'_>) -> fmtResult {
+        match self.slots.get(i) {
+            Slot::Str(_) => fmt.write_str(self.slots.str(i).unwrap_or_default()),
+            Slot::Values(values) => match &self.inner.header_separator {
+                Some(separator) => {
+                    for (n, value) in values.iter().enumerate() {
+                        if n > 0 {
+                            fmt.write_str(separator)?;
+                        }
+                        fmt.write_str(value)?;
+                    }
+                    Ok(())
+                }
+                None => fmt.write_str(&values[0]),
+            },
+            Slot::Template => {
+                let precision = self.inner.duration_precision;
+                let unit = &self.inner.format.0[i];
+                unit.render(fmt, self.logged_size(), self.elapsed(), precision)
+            }
+        }
+    }
+
+    /// The time since the request arrived, or the time taken once the record is logged, so
+    /// that all units of a record agree.
+    fn elapsed(&self) -> Duration {
+        self.duration
+            .unwrap_or_else(|| self.inner.clock.now().saturating_duration_since(self.start))
+    }
+
+    /// The size of the body for `%b`, the expected one if the record is logged before the body
+    /// is sent.
+    fn logged_size(&self) -> usize {
+        if self.inner.log_point == LogPoint::ResponseHead || !self.inner.wrap_body {
+            self.expected_size.unwrap_or(0)
+        } else {
+            self.size
+        }
+    }
+
+    /// Render the `%{label}xc` units with the [`LogContext`], once the record is logged.
+    fn render_context(self: Pin<&mut Self>, duration: Duration) {
+        let size = self.logged_size();
+        let this = self.project();
+        let ctx = match this.context {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        ctx.finish(duration, size);
+        for (i, template) in this.inner.format.0.iter().enumerate() {
+            if let FormatText::CustomContext(label) = template {
+                let value = match this.inner.context_replacements.get(label) {
+                    Some(f) => f.call(ctx),
+                    None => "-".to_owned(),
+                };
+                this.slots.set(i, this.inner.sanitize(template, value));
+            }
+        }
+    }
+
+    /// Render the `%X` units, once the body is finished or dropped.
+    fn render_connection_status(self: Pin<&mut Self>) {
+        let complete = self.inner.log_point == LogPoint::ResponseHead
+            || (!self.body_error
+                && (self.finished || self.expected_size.is_some_and(|len| self.size >= len)));
+        let status = if complete { "-" } else { "X" };
+        let this = self.project();
+        for (i, template) in this.inner.format.0.iter().enumerate() {
+            if let FormatText::ConnectionStatus = template {
+                this.slots.set(i, status);
+            }
+        }
+    }
+
+    /// Render the record as text, truncated to the maximum line length.
+    fn render_line(&self) -> String {
+        let mut line = String::new();
+        self.write_line(&mut line);
+        line
+    }
+
+    /// Write the record to `out` like the configured output, as JSON for
+    /// [`Fields`](OutputFormat::Fields).
+    fn write_record(&self, level: Level, out: &mut String) {
+        match self.inner.output {
+            OutputFormat::Text => self.write_line(out),
+            OutputFormat::Json | OutputFormat::Fields => self.write_json(out),
+            OutputFormat::Gelf => self.write_gelf(level, out),
+            OutputFormat::W3c => self.write_w3c(out),
+        }
+    }
+
+    /// Write the record as text to `out`, truncated to the maximum line length.
+    fn write_line(&self, out: &mut String) {
+        let start = out.len();
+        if write!(out, "{}", FormatDisplay(&|fmt| self.render_text(fmt))).is_err() {
+            self.inner.stats.record_render_errors(1);
+        }
+        if let Some(max) = self.inner.max_line_len {
+            truncate(out, start + max);
+        }
+    }
+
+    /// Write the record as a JSON object to `out`.
+    fn write_json(&self, out: &mut String) {
+        let mut record = Map::new();
+        for (key, value) in self.structured_fields() {
+            let value = value.map_or(Value::Null, Value::from);
+            match key.split() {
+                (Some(group), key) => {
+                    if let Value::Object(map) = record
+                        .entry(group)
+                        .or_insert_with(|| Value::Object(Map::new()))
+                    {
+                        map.insert(key, value);
+                    }
+                }
+                (None, key) => {
+                    record.insert(key, value);
+                }
+            }
+        }
+        let _ = write!(out, "{}", Value::Object(record));
+    }
+
+    /// Write the record as a GELF message logged at `level` to `out`.
+    fn write_gelf(&self, level: Level, out: &mut String) {
+        let mut record = Map::new();
+        record.insert("version".to_owned(), Value::from("1.1"));
+        let host = self.inner.gelf_host.as_deref().unwrap_or(hostname());
+        record.insert("host".to_owned(), Value::from(host));
+        let full = FormatDisplay(&|fmt| self.render_text(fmt)).to_string();
+        let short = self.render_line();
+        if short != full {
+            record.insert("full_message".to_owned(), Value::from(full));
+        }
+        record.insert("short_message".to_owned(), Value::from(short));
+        let millis = self.inner.clock.now_utc().unix_timestamp_nanos() / 1_000_000;
+        record.insert("timestamp".to_owned(), Value::from(millis as f64 / 1000.0));
+        record.insert("level".to_owned(), Value::from(syslog_severity(level)));
+        for (name, value) in self.flat_fields() {
+            // the additional fields are limited to word characters, dots and dashes, and `_id`
+            // is reserved
+            let name: String = name
+                .chars()
+                .map(|c| match c {
+                    '.' | '-' => c,
+                    c if c.is_ascii_alphanumeric() => c,
+                    _ => '_',
+                })
+                .collect();
+            if name != "id" {
+                record.insert(format!("_{}", name), Value::from(value));
+            }
+        }
+        let _ = write!(out, "{}", Value::Object(record));
+    }
+
+    /// The `#Version` and `#Fields` directives of the W3C extended log format, one per line,
+    /// for the first record written, `None` for the others and the other outputs.
+    fn take_directives(&self) -> Option<String> {
+        if self.inner.output != OutputFormat::W3c
+            || self
+                .inner
+                .w3c_directives_written
+                .swap(true, Ordering::Relaxed)
+        {
+            return None;
+        }
+        let mut directives = String::from("#Version: 1.0\n#Fields:");
+        for name in self.inner.format.0.iter().filter_map(FormatText::w3c_field) {
+            directives.push(' ');
+            directives.push_str(&name);
+        }
+        directives.push('\n');
+        Some(directives)
+    }
+
+    /// Write the record as a line of the W3C extended log format to `out`, truncated to the
+    /// maximum line length.
+    fn write_w3c(&self, line: &mut String) {
+        let start = line.len();
+        let mut columns = 0;
+        for (i, template) in self.inner.format.0.iter().enumerate() {
+            if template.w3c_field().is_none() {
+                continue;
+            }
+            if columns > 0 {
+                line.push(' ');
+            }
+            columns += 1;
+            let value = FormatDisplay(&|fmt| self.render_unit(i, fmt)).to_string();
+            if let FormatText::RequestTime = template {
+                // `date time` are two columns
+                match value.split_once('T') {
+                    Some((date, time)) => {
+                        line.push_str(date);
+                        line.push(' ');
+                        line.push_str(time);
+                    }
+                    None => {
+                        line.push_str(&w3c_value(&value));
+                        line.push_str(" -");
+                    }
+                }
+                continue;
+            }
+            if let FormatText::TimeMillis = template {
+                // `time-taken` is in seconds
+                if let Ok(millis) = value.parse::<f64>() {
+                    let decimals = value
+                        .split_once('.')
+                        .map_or(0, |(_, decimals)| decimals.len());
+                    let _ = write!(line, "{:.*}", decimals + 3, millis / 1000.0);
+                    continue;
+                }
+            }
+            line.push_str(&w3c_value(&value));
+        }
+        if let Some(max) = self.inner.max_line_len {
+            truncate(line, start + max);
+        }
+    }
+
+    /// The structured fields named like `request_headers.user-agent`, without the missing
+    /// values. The values of multi-valued headers are joined like in the text output.
+    fn flat_fields(&self) -> Vec<(String, FieldValue)> {
+        self.structured_fields()
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let name = key.flat_name();
+                let value = match value? {
+                    FieldValue::List(values) => {
+                        let separator = self.inner.header_separator.as_deref().unwrap_or(", ");
+                        FieldValue::Str(values.join(separator))
+                    }
+                    value => value,
+                };
+                Some((name, value))
+            })
+            .collect()
+    }
+
+    /// Emit the record as an event with one field per format unit, the fields of header and
+    /// environment tokens are named like `request_headers.user-agent`. The missing values are
+    /// left out of the event, not out of its fields, see [`Inner::event_field_names`], and
+    /// the fields added by `on_log` are written as a JSON object in [`EXTRA_FIELD`].
+    fn emit_fields(&self, level: Level) {
+        let (mut fields, added): (Vec<_>, Vec<_>) = self
+            .flat_fields()
+            .into_iter()
+            .partition(|(key, _)| self.inner.field_names.contains(key));
+        if !added.is_empty() {
+            let added: Map<String, Value> = added
+                .into_iter()
+                .map(|(key, value)| (key, value.into()))
+                .collect();
+            fields.push((
+                EXTRA_FIELD.to_owned(),
+                FieldValue::Str(Value::Object(added).to_string()),
+            ));
+        }
+        #[cfg(feature = "dd")]
+        if let Some(dd) = self.dd {
+            fields.push((
+                "dd.trace_id".to_owned(),
+                FieldValue::Str(dd.trace_id.to_string()),
+            ));
+            fields.push((
+                "dd.span_id".to_owned(),
+                FieldValue::Str(dd.span_id.to_string()),
+            ));
+        }
+        let fields: Vec<(&str, Option<&dyn tracing::field::Value>)> = self
+            .inner
+            .field_names
+            .iter()
+            .map(|name| {
+                let value = fields
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .and_then(|(_, value)| value.as_value());
+                (name.as_str(), value)
+            })
+            .collect();
+        dynamic::emit(level, &self.span, &fields);
+    }
+
+    /// Emit the rendered record, with the Datadog correlation fields if enabled.
+    fn emit(&self, level: Level, record: fmt::Arguments<'_>) {
+        #[cfg(feature = "dd")]
+        if let Some(dd) = self.dd {
+            return dd::emit(level, &self.span, dd, record);
+        }
+        emit(level, &self.span, record)
+    }
 }
 
 #[pinned_drop]
 impl<State: Clone + Send + Sync + 'static> PinnedDrop for StreamLog<State> {
-    fn drop(self: Pin<&mut Self>) {
-        let render = |fmt: &mut Formatter<'_>| {
-            for unit in &self.format.0 {
-                unit.render(fmt, self.size, self.time)?;
+    fn drop(mut self: Pin<&mut Self>) {
+        if !self.logged {
+            self.as_mut().render_bodies();
+            self.as_mut().render_connection_status();
+        }
+        let elapsed = self.inner.clock.now().saturating_duration_since(self.start);
+        *self.as_mut().project().duration = Some(elapsed);
+        if self.body_error {
+            self.inner.stats.record_body_error();
+        }
+        self.inner.stats.record_render_errors(self.slots.errors);
+        let duration = elapsed.as_secs_f64();
+        if let Some(metrics) = &self.metrics {
+            metrics.record(self.status as u16, duration);
+        }
+        #[cfg(feature = "opentelemetry")]
+        if self.inner.otel_span {
+            otel::record_response(&self.span, self.status, self.size);
+        }
+        #[cfg(feature = "sentry")]
+        if let Some(sentry) = self.as_mut().project().sentry.take() {
+            sentry.finish(self.render_line(), self.status, duration);
+        }
+
+        if !self.logged {
+            self.log(elapsed);
+        }
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> StreamLog<State> {
+    /// Write the access record unless it is suppressed, `duration` is the time taken.
+    fn log(mut self: Pin<&mut Self>, duration: Duration) {
+        *self.as_mut().project().logged = true;
+        *self.as_mut().project().duration = Some(duration);
+
+        let slow = self
+            .inner
+            .slow_threshold
+            .is_some_and(|threshold| duration >= threshold);
+        if let Some(reason) = self.suppressed(slow) {
+            self.inner.stats.record_dropped(reason);
+            return;
+        }
+        if let Some(limiter) = &self.inner.rate_limit {
+            match limiter.acquire() {
+                None => {
+                    self.inner.stats.record_dropped(Dropped::RateLimited);
+                    return;
+                }
+                Some(0) => {}
+                Some(suppressed) => rate_limit::report(self.span.id(), suppressed),
             }
-            Ok(())
+        }
+        self.as_mut().render_context(duration);
+
+        let mut level = match (self.scope.get_level(), &self.inner.level_for_status) {
+            (Some(level), _) => level,
+            (None, Some(f)) => f(self.status),
+            (None, None) => self.inner.level,
         };
-        info!(parent: &self.span, "{}", FormatDisplay(&render));
+        if slow {
+            // more severe levels are smaller
+            level = level.min(Level::WARN);
+        }
+        if let Some(f) = self.inner.on_log.clone() {
+            let size = self.logged_size();
+            let this = self.as_mut().project();
+            let mut record = LogRecord::new(
+                &this.inner.format,
+                this.slots,
+                this.fields,
+                this.inner.field_naming,
+                this.span,
+                *this.status,
+                duration,
+                size,
+                level,
+                this.inner.clock.now_utc(),
+            );
+            if !f(&mut record) {
+                self.inner.stats.record_dropped(Dropped::Filtered);
+                return;
+            }
+            level = record.level();
+        }
+        let directives = self.take_directives();
+        if !self.inner.sinks.is_empty() {
+            let size = self.logged_size();
+            buffer::with_record(|message| {
+                self.write_record(level, message);
+                let this = self.as_mut().project();
+                let record = LogRecord::new(
+                    &this.inner.format,
+                    this.slots,
+                    this.fields,
+                    this.inner.field_naming,
+                    this.span,
+                    *this.status,
+                    duration,
+                    size,
+                    level,
+                    this.inner.clock.now_utc(),
+                )
+                .with_message(message)
+                .with_directives(directives.as_deref());
+                #[cfg(feature = "dd")]
+                let record = record.with_datadog(*this.dd);
+                for sink in &this.inner.sinks {
+                    sink.emit(&record);
+                }
+            });
+            self.inner.stats.record_emitted();
+            return;
+        }
+        if self.inner.output == OutputFormat::Fields {
+            self.emit_fields(level);
+            self.inner.stats.record_emitted();
+            return;
+        }
+        for directive in directives.iter().flat_map(|directives| directives.lines()) {
+            self.emit(level, format_args!("{}", directive));
+        }
+        buffer::with_record(|record| {
+            self.write_record(level, record);
+            self.emit(level, format_args!("{}", record));
+        });
+        self.inner.stats.record_emitted();
+    }
+}
+
+/// Truncate `s` to at most `max` bytes on a character boundary, followed by a marker with
+/// the number of dropped bytes.
+fn truncate(s: &mut String, max: usize) {
+    if s.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    let dropped = s.len() - end;
+    s.truncate(end);
+    s.push_str(&format!("...[truncated {} bytes]", dropped));
+}
+
+/// Returns `true` with the probability `rate`.
+fn sample(rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    // every `RandomState` is seeded differently, which is enough for sampling
+    let random = RandomState::new().build_hasher().finish();
+    let random = (random >> 11) as f64 / (1u64 << 53) as f64;
+    random < rate
+}
+
+/// Escape the control characters and backslashes of `s`, see
+/// [`TracingMiddlewareBuilder::escape_control_chars`].
+fn escape_control_chars(s: &str) -> Cow<'_, str> {
+    let needs_escape = |c: char| {
+        c == '\\'
+            || c.is_control()
+            || matches!(
+                c,
+                '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+            )
+    };
+    if !s.contains(needs_escape) {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        if needs_escape(c) {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// `host` without the port, like `example.com` for `example.com:8080` or `[::1]` for
+/// `[::1]:8080`, which `%p` logs.
+fn without_port(host: &str) -> &str {
+    // the colons of an IPv6 literal are inside the brackets
+    let start = host.rfind(']').unwrap_or(0);
+    match host[start..].rfind(':') {
+        Some(colon)
+            if host[start + colon + 1..]
+                .bytes()
+                .all(|b| b.is_ascii_digit()) =>
+        {
+            &host[..start + colon]
+        }
+        _ => host,
+    }
+}
+
+/// The IP address of the client address `addr`, like `192.0.2.1` for `192.0.2.1:5000` or
+/// `::1` for `[::1]:5000`.
+fn without_socket_port(addr: &str) -> Cow<'_, str> {
+    match addr.parse::<SocketAddr>() {
+        Ok(addr) => Cow::Owned(addr.ip().to_string()),
+        Err(_) => Cow::Borrowed(addr),
+    }
+}
+
+/// A value of the W3C extended log format: `-` if empty, with the whitespace replaced by `+`
+/// to keep the columns apart.
+fn w3c_value(value: &str) -> Cow<'_, str> {
+    if value.is_empty() {
+        Cow::Borrowed("-")
+    } else if value.contains(char::is_whitespace) {
+        Cow::Owned(value.replace(char::is_whitespace, "+"))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// The name of this host, looked up once with `gethostname`, then from the `HOSTNAME`
+/// variable and `/etc/hostname`, `localhost` if it is unknown.
+fn hostname() -> &'static str {
+    static HOSTNAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        let known = |name: String| Some(name.trim().to_owned()).filter(|name| !name.is_empty());
+        gethostname::gethostname()
+            .into_string()
+            .ok()
+            .and_then(known)
+            .or_else(|| std::env::var("HOSTNAME").ok().and_then(known))
+            .or_else(|| {
+                std::fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .and_then(known)
+            })
+            .unwrap_or_else(|| "localhost".to_owned())
+    })
+}
+
+/// The syslog severity of `level`, used by syslog and GELF: `err`, `warning`, `info` and
+/// `debug` for the lower levels.
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// Whether an access record at `level` would be recorded by the current subscriber.
+fn enabled(level: Level) -> bool {
+    match level {
+        Level::ERROR => tracing::enabled!(Level::ERROR),
+        Level::WARN => tracing::enabled!(Level::WARN),
+        Level::INFO => tracing::enabled!(Level::INFO),
+        Level::DEBUG => tracing::enabled!(Level::DEBUG),
+        _ => tracing::enabled!(Level::TRACE),
+    }
+}
+
+/// Emit an access record at a level only known at runtime, the tracing macros need a
+/// constant one.
+fn emit(level: Level, parent: &Span, record: fmt::Arguments<'_>) {
+    match level {
+        Level::ERROR => error!(parent: parent, "{}", record),
+        Level::WARN => warn!(parent: parent, "{}", record),
+        Level::INFO => info!(parent: parent, "{}", record),
+        Level::DEBUG => debug!(parent: parent, "{}", record),
+        _ => trace!(parent: parent, "{}", record),
     }
 }
 
@@ -563,8 +4928,16 @@ where
     ) -> Poll<std::io::Result<usize>> {
         let this = self.project();
         let res = this.body.poll_read(cx, buf);
-        if let Poll::Ready(size) = &res {
-            *this.size += if let Ok(n) = size { *n } else { 0 };
+        match &res {
+            Poll::Ready(Ok(n)) => {
+                *this.size += *n;
+                *this.finished |= *n == 0 && !buf.is_empty();
+                if let Some(captured) = this.response_body {
+                    captured.push(&buf[..*n]);
+                }
+            }
+            Poll::Ready(Err(_)) => *this.body_error = true,
+            Poll::Pending => {}
         }
         res
     }
@@ -0,0 +1,272 @@
+//! A Grafana Loki sink for the access records.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TrySendError};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+use tide::http::url::{Position, Url};
+use tracing::error;
+
+use crate::worker::{Overflow, Worker};
+use crate::{LogRecord, LogSink};
+
+/// A sink pushing the access records to the HTTP API of Grafana Loki, enabled with the
+/// `loki` feature, so no promtail agent is needed next to the service.
+///
+/// The records are sent in batches from a background thread, once
+/// [`batch_size`](Self::batch_size) records are queued or
+/// [`batch_interval`](Self::batch_interval) after the first one, as a single stream with
+/// the [labels](Self::label). The records left are sent when the sink is dropped, waiting
+/// up to 5 seconds.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use tide_tracing_middleware::{LokiSink, TracingMiddlewareBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let sink = LokiSink::new("http://loki:3100")?
+///     .label("app", "checkout")
+///     .label("env", "prod")
+///     .batch_interval(Duration::from_secs(2));
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::default().sink(sink).build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only plain `http` URLs are supported. Records are dropped, with an error event, when
+/// Loki cannot be reached, and while [`queue_size`](Self::queue_size) records are waiting,
+/// with an error event counting them at most every 10 seconds.
+#[derive(Debug)]
+pub struct LokiSink {
+    config: PushConfig,
+    queue_size: usize,
+    worker: OnceLock<Worker<(String, String)>>,
+    overflow: Overflow,
+}
+
+#[derive(Debug, Clone)]
+struct PushConfig {
+    url: Url,
+    labels: Map<String, Value>,
+    tenant: Option<String>,
+    batch_size: usize,
+    batch_interval: Duration,
+}
+
+impl LokiSink {
+    /// Push to the Loki server at `url`, like `http://loki:3100`. The push path
+    /// `/loki/api/v1/push` is added if `url` has no path.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let mut url = Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+        if url.scheme() != "http" {
+            return Err(invalid(format!("unsupported scheme {}", url.scheme())));
+        }
+        if url.host_str().is_none() {
+            return Err(invalid("no host to push to".to_owned()));
+        }
+        if url.path() == "/" {
+            url.set_path("/loki/api/v1/push");
+        }
+        Ok(Self {
+            config: PushConfig {
+                url,
+                labels: Map::new(),
+                tenant: None,
+                batch_size: 100,
+                batch_interval: Duration::from_secs(1),
+            },
+            queue_size: 10_000,
+            worker: OnceLock::new(),
+            overflow: Overflow::default(),
+        })
+    }
+
+    /// Add the label `key="value"` to the stream, `job="tide"` is used if none is set.
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.config
+            .labels
+            .insert(key.to_owned(), Value::from(value));
+        self
+    }
+
+    /// Push to the tenant `id` of a multi-tenant Loki, with the `X-Scope-OrgID` header.
+    ///
+    /// Fails if `id` is empty or has characters other than visible ASCII, which cannot be
+    /// sent in the header.
+    pub fn tenant(mut self, id: &str) -> io::Result<Self> {
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_graphic()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid tenant {:?}", id),
+            ));
+        }
+        self.config.tenant = Some(id.to_owned());
+        Ok(self)
+    }
+
+    /// Push once `n` records are queued, 100 by default.
+    pub fn batch_size(mut self, n: usize) -> Self {
+        self.config.batch_size = n.max(1);
+        self
+    }
+
+    /// Push at most `interval` after a record is queued, 1 second by default.
+    pub fn batch_interval(mut self, interval: Duration) -> Self {
+        self.config.batch_interval = interval;
+        self
+    }
+
+    /// Drop the records while `n` of them are waiting to be pushed, 10000 by default and at
+    /// least 1.
+    pub fn queue_size(mut self, n: usize) -> Self {
+        self.queue_size = n.max(1);
+        self
+    }
+
+    /// The worker pushing the records, started on the first record.
+    fn worker(&self) -> &Worker<(String, String)> {
+        self.worker.get_or_init(|| {
+            let config = self.config.clone();
+            Worker::spawn("loki-sink", self.queue_size, move |receiver| {
+                config.run(receiver)
+            })
+        })
+    }
+}
+
+impl Drop for LokiSink {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            if !worker.shutdown() {
+                error!("timed out pushing the queued access records to Loki");
+            }
+        }
+    }
+}
+
+impl LogSink for LokiSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        for line in record.lines() {
+            match self.worker().send((nanos.to_string(), line.to_owned())) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    if let Some(dropped) = self.overflow.record(Instant::now()) {
+                        error!(
+                            "the Loki sink queue is full, {} access records dropped",
+                            dropped
+                        )
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    error!("the Loki sink thread exited, access record dropped")
+                }
+            }
+        }
+    }
+}
+
+impl PushConfig {
+    /// Push the records of `receiver` in batches until the sink is dropped.
+    fn run(self, receiver: Receiver<(String, String)>) {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut deadline: Option<Instant> = None;
+        loop {
+            let entry = match deadline {
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => {
+                    receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+            };
+            let disconnected = match entry {
+                Ok(entry) => {
+                    if batch.is_empty() {
+                        deadline = Some(Instant::now() + self.batch_interval);
+                    }
+                    batch.push(entry);
+                    if batch.len() < self.batch_size {
+                        continue;
+                    }
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+            if !batch.is_empty() {
+                if let Err(err) = self.push(&batch) {
+                    error!(
+                        "failed to push {} access records to Loki: {}",
+                        batch.len(),
+                        err
+                    );
+                }
+                batch.clear();
+            }
+            deadline = None;
+            if disconnected {
+                return;
+            }
+        }
+    }
+
+    /// Send `batch` to Loki over HTTP/1.1.
+    fn push(&self, batch: &[(String, String)]) -> io::Result<()> {
+        let labels = if self.labels.is_empty() {
+            json!({ "job": "tide" })
+        } else {
+            Value::Object(self.labels.clone())
+        };
+        let body = json!({
+            "streams": [{ "stream": labels, "values": batch }],
+        })
+        .to_string();
+
+        let host = &self.url[Position::BeforeHost..Position::AfterPort];
+        let addr = self.url.socket_addrs(|| None)?.into_iter().next();
+        let addr = addr.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", host))
+        })?;
+        let timeout = Duration::from_secs(5);
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut target = self.url.path().to_owned();
+        if let Some(query) = self.url.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            target,
+            host,
+            body.len()
+        );
+        if let Some(tenant) = &self.tenant {
+            request.push_str("X-Scope-OrgID: ");
+            request.push_str(tenant);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        request.push_str(&body);
+        stream.write_all(request.as_bytes())?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        match status_line.split(' ').nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "unexpected response {}",
+                status_line.trim_end()
+            ))),
+        }
+    }
+}
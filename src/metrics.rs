@@ -0,0 +1,321 @@
+//! Request metrics in the Prometheus text exposition format, or through the `metrics` crate
+//! with the `metrics` feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::StatsdSink;
+
+/// The default buckets of the request duration histogram, in seconds.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// A handle to the request metrics collected by the middleware, enabled with
+/// [`TracingMiddlewareBuilder::metrics`](crate::TracingMiddlewareBuilder::metrics).
+///
+/// The handle is cheap to clone, all clones share the same metrics:
+///
+/// - `http_requests_total{method,status,route}`: counter of the finished requests
+/// - `http_request_duration_seconds{route}`: histogram of the request durations per route,
+///   for latency percentiles per endpoint
+/// - `http_requests_in_flight`: gauge of the requests whose response body is not finished
+///
+/// The metrics are recorded from the same timing code as the access record, when the
+/// response body is finished. Excluded paths are not counted.
+///
+/// ```rust,no_run
+/// use tide_tracing_middleware::{Metrics, TracingMiddlewareBuilder};
+///
+/// let metrics = Metrics::new();
+/// let mut app = tide::new();
+/// app.with(
+///     TracingMiddlewareBuilder::default()
+///         .metrics(metrics.clone())
+///         .build(),
+/// );
+/// // later, e.g. in a `/metrics` endpoint
+/// let text = metrics.render();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+#[derive(Debug)]
+struct Registry {
+    requests: BTreeMap<(String, u16, String), u64>,
+    buckets: Vec<f64>,
+    durations: BTreeMap<String, Histogram>,
+    in_flight: i64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    /// Create metrics with the [`DEFAULT_BUCKETS`].
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    /// Create metrics with the given upper bounds of the duration histogram buckets, in
+    /// seconds. The `+Inf` bucket is always added.
+    pub fn with_buckets(buckets: &[f64]) -> Self {
+        let mut buckets: Vec<f64> = buckets.iter().copied().filter(|b| b.is_finite()).collect();
+        buckets.sort_by(|a, b| a.total_cmp(b));
+        buckets.dedup();
+        Self {
+            registry: Arc::new(Mutex::new(Registry {
+                requests: BTreeMap::new(),
+                buckets,
+                durations: BTreeMap::new(),
+                in_flight: 0,
+            })),
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, status, route), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",status=\"{}\",route=\"{}\"}} {}",
+                escape(method),
+                status,
+                escape(route),
+                count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds HTTP request duration in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (route, histogram) in &registry.durations {
+            let route = escape(route);
+            for (bucket, count) in registry.buckets.iter().zip(&histogram.counts) {
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bucket, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            );
+        }
+
+        out.push_str("# HELP http_requests_in_flight Number of HTTP requests in flight.\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(out, "http_requests_in_flight {}", registry.in_flight);
+        out
+    }
+
+    /// Record a finished request.
+    pub(crate) fn record(&self, method: &str, status: u16, route: &str, duration: f64) {
+        let mut registry = self.lock();
+        *registry
+            .requests
+            .entry((method.to_owned(), status, route.to_owned()))
+            .or_default() += 1;
+
+        let registry = &mut *registry;
+        let buckets = &registry.buckets;
+        let histogram = registry
+            .durations
+            .entry(route.to_owned())
+            .or_insert_with(|| Histogram {
+                counts: vec![0; buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        // buckets are cumulative
+        for (bucket, count) in buckets.iter().zip(&mut histogram.counts) {
+            if duration <= *bucket {
+                *count += 1;
+            }
+        }
+        histogram.sum += duration;
+        histogram.count += 1;
+    }
+
+    fn add_in_flight(&self, delta: i64) {
+        self.lock().in_flight += delta;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The names of the metrics emitted through the `metrics` crate, see
+/// [`TracingMiddlewareBuilder::metrics_facade`](crate::TracingMiddlewareBuilder::metrics_facade).
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct MetricNames {
+    requests_total: String,
+    request_duration: String,
+    requests_in_flight: String,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricNames {
+    /// The name of the request counter, `http_requests_total` by default.
+    pub fn requests_total(mut self, name: impl Into<String>) -> Self {
+        self.requests_total = name.into();
+        self
+    }
+
+    /// The name of the request duration histogram, `http_request_duration_seconds` by
+    /// default.
+    pub fn request_duration(mut self, name: impl Into<String>) -> Self {
+        self.request_duration = name.into();
+        self
+    }
+
+    /// The name of the in-flight request gauge, `http_requests_in_flight` by default.
+    pub fn requests_in_flight(mut self, name: impl Into<String>) -> Self {
+        self.requests_in_flight = name.into();
+        self
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Default for MetricNames {
+    fn default() -> Self {
+        Self {
+            requests_total: "http_requests_total".to_owned(),
+            request_duration: "http_request_duration_seconds".to_owned(),
+            requests_in_flight: "http_requests_in_flight".to_owned(),
+        }
+    }
+}
+
+/// Counts a request as in flight until it is dropped with the response body.
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Count a request as in flight, returns the number of requests in flight including it.
+    pub(crate) fn start(count: &Arc<AtomicUsize>) -> (Self, usize) {
+        let n = count.fetch_add(1, Ordering::Relaxed) + 1;
+        (Self(count.clone()), n)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Where the request metrics are recorded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricSinks {
+    pub(crate) prometheus: Option<Metrics>,
+    #[cfg(feature = "metrics")]
+    pub(crate) facade: Option<Arc<MetricNames>>,
+    pub(crate) statsd: Option<Arc<StatsdSink>>,
+}
+
+impl MetricSinks {
+    pub(crate) fn is_enabled(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        if self.facade.is_some() {
+            return true;
+        }
+        self.prometheus.is_some() || self.statsd.is_some()
+    }
+}
+
+/// The labels of a request, taken when the request starts. The request is counted in the
+/// in-flight gauges until this is dropped.
+pub(crate) struct RequestMetrics {
+    sinks: MetricSinks,
+    method: String,
+    route: String,
+}
+
+impl RequestMetrics {
+    pub(crate) fn start(sinks: MetricSinks, method: String, route: String) -> Self {
+        if let Some(metrics) = &sinks.prometheus {
+            metrics.add_in_flight(1);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(names) = &sinks.facade {
+            ::metrics::gauge!(names.requests_in_flight.clone()).increment(1.0);
+        }
+        Self {
+            sinks,
+            method,
+            route,
+        }
+    }
+
+    pub(crate) fn record(&self, status: u16, duration: f64) {
+        if let Some(metrics) = &self.sinks.prometheus {
+            metrics.record(&self.method, status, &self.route, duration);
+        }
+        if let Some(statsd) = &self.sinks.statsd {
+            statsd.record(&self.method, status, &self.route, duration);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(names) = &self.sinks.facade {
+            let labels = [
+                ("method", self.method.clone()),
+                ("status", status.to_string()),
+                ("route", self.route.clone()),
+            ];
+            ::metrics::counter!(names.requests_total.clone(), &labels).increment(1);
+            ::metrics::histogram!(names.request_duration.clone(), "route" => self.route.clone())
+                .record(duration);
+        }
+    }
+}
+
+impl Drop for RequestMetrics {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.sinks.prometheus {
+            metrics.add_in_flight(-1);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(names) = &self.sinks.facade {
+            ::metrics::gauge!(names.requests_in_flight.clone()).decrement(1.0);
+        }
+    }
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
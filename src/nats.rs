@@ -0,0 +1,282 @@
+//! A NATS sink for the access records.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TrySendError};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tracing::error;
+
+use crate::worker::{Overflow, Worker};
+use crate::{LogRecord, LogSink};
+
+/// A sink publishing each access record as a message to a NATS subject, enabled with the
+/// `nats` feature, for pipelines consuming the records as events. Use
+/// [`OutputFormat::Json`](crate::OutputFormat::Json) to publish structured records.
+///
+/// ```rust,no_run
+/// use tide_tracing_middleware::{NatsSink, OutputFormat, TracingMiddlewareBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let sink = NatsSink::new("127.0.0.1:4222", "audit.http")?;
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::new("%t %a %M %U %s %D")
+///     .output(OutputFormat::Json)
+///     .sink(sink)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Messages are published with core NATS, at most once, over a plain TCP connection opened
+/// by a background thread on the first record. The records left are published when the
+/// sink is dropped, waiting up to 5 seconds. Servers requiring TLS are not supported.
+///
+/// Records are dropped, with an error event, while the server cannot be reached, when the
+/// connection is retried at most once a second, and while
+/// [`queue_size`](Self::queue_size) records are waiting, with an error event counting them
+/// at most every 10 seconds.
+#[derive(Debug)]
+pub struct NatsSink {
+    config: ConnectConfig,
+    queue_size: usize,
+    worker: OnceLock<Worker<String>>,
+    overflow: Overflow,
+}
+
+/// How long the connection waits for a record before answering the pings of the server.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the records are dropped after the connection failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct ConnectConfig {
+    addr: SocketAddr,
+    subject: String,
+    token: Option<String>,
+}
+
+impl NatsSink {
+    /// Publish to `subject` on the NATS server at `addr`.
+    pub fn new(addr: impl ToSocketAddrs, subject: &str) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        Ok(Self {
+            config: ConnectConfig {
+                addr,
+                subject: subject.to_owned(),
+                token: None,
+            },
+            queue_size: 10_000,
+            worker: OnceLock::new(),
+            overflow: Overflow::default(),
+        })
+    }
+
+    /// Authenticate with `token`.
+    pub fn token(mut self, token: &str) -> Self {
+        self.config.token = Some(token.to_owned());
+        self
+    }
+
+    /// Drop the records while `n` of them are waiting to be published, 10000 by default and
+    /// at least 1.
+    pub fn queue_size(mut self, n: usize) -> Self {
+        self.queue_size = n.max(1);
+        self
+    }
+
+    /// The worker publishing the records, started on the first record.
+    fn worker(&self) -> &Worker<String> {
+        self.worker.get_or_init(|| {
+            let config = self.config.clone();
+            Worker::spawn("nats-sink", self.queue_size, move |receiver| {
+                Publisher::new(config).run(receiver)
+            })
+        })
+    }
+}
+
+impl Drop for NatsSink {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            if !worker.shutdown() {
+                error!("timed out publishing the queued access records to NATS");
+            }
+        }
+    }
+}
+
+impl LogSink for NatsSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        for line in record.lines() {
+            match self.worker().send(line.to_owned()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    if let Some(dropped) = self.overflow.record(Instant::now()) {
+                        error!(
+                            "the NATS sink queue is full, {} access records dropped",
+                            dropped
+                        )
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    error!("the NATS sink thread exited, access record dropped")
+                }
+            }
+        }
+    }
+}
+
+/// The connection of the worker.
+struct Publisher {
+    config: ConnectConfig,
+    conn: Option<TcpStream>,
+    /// When the connection may be opened again after it failed.
+    retry_at: Option<Instant>,
+    /// The records dropped since the connection failed.
+    dropped: u64,
+}
+
+impl Publisher {
+    fn new(config: ConnectConfig) -> Self {
+        Self {
+            config,
+            conn: None,
+            retry_at: None,
+            dropped: 0,
+        }
+    }
+
+    /// Publish the records of `receiver` until the sink is dropped.
+    fn run(mut self, receiver: Receiver<String>) {
+        loop {
+            match receiver.recv_timeout(IDLE_INTERVAL) {
+                Ok(payload) => self.send(&payload),
+                Err(RecvTimeoutError::Timeout) => self.keep_alive(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Publish `payload`, reopening the connection if publishing fails.
+    fn send(&mut self, payload: &str) {
+        if let Some(conn) = &mut self.conn {
+            if self.config.publish(conn, payload).is_ok() {
+                return;
+            }
+            self.conn = None;
+        }
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            self.dropped += 1;
+            return;
+        }
+        let result = self.config.connect().and_then(|mut conn| {
+            self.config.publish(&mut conn, payload)?;
+            Ok(conn)
+        });
+        match result {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                self.retry_at = None;
+                if self.dropped > 0 {
+                    error!(
+                        "{} access records dropped while the NATS server was unreachable",
+                        std::mem::take(&mut self.dropped)
+                    );
+                }
+            }
+            Err(err) => {
+                self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                error!(
+                    "failed to publish {} access records to NATS subject {}: {}",
+                    std::mem::take(&mut self.dropped) + 1,
+                    self.config.subject,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Answer the pings the server sent while no record was published, so it does not close
+    /// an idle connection.
+    fn keep_alive(&mut self) {
+        if let Some(conn) = &mut self.conn {
+            let result = read_pings(conn)
+                .and_then(|pings| conn.write_all("PONG\r\n".repeat(pings).as_bytes()));
+            if result.is_err() {
+                self.conn = None;
+            }
+        }
+    }
+}
+
+impl ConnectConfig {
+    /// Open a connection, reading the `INFO` of the server and sending `CONNECT`.
+    fn connect(&self) -> io::Result<TcpStream> {
+        let timeout = Duration::from_secs(5);
+        let mut conn = TcpStream::connect_timeout(&self.addr, timeout)?;
+        conn.set_read_timeout(Some(timeout))?;
+        conn.set_write_timeout(Some(timeout))?;
+        let mut info = String::new();
+        BufReader::new(&conn).read_line(&mut info)?;
+        if !info.starts_with("INFO ") {
+            return Err(io::Error::other(format!(
+                "unexpected greeting {}",
+                info.trim_end()
+            )));
+        }
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": env!("CARGO_PKG_NAME"),
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        if let Some(token) = &self.token {
+            options["auth_token"] = token.as_str().into();
+        }
+        conn.write_all(format!("CONNECT {}\r\n", options).as_bytes())?;
+        Ok(conn)
+    }
+
+    /// Publish `payload`, answering the pings the server sent meanwhile.
+    fn publish(&self, conn: &mut TcpStream, payload: &str) -> io::Result<()> {
+        let pings = read_pings(conn)?;
+        let mut message = String::with_capacity(payload.len() + self.subject.len() + 32);
+        for _ in 0..pings {
+            message.push_str("PONG\r\n");
+        }
+        message.push_str(&format!("PUB {} {}\r\n", self.subject, payload.len()));
+        message.push_str(payload);
+        message.push_str("\r\n");
+        conn.write_all(message.as_bytes())
+    }
+}
+
+/// Read what the server sent without waiting, and return the number of pings in it.
+fn read_pings(conn: &mut TcpStream) -> io::Result<usize> {
+    conn.set_nonblocking(true)?;
+    let mut pings = 0;
+    let mut buf = [0; 512];
+    let received = loop {
+        match conn.read(&mut buf) {
+            Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                let received = String::from_utf8_lossy(&buf[..n]);
+                pings += received.matches("PING\r\n").count();
+                if let Some(err) = received.lines().find(|line| line.starts_with("-ERR")) {
+                    error!("NATS server error: {}", err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(pings),
+            Err(err) => break Err(err),
+        }
+    };
+    conn.set_nonblocking(false)?;
+    received
+}
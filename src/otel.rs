@@ -0,0 +1,101 @@
+//! Spans wired for `tracing-opentelemetry`.
+//!
+//! `tracing-opentelemetry` turns the fields of a span into attributes and treats the
+//! `otel.*` fields specially, which gives the span kind SERVER, the HTTP semantic attributes
+//! and an error status for 5xx responses.
+
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId};
+use opentelemetry::Context;
+use tide::{Request, StatusCode};
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::TraceContext;
+
+/// Create a SERVER span for the request, the response attributes are recorded later by
+/// [`record_response`], and the scrubbed query and anonymized client address by the
+/// middleware.
+pub(crate) fn server_span<State: Clone + Send + Sync + 'static>(req: &Request<State>) -> Span {
+    let span = tracing::info_span!(
+        "HTTP request",
+        otel.name = %req.method(),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        http.request.method = %req.method(),
+        http.response.status_code = Empty,
+        http.response.body.size = Empty,
+        url.path = req.url().path(),
+        url.query = Empty,
+        url.scheme = req.url().scheme(),
+        network.protocol.version = Empty,
+        client.address = Empty,
+        server.address = Empty,
+        user_agent.original = Empty,
+    );
+    if let Some(version) = req.version() {
+        span.record(
+            "network.protocol.version",
+            version.to_string().trim_start_matches("HTTP/"),
+        );
+    }
+    if let Some(host) = req.host() {
+        span.record("server.address", host);
+    }
+    if let Some(user_agent) = req.header("User-Agent") {
+        span.record("user_agent.original", user_agent.as_str());
+    }
+    span
+}
+
+/// Record the response attributes on a span created by [`server_span`], marking it as an
+/// error for 5xx responses.
+pub(crate) fn record_response(span: &Span, status: StatusCode, size: usize) {
+    span.record("http.response.status_code", status as u16);
+    span.record("http.response.body.size", size as u64);
+    if status.is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+/// The trace ID of `span` in hex, `None` if no OpenTelemetry layer is installed.
+pub(crate) fn trace_id(span: &Span) -> Option<String> {
+    span_context(span).map(|span_context| span_context.trace_id().to_string())
+}
+
+/// The trace ID and span ID of `span`, `None` if no OpenTelemetry layer is installed.
+#[cfg(feature = "dd")]
+pub(crate) fn span_ids(span: &Span) -> Option<([u8; 16], [u8; 8])> {
+    span_context(span).map(|span_context| {
+        (
+            span_context.trace_id().to_bytes(),
+            span_context.span_id().to_bytes(),
+        )
+    })
+}
+
+fn span_context(span: &Span) -> Option<SpanContext> {
+    let cx = span.context();
+    let span_context = cx.span().span_context().clone();
+    if span_context.is_valid() {
+        Some(span_context)
+    } else {
+        None
+    }
+}
+
+/// Make `span` a child of the caller's span described by `cx`.
+pub(crate) fn set_parent(span: &Span, cx: &TraceContext) {
+    let trace_state = cx
+        .trace_state()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_default();
+    let span_context = SpanContext::new(
+        TraceId::from_bytes(cx.trace_id()),
+        SpanId::from_bytes(cx.span_id()),
+        TraceFlags::new(cx.flags()),
+        true,
+        trace_state,
+    );
+    let _ = span.set_parent(Context::new().with_remote_span_context(span_context));
+}
@@ -0,0 +1,72 @@
+//! Catching panics of the handler.
+
+use std::any::Any;
+use std::panic::{resume_unwind, AssertUnwindSafe};
+
+use futures::FutureExt;
+use tide::{Next, Request, Response, StatusCode};
+use tracing::error;
+
+/// The target of the panic events, the one of the access records.
+const TARGET: &str = "tide_tracing_middleware";
+
+/// What happens after a panic of the handler is logged, see
+/// [`TracingMiddlewareBuilder::catch_panics`](crate::TracingMiddlewareBuilder::catch_panics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnPanic {
+    /// Continue unwinding, like without the middleware. No access record is logged.
+    Resume,
+    /// Respond with a 500 Internal Server Error, whose error (see `%E`) is the panic message.
+    /// The access record is logged as usual.
+    Respond,
+}
+
+/// Run the rest of the middleware chain and the handler, logging their panics as errors.
+pub(crate) async fn run<State: Clone + Send + Sync + 'static>(
+    next: Next<'_, State>,
+    req: Request<State>,
+    on_panic: Option<OnPanic>,
+) -> Response {
+    let on_panic = match on_panic {
+        Some(on_panic) => on_panic,
+        None => return next.run(req).await,
+    };
+    let method = req.method();
+    let path = req.url().path().to_owned();
+    let payload = match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+        Ok(resp) => return resp,
+        Err(payload) => payload,
+    };
+
+    let message = message(&*payload);
+    error!(
+        target: TARGET,
+        method = %method,
+        path = %path,
+        panic = %message,
+        "handler panicked: {}",
+        message
+    );
+    match on_panic {
+        OnPanic::Resume => resume_unwind(payload),
+        OnPanic::Respond => {
+            let mut resp = Response::new(StatusCode::InternalServerError);
+            resp.set_error(tide::Error::from_str(
+                StatusCode::InternalServerError,
+                format!("handler panicked: {}", message),
+            ));
+            resp
+        }
+    }
+}
+
+/// The message of a panic payload, which is a `&str` or a `String` for the `panic!` macro.
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
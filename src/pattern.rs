@@ -0,0 +1,43 @@
+//! Sets of regex patterns, matching nothing without the `regex` feature.
+
+#[cfg(feature = "regex")]
+use regex::RegexSet;
+
+/// The patterns added by the `*_regex` methods of the builder.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PatternSet {
+    #[cfg(feature = "regex")]
+    set: Option<RegexSet>,
+}
+
+impl PatternSet {
+    /// Add `pattern` to the set.
+    #[cfg(feature = "regex")]
+    pub(crate) fn push(&mut self, pattern: String) -> Result<(), regex::Error> {
+        let mut patterns = self
+            .set
+            .as_ref()
+            .map_or(Vec::new(), |set| set.patterns().to_vec());
+        patterns.push(pattern);
+        self.set = Some(RegexSet::new(patterns)?);
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        #[cfg(feature = "regex")]
+        if let Some(set) = &self.set {
+            return set.is_empty();
+        }
+        true
+    }
+
+    /// Whether any pattern matches `s`.
+    pub(crate) fn is_match(&self, s: &str) -> bool {
+        #[cfg(feature = "regex")]
+        if let Some(set) = &self.set {
+            return set.is_match(s);
+        }
+        let _ = s;
+        false
+    }
+}
@@ -0,0 +1,33 @@
+//! Salted hashing of personal data.
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use crate::propagation::to_hex;
+
+/// The number of bytes of the hash that are logged, as hex.
+const HASH_LEN: usize = 8;
+
+/// Hashes logged values with HMAC-SHA256 keyed with a salt, so equal values can still be
+/// correlated without storing them.
+#[derive(Debug, Clone)]
+pub(crate) struct PiiHasher {
+    salt: Vec<u8>,
+}
+
+impl PiiHasher {
+    /// A hasher with a random salt, hashes are only comparable within the process.
+    pub(crate) fn random() -> Self {
+        Self::new(uuid::Uuid::new_v4().as_bytes().to_vec())
+    }
+
+    pub(crate) fn new(salt: Vec<u8>) -> Self {
+        Self { salt }
+    }
+
+    pub(crate) fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.salt).expect("HMAC accepts any key length");
+        mac.update(value.as_bytes());
+        to_hex(&mac.finalize().into_bytes()[..HASH_LEN])
+    }
+}
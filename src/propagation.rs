@@ -0,0 +1,226 @@
+//! Trace context propagation headers.
+
+use std::fmt::Write;
+
+use tide::Request;
+
+use crate::xray::{self, XRayTraceHeader};
+
+/// The headers the trace context of the caller is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Propagation {
+    /// W3C Trace Context: `traceparent` and `tracestate`.
+    #[default]
+    W3c,
+    /// Zipkin B3: the single `b3` header, or `X-B3-TraceId`, `X-B3-SpanId`, `X-B3-Sampled`
+    /// and `X-B3-Flags` if it is absent.
+    B3,
+    /// AWS X-Ray: `X-Amzn-Trace-Id`, see [`XRayTraceHeader`].
+    XRay,
+}
+
+/// The trace context of the caller, parsed from the headers selected with [`Propagation`].
+///
+/// The middleware stores it in the request extensions, so handlers and
+/// [`gen_tracing_span`](crate::TracingMiddlewareBuilder::gen_tracing_span) can read it with
+/// `req.ext::<TraceContext>()`, and the format can render it with `%{trace_id}` and
+/// `%{span_id}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    flags: u8,
+    trace_state: Option<String>,
+}
+
+impl TraceContext {
+    /// Parse a `traceparent` header value and an optional `tracestate` header value.
+    ///
+    /// Returns `None` if `traceparent` is invalid.
+    pub fn from_w3c(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        // later versions may append fields, version 00 must not
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        from_hex::<1>(version)?;
+
+        let trace_id = from_hex::<16>(trace_id)?;
+        let span_id = from_hex::<8>(span_id)?;
+        let [flags] = from_hex::<1>(flags)?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            span_id,
+            flags,
+            trace_state: tracestate
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned),
+        })
+    }
+
+    /// Parse a single `b3` header value: `{TraceId}-{SpanId}-{SamplingState}-{ParentSpanId}`,
+    /// where the last two parts are optional.
+    ///
+    /// Returns `None` if the value is invalid or only carries a sampling decision.
+    pub fn from_b3(b3: &str) -> Option<Self> {
+        let mut parts = b3.trim().split('-');
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let sampled = parts.next();
+        let parent_id = parts.next();
+        if parts.next().is_some() || parent_id.is_some_and(|id| from_hex::<8>(id).is_none()) {
+            return None;
+        }
+        Self::from_b3_parts(trace_id, span_id, sampled, None)
+    }
+
+    /// Parse the `X-B3-TraceId`, `X-B3-SpanId`, `X-B3-Sampled` and `X-B3-Flags` header
+    /// values.
+    ///
+    /// Returns `None` if the IDs are invalid.
+    pub fn from_b3_multi(
+        trace_id: &str,
+        span_id: &str,
+        sampled: Option<&str>,
+        flags: Option<&str>,
+    ) -> Option<Self> {
+        Self::from_b3_parts(trace_id.trim(), span_id.trim(), sampled, flags)
+    }
+
+    /// Parse an AWS X-Ray `X-Amzn-Trace-Id` header value.
+    ///
+    /// Returns `None` if the value is invalid or has no `Parent`, as a header added by a load
+    /// balancer only names the trace.
+    pub fn from_xray(value: &str) -> Option<Self> {
+        let header = XRayTraceHeader::parse(value)?;
+        Some(Self {
+            trace_id: header.trace_id(),
+            span_id: from_hex::<8>(header.parent()?)?,
+            flags: if header.sampled() == Some(true) {
+                0x01
+            } else {
+                0x00
+            },
+            trace_state: None,
+        })
+    }
+
+    fn from_b3_parts(
+        trace_id: &str,
+        span_id: &str,
+        sampled: Option<&str>,
+        flags: Option<&str>,
+    ) -> Option<Self> {
+        // 64 bit trace IDs are left padded to 128 bits
+        let trace_id = match trace_id.len() {
+            16 => {
+                let mut id = [0; 16];
+                id[8..].copy_from_slice(&from_hex::<8>(trace_id)?);
+                id
+            }
+            _ => from_hex::<16>(trace_id)?,
+        };
+        let span_id = from_hex::<8>(span_id)?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        let debug = flags.is_some_and(|f| f.trim() == "1");
+        let sampled = match sampled.map(str::trim) {
+            Some("1") | Some("d") | Some("true") => true,
+            _ => debug,
+        };
+
+        Some(Self {
+            trace_id,
+            span_id,
+            flags: if sampled { 0x01 } else { 0x00 },
+            trace_state: None,
+        })
+    }
+
+    /// Parse the trace context from the headers of a request.
+    pub(crate) fn from_request<State: Clone + Send + Sync + 'static>(
+        req: &Request<State>,
+        propagation: Propagation,
+    ) -> Option<Self> {
+        let header = |name: &str| req.header(name).map(|v| v.as_str());
+        match propagation {
+            Propagation::W3c => Self::from_w3c(header("traceparent")?, header("tracestate")),
+            Propagation::B3 => match header("b3") {
+                Some(b3) => Self::from_b3(b3),
+                None => Self::from_b3_multi(
+                    header("X-B3-TraceId")?,
+                    header("X-B3-SpanId")?,
+                    header("X-B3-Sampled"),
+                    header("X-B3-Flags"),
+                ),
+            },
+            Propagation::XRay => Self::from_xray(header(xray::HEADER)?),
+        }
+    }
+
+    /// The 16 byte trace ID.
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    /// The 8 byte ID of the caller's span, the parent of the spans of this request.
+    pub fn span_id(&self) -> [u8; 8] {
+        self.span_id
+    }
+
+    /// The trace flags.
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Whether the caller sampled the trace.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 0x01 == 0x01
+    }
+
+    /// The vendor specific W3C `tracestate` header value, if any.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_deref()
+    }
+
+    /// The trace ID as 32 lowercase hex digits.
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    /// The span ID as 16 lowercase hex digits.
+    pub fn span_id_hex(&self) -> String {
+        to_hex(&self.span_id)
+    }
+}
+
+/// Parse exactly `N` bytes from `2 * N` hex digits.
+fn from_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != 2 * N || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(s, "{:02x}", byte);
+    }
+    s
+}
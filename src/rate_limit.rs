@@ -0,0 +1,100 @@
+//! A token bucket limiting the access records per second.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::span::Id;
+use tracing::warn;
+
+use crate::Clock;
+
+/// The minimum interval between two summaries of the suppressed records.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct RateLimiter {
+    rate: f64,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+struct State {
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: u64,
+    last_summary: Instant,
+    flush_scheduled: bool,
+}
+
+impl RateLimiter {
+    /// Allow `rate` records per second of `clock`, with bursts of up to one second.
+    pub(crate) fn new(rate: u32, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        Self {
+            rate: rate as f64,
+            clock,
+            state: Mutex::new(State {
+                tokens: rate as f64,
+                last_refill: now,
+                suppressed: 0,
+                last_summary: now,
+                flush_scheduled: false,
+            }),
+        }
+    }
+
+    /// Take a token for a record. Returns `None` if the record is suppressed, or else the
+    /// number of suppressed records to report, if it is time for a summary.
+    ///
+    /// The first suppressed record also starts a timer reporting the suppressed records a
+    /// second later, in case no record is let through by then.
+    pub(crate) fn acquire(self: &Arc<Self>) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let now = self.clock.now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.rate);
+        state.last_refill = now;
+
+        if state.tokens < 1.0 {
+            state.suppressed += 1;
+            if !state.flush_scheduled {
+                state.flush_scheduled = true;
+                let limiter = Arc::clone(self);
+                async_std::task::spawn(async move {
+                    async_std::task::sleep(SUMMARY_INTERVAL).await;
+                    limiter.flush();
+                });
+            }
+            return None;
+        }
+        state.tokens -= 1.0;
+
+        if state.suppressed > 0 && now.duration_since(state.last_summary) >= SUMMARY_INTERVAL {
+            state.last_summary = now;
+            return Some(std::mem::take(&mut state.suppressed));
+        }
+        Some(0)
+    }
+
+    /// Report the records suppressed since the last summary, outside of any request.
+    fn flush(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.flush_scheduled = false;
+        if state.suppressed > 0 {
+            state.last_summary = self.clock.now();
+            let suppressed = std::mem::take(&mut state.suppressed);
+            drop(state);
+            report(None, suppressed);
+        }
+    }
+}
+
+/// Log a summary of `suppressed` records, in the span `parent` of the request letting the
+/// next record through if any.
+pub(crate) fn report(parent: Option<Id>, suppressed: u64) {
+    warn!(
+        parent: parent,
+        suppressed,
+        "{} access records suppressed by max_logs_per_second",
+        suppressed
+    );
+}
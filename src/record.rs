@@ -0,0 +1,205 @@
+//! The access record as seen by the `on_log` hook.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use tide::StatusCode;
+use time::OffsetDateTime;
+use tracing::{Level, Span};
+
+use crate::{FieldNaming, Format, Slot, Slots};
+
+/// An access record about to be written, passed to
+/// [`TracingMiddlewareBuilder::on_log`](crate::TracingMiddlewareBuilder::on_log) and then
+/// to the [sinks](crate::LogSink).
+///
+/// The values are looked up by the keys of the [JSON output](crate::OutputFormat::Json),
+/// like `path` or `request_headers.user-agent`, whatever the output. The timing and the
+/// size, which are only rendered when the record is written, are available from
+/// [`duration`](Self::duration) and [`body_size`](Self::body_size).
+pub struct LogRecord<'a> {
+    template: &'a Format,
+    slots: &'a mut Slots,
+    fields: &'a mut Vec<(String, String)>,
+    naming: FieldNaming,
+    span: &'a Span,
+    message: Option<&'a str>,
+    directives: Option<&'a str>,
+    status: StatusCode,
+    duration: Duration,
+    body_size: usize,
+    level: Level,
+    time: OffsetDateTime,
+    #[cfg(feature = "dd")]
+    dd: Option<crate::dd::DatadogIds>,
+}
+
+impl<'a> LogRecord<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        template: &'a Format,
+        slots: &'a mut Slots,
+        fields: &'a mut Vec<(String, String)>,
+        naming: FieldNaming,
+        span: &'a Span,
+        status: StatusCode,
+        duration: Duration,
+        body_size: usize,
+        level: Level,
+        time: OffsetDateTime,
+    ) -> Self {
+        Self {
+            template,
+            slots,
+            fields,
+            naming,
+            span,
+            message: None,
+            directives: None,
+            status,
+            duration,
+            body_size,
+            level,
+            time,
+            #[cfg(feature = "dd")]
+            dd: None,
+        }
+    }
+
+    /// Set the rendered record, once the fields are final.
+    pub(crate) fn with_message(mut self, message: &'a str) -> Self {
+        self.message = Some(message);
+        self
+    }
+
+    /// Set the directive lines to write before the record.
+    pub(crate) fn with_directives(mut self, directives: Option<&'a str>) -> Self {
+        self.directives = directives;
+        self
+    }
+
+    /// Set the IDs the record is correlated with Datadog traces by.
+    #[cfg(feature = "dd")]
+    pub(crate) fn with_datadog(mut self, ids: Option<crate::dd::DatadogIds>) -> Self {
+        self.dd = ids;
+        self
+    }
+
+    /// Emit `line` as a tracing event at the level of the record in the span of the request,
+    /// with the Datadog correlation fields if enabled, like the records written without a
+    /// sink.
+    pub(crate) fn emit(&self, line: &str) {
+        #[cfg(feature = "dd")]
+        if let Some(dd) = self.dd {
+            return crate::dd::emit(self.level, self.span, dd, format_args!("{}", line));
+        }
+        crate::emit(self.level, self.span, format_args!("{}", line))
+    }
+
+    /// The record rendered like the configured [`OutputFormat`](crate::OutputFormat), as JSON
+    /// for [`Fields`](crate::OutputFormat::Fields). `None` in the `on_log` hook, since the
+    /// fields can still change.
+    pub fn message(&self) -> Option<&str> {
+        self.message
+    }
+
+    /// The directive lines to write before the [`message`](Self::message), each ending with a
+    /// newline, like the `#Version` and `#Fields` of [`W3c`](crate::OutputFormat::W3c) for
+    /// the first record. Sinks writing files should keep them to start new files with.
+    pub fn directives(&self) -> Option<&str> {
+        self.directives
+    }
+
+    /// The lines of the [directives](Self::directives) followed by the
+    /// [message](Self::message), for the sinks writing a line per message.
+    pub(crate) fn lines(&self) -> impl Iterator<Item = &str> {
+        let directives = self.directives.into_iter().flat_map(str::lines);
+        directives.chain(self.message)
+    }
+
+    /// The time the record is written, from the
+    /// [clock](crate::TracingMiddlewareBuilder::clock) of the middleware.
+    pub fn time(&self) -> OffsetDateTime {
+        self.time
+    }
+
+    /// The span of the request.
+    pub fn span(&self) -> &Span {
+        self.span
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The time taken to serve the request, like `%T`.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The size of the response body, like `%b`.
+    pub fn body_size(&self) -> usize {
+        self.body_size
+    }
+
+    /// The level the record is written at.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Write the record at `level` instead.
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    /// The value of the field `key`, `None` if it is missing or only rendered when the record
+    /// is written. Multi-valued headers are joined with `, `.
+    pub fn get(&self, key: &str) -> Option<Cow<'_, str>> {
+        if let Some(i) = self.position(key) {
+            if let Slot::Values(values) = self.slots.get(i) {
+                return Some(Cow::Owned(values.join(", ")));
+            }
+            return self
+                .slots
+                .str(i)
+                .filter(|value| *value != "-")
+                .map(Cow::Borrowed);
+        }
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| Cow::Borrowed(value.as_str()))
+    }
+
+    /// Set the field `key` to `value`, like `[REDACTED]`. The value of the format token with
+    /// this key is replaced, or else the field is added to the record like
+    /// [`with_field`](crate::TracingMiddlewareBuilder::with_field), except for
+    /// [`Fields`](crate::OutputFormat::Fields) output where the added fields are written
+    /// together in the `extra` field.
+    pub fn set<V: Into<String>>(&mut self, key: &str, value: V) {
+        let value = value.into();
+        if let Some(i) = self.position(key) {
+            self.slots.set(i, value);
+            return;
+        }
+        match self.fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, field)) => *field = value,
+            None => self.fields.push((key.to_owned(), value)),
+        }
+    }
+
+    /// The index of the format unit with the structured key `key`.
+    fn position(&self, key: &str) -> Option<usize> {
+        self.template.0.iter().position(|unit| {
+            unit.field_key(self.naming)
+                .is_some_and(|field| match field.split() {
+                    (Some(group), name) => key
+                        .strip_prefix(group)
+                        .and_then(|key| key.strip_prefix('.'))
+                        .is_some_and(|key| key == name),
+                    (None, name) => key == name,
+                })
+        })
+    }
+}
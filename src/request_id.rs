@@ -0,0 +1,79 @@
+//! Request IDs read from a request header or generated.
+
+use std::fmt::{self, Display, Formatter};
+
+use tide::http::headers::HeaderName;
+use tide::{Request, Response};
+use uuid::Uuid;
+
+/// The ID of a request, enabled with
+/// [`TracingMiddlewareBuilder::request_id`](crate::TracingMiddlewareBuilder::request_id).
+///
+/// It is read from the `X-Request-Id` request header, or generated as a UUID if the header is
+/// absent or invalid. The middleware stores it in the request extensions, so handlers can read
+/// it with `req.ext::<RequestId>()`, and the format can render it with `%{request_id}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The maximum length of a request ID accepted from a request header.
+    const MAX_LEN: usize = 128;
+
+    /// The request ID.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn from_request<State: Clone + Send + Sync + 'static>(
+        req: &Request<State>,
+        header: &HeaderName,
+    ) -> Self {
+        let id = req.header(header).map(|v| v.as_str()).filter(|id| {
+            !id.is_empty() && id.len() <= Self::MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+        });
+        match id {
+            Some(id) => RequestId(id.to_owned()),
+            None => RequestId(Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestIdConfig {
+    pub(crate) header: HeaderName,
+    pub(crate) echo: bool,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header: HeaderName::from("X-Request-Id"),
+            echo: false,
+        }
+    }
+}
+
+impl RequestIdConfig {
+    /// Read or generate the ID of the request and store it in the request extensions.
+    pub(crate) fn apply<State: Clone + Send + Sync + 'static>(
+        &self,
+        req: &mut Request<State>,
+    ) -> RequestId {
+        let id = RequestId::from_request(req, &self.header);
+        req.set_ext(id.clone());
+        id
+    }
+
+    /// Send the ID back in the response header, unless the handler set it already.
+    pub(crate) fn echo(&self, resp: &mut Response, id: &RequestId) {
+        if self.echo && resp.header(&self.header).is_none() {
+            resp.insert_header(&self.header, id.as_str());
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Configuration scoped to URL prefixes.
+
+use tracing::Level;
+
+/// Configuration for the requests under a URL prefix, overriding the configuration of the
+/// middleware, see
+/// [`TracingMiddlewareBuilder::prefix_config`](crate::TracingMiddlewareBuilder::prefix_config).
+///
+/// Settings that are not set fall back to the next shorter matching prefix, and then to the
+/// middleware.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixConfig {
+    level: Option<Level>,
+    sample_rate: Option<f64>,
+    exclude: Option<bool>,
+}
+
+impl PrefixConfig {
+    /// Create a configuration that overrides nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log access records at `level`, regardless of the response status.
+    pub fn level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Log only a random fraction `rate` of the successful requests, see
+    /// [`TracingMiddlewareBuilder::sample_rate`](crate::TracingMiddlewareBuilder::sample_rate).
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = Some(rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Ignore and do not log the requests, or log them even though a shorter prefix excludes
+    /// them.
+    pub fn exclude(mut self, exclude: bool) -> Self {
+        self.exclude = Some(exclude);
+        self
+    }
+
+    pub(crate) fn get_level(&self) -> Option<Level> {
+        self.level
+    }
+
+    pub(crate) fn get_sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    pub(crate) fn is_excluded(&self) -> bool {
+        self.exclude == Some(true)
+    }
+
+    /// Fill the settings that are not set from `other`.
+    fn or(self, other: &PrefixConfig) -> Self {
+        Self {
+            level: self.level.or(other.level),
+            sample_rate: self.sample_rate.or(other.sample_rate),
+            exclude: self.exclude.or(other.exclude),
+        }
+    }
+}
+
+/// The prefix configurations of a middleware, longest prefix first.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scopes(Vec<(String, PrefixConfig)>);
+
+impl Scopes {
+    pub(crate) fn insert(&mut self, prefix: &str, config: PrefixConfig) {
+        let prefix = prefix
+            .trim_end_matches('*')
+            .trim_end_matches('/')
+            .to_owned();
+        self.0.retain(|(p, _)| *p != prefix);
+        self.0.push((prefix, config));
+        self.0
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    /// Whether any prefix samples its records.
+    pub(crate) fn samples(&self) -> bool {
+        self.0
+            .iter()
+            .any(|(_, config)| config.sample_rate.is_some_and(|rate| rate < 1.0))
+    }
+
+    /// The configuration for `path`, merged from all matching prefixes.
+    pub(crate) fn resolve(&self, path: &str) -> PrefixConfig {
+        self.0
+            .iter()
+            .filter(|(prefix, _)| matches(prefix, path))
+            .fold(PrefixConfig::default(), |config, (_, scoped)| {
+                config.or(scoped)
+            })
+    }
+}
+
+/// Whether `path` is `prefix` or below it, `/api` matches `/api/users` but not `/apis`.
+fn matches(prefix: &str, path: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
@@ -0,0 +1,50 @@
+//! Scrubbing of secrets from query strings.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::pattern::PatternSet;
+
+/// The value logged in place of scrubbed query parameters.
+const SCRUBBED: &str = "***";
+
+/// The query parameters whose values are not logged.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QueryScrubber {
+    pub(crate) names: HashSet<String>,
+    pub(crate) regex: PatternSet,
+}
+
+impl QueryScrubber {
+    /// `query` with the values of the scrubbed parameters replaced by `***`. Parameter names
+    /// are matched after percent-decoding.
+    pub(crate) fn scrub<'a>(&self, query: &'a str) -> Cow<'a, str> {
+        if self.names.is_empty() && self.regex.is_empty() {
+            return Cow::Borrowed(query);
+        }
+        let mut scrubbed = false;
+        let pairs: Vec<Cow<'_, str>> = query
+            .split('&')
+            .map(|pair| {
+                let (raw_name, decoded_name) = match pair.split_once('=') {
+                    Some((name, _)) => match form_urlencoded::parse(pair.as_bytes()).next() {
+                        Some((decoded, _)) => (name, decoded),
+                        None => return Cow::Borrowed(pair),
+                    },
+                    None => return Cow::Borrowed(pair),
+                };
+                if self.names.contains(&*decoded_name) || self.regex.is_match(&decoded_name) {
+                    scrubbed = true;
+                    Cow::Owned(format!("{}={}", raw_name, SCRUBBED))
+                } else {
+                    Cow::Borrowed(pair)
+                }
+            })
+            .collect();
+        if scrubbed {
+            Cow::Owned(pairs.join("&"))
+        } else {
+            Cow::Borrowed(query)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Sentry transactions, breadcrumbs and error events.
+
+use std::sync::Arc;
+
+use sentry_core::protocol::{self, Breadcrumb, Event, SpanStatus, Value};
+use sentry_core::{Hub, Transaction, TransactionContext};
+use tide::{Request, StatusCode};
+
+/// The Sentry state of a request, from the start of `handle` until the response body is
+/// finished.
+pub(crate) struct SentryRequest {
+    hub: Arc<Hub>,
+    transaction: Transaction,
+    name: String,
+    request: protocol::Request,
+}
+
+impl SentryRequest {
+    /// Start a transaction for the request, continuing the caller's trace from the
+    /// `sentry-trace` header.
+    pub(crate) fn start<State: Clone + Send + Sync + 'static>(req: &Request<State>) -> Self {
+        let hub = Hub::current();
+        let name = format!("{} {}", req.method(), req.url().path());
+        let headers = req
+            .iter()
+            .map(|(name, values)| (name.as_str(), values.last().as_str()));
+        let ctx = TransactionContext::continue_from_headers(&name, "http.server", headers);
+        let transaction = hub.start_transaction(ctx);
+
+        // the query and headers are left out, they may contain credentials
+        let mut url = req.url().clone();
+        url.set_query(None);
+        let request = protocol::Request {
+            url: Some(url),
+            method: Some(req.method().to_string()),
+            ..Default::default()
+        };
+        transaction.set_request(request.clone());
+
+        Self {
+            hub,
+            transaction,
+            name,
+            request,
+        }
+    }
+
+    /// Record the access `line` as a breadcrumb, finish the transaction and capture an error
+    /// event for 5xx responses.
+    pub(crate) fn finish(self, line: String, status: StatusCode, duration: f64) {
+        let mut data = protocol::Map::new();
+        if let Some(method) = &self.request.method {
+            data.insert("method".to_owned(), Value::from(method.as_str()));
+        }
+        if let Some(url) = &self.request.url {
+            data.insert("url".to_owned(), Value::from(url.as_str()));
+        }
+        data.insert("status_code".to_owned(), Value::from(status as u16));
+        data.insert("duration".to_owned(), Value::from(duration));
+        self.hub.add_breadcrumb(Breadcrumb {
+            ty: "http".to_owned(),
+            category: Some("http".to_owned()),
+            level: breadcrumb_level(status),
+            message: Some(line.clone()),
+            data,
+            ..Default::default()
+        });
+
+        self.transaction
+            .set_data("http.response.status_code", Value::from(status as u16));
+        self.transaction.set_data("duration", Value::from(duration));
+        self.transaction.set_status(span_status(status));
+
+        if status.is_server_error() {
+            let mut event = Event {
+                level: protocol::Level::Error,
+                message: Some(line),
+                transaction: Some(self.name),
+                request: Some(self.request),
+                ..Default::default()
+            };
+            event
+                .tags
+                .insert("status_code".to_owned(), (status as u16).to_string());
+            event.contexts.insert(
+                "trace".to_owned(),
+                self.transaction.get_trace_context().into(),
+            );
+            self.hub.capture_event(event);
+        }
+
+        self.transaction.finish();
+    }
+}
+
+fn breadcrumb_level(status: StatusCode) -> protocol::Level {
+    if status.is_server_error() {
+        protocol::Level::Error
+    } else if status.is_client_error() {
+        protocol::Level::Warning
+    } else {
+        protocol::Level::Info
+    }
+}
+
+/// The span status for a response status, as mapped by the Sentry SDKs.
+fn span_status(status: StatusCode) -> SpanStatus {
+    match status {
+        StatusCode::Unauthorized => SpanStatus::Unauthenticated,
+        StatusCode::Forbidden => SpanStatus::PermissionDenied,
+        StatusCode::NotFound => SpanStatus::NotFound,
+        StatusCode::Conflict => SpanStatus::AlreadyExists,
+        StatusCode::TooManyRequests => SpanStatus::ResourceExhausted,
+        status if status.is_client_error() => SpanStatus::InvalidArgument,
+        StatusCode::NotImplemented => SpanStatus::Unimplemented,
+        StatusCode::ServiceUnavailable => SpanStatus::Unavailable,
+        StatusCode::GatewayTimeout => SpanStatus::DeadlineExceeded,
+        status if status.is_server_error() => SpanStatus::InternalError,
+        _ => SpanStatus::Ok,
+    }
+}
@@ -0,0 +1,295 @@
+//! Destinations of the access records other than tracing events.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::LogRecord;
+
+/// A destination of access records, see
+/// [`TracingMiddlewareBuilder::sink`](crate::TracingMiddlewareBuilder::sink).
+///
+/// A sink that forwards failed requests to an audit service:
+///
+/// ```
+/// use std::sync::mpsc::Sender;
+/// use std::sync::Mutex;
+///
+/// use tide_tracing_middleware::{LogRecord, LogSink};
+///
+/// struct AuditSink {
+///     queue: Mutex<Sender<String>>,
+/// }
+///
+/// impl LogSink for AuditSink {
+///     fn emit(&self, record: &LogRecord<'_>) {
+///         if record.status().is_success() {
+///             return;
+///         }
+///         if let Some(message) = record.message() {
+///             let _ = self.queue.lock().unwrap().send(message.to_owned());
+///         }
+///     }
+/// }
+/// ```
+///
+/// `emit` is called when the response body is finished, on the task serving the request,
+/// so slow destinations should queue the records and send them from another task.
+pub trait LogSink: Send + Sync {
+    /// Write `record`.
+    fn emit(&self, record: &LogRecord<'_>);
+}
+
+/// The default destination, emitting the records as tracing events in the span of the
+/// request, like the records written without a sink, directives included.
+///
+/// Records written with [`OutputFormat::Fields`](crate::OutputFormat::Fields) are emitted
+/// as JSON by this sink.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingSink;
+
+impl LogSink for TracingSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        for line in record.lines() {
+            record.emit(line);
+        }
+    }
+}
+
+/// How often a [`FileSink`] starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// A file per hour, the rotated files are named like `access.log.2024-01-31-13`.
+    Hourly,
+    /// A file per day, the rotated files are named like `access.log.2024-01-31`, the default.
+    Daily,
+    /// A single file that is never rotated.
+    Never,
+}
+
+impl Rotation {
+    /// The period `time` falls in, the suffix of the file rotated at its end.
+    fn period(self, time: OffsetDateTime) -> String {
+        match self {
+            Rotation::Hourly => time.format("%Y-%m-%d-%H"),
+            Rotation::Daily => time.format("%Y-%m-%d"),
+            Rotation::Never => String::new(),
+        }
+    }
+
+    /// Whether `s` is a period of this rotation, like `2024-01-31` for daily files.
+    fn is_period(self, s: &str) -> bool {
+        let pattern = match self {
+            Rotation::Hourly => "0000-00-00-00",
+            Rotation::Daily => "0000-00-00",
+            Rotation::Never => return false,
+        };
+        s.len() == pattern.len()
+            && s.bytes().zip(pattern.bytes()).all(|(b, p)| match p {
+                b'0' => b.is_ascii_digit(),
+                _ => b == p,
+            })
+    }
+}
+
+/// A sink appending the access records, one per line, to a file that is rotated daily or
+/// hourly, for deployments that tail flat access logs.
+///
+/// The records are written to the file at `path`, which is renamed with the UTC date of its
+/// records as suffix, like `access.log.2024-01-31`, when the first record of the next period
+/// is written. The periods follow the [time](LogRecord::time) of the records, from the
+/// [clock](crate::TracingMiddlewareBuilder::clock) of the middleware. [`max_files`](Self::max_files) limits the number of rotated files kept.
+///
+/// ```no_run
+/// use tide_tracing_middleware::{FileSink, Rotation, TracingMiddlewareBuilder};
+///
+/// let sink = FileSink::new("/var/log/app/access.log")
+///     .expect("open the access log")
+///     .rotation(Rotation::Hourly)
+///     .max_files(48);
+/// let builder = TracingMiddlewareBuilder::<()>::new(r#"%h - %u %{clf}t "%r" %s %{clf}b"#).sink(sink);
+/// ```
+///
+/// The [directives](LogRecord::directives) of a record, like the `#Fields` of
+/// [`OutputFormat::W3c`](crate::OutputFormat::W3c), are written before it and repeated at the
+/// top of every new file.
+///
+/// Records are written with blocking file I/O when the response body is finished, which is
+/// fast on local disks but stalls the task on slow network filesystems.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    rotation: Rotation,
+    max_files: Option<usize>,
+    state: Mutex<FileState>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    file: File,
+    /// The period of the records in the file, `None` while it is empty.
+    period: Option<String>,
+    directives: String,
+}
+
+impl FileSink {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = open(&path)?;
+        let rotation = Rotation::Daily;
+        let period = left_period(&file, rotation)?;
+        Ok(Self {
+            path,
+            rotation,
+            max_files: None,
+            state: Mutex::new(FileState {
+                file,
+                period,
+                directives: String::new(),
+            }),
+        })
+    }
+
+    /// Set how often a new file is started, daily by default.
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        let state = self.state.get_mut().unwrap_or_else(|err| err.into_inner());
+        if let Ok(period) = left_period(&state.file, rotation) {
+            state.period = period;
+        }
+        self
+    }
+
+    /// Only keep the `n` most recent rotated files, older ones are deleted on rotation. All
+    /// of them are kept by default. Other files starting with the name of the file, like
+    /// `access.log.bak` or `access.log.2024-01-31.gz`, are left alone.
+    pub fn max_files(mut self, n: usize) -> Self {
+        self.max_files = Some(n);
+        self
+    }
+
+    /// Write `line` at `time`, after the `directives`.
+    fn write(&self, directives: Option<&str>, line: &str, time: OffsetDateTime) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(directives) = directives {
+            state.directives = directives.to_owned();
+        }
+        let period = self.rotation.period(time);
+        let rotated = state
+            .period
+            .replace(period.clone())
+            .filter(|rotated| *rotated != period);
+        let new_file = rotated.is_some();
+        if let Some(rotated) = rotated {
+            self.rotate(&mut state, &rotated)?;
+        }
+        let mut record = String::with_capacity(state.directives.len() + line.len() + 1);
+        if new_file || directives.is_some() {
+            record.push_str(&state.directives);
+        }
+        record.push_str(line);
+        record.push('\n');
+        state.file.write_all(record.as_bytes())
+    }
+
+    /// Rename the file with the suffix `period`, start a new one and delete the rotated files
+    /// beyond the retention.
+    fn rotate(&self, state: &mut FileState, period: &str) -> io::Result<()> {
+        state.file.flush()?;
+        let mut rotated = self.rotated_path(period, None);
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = self.rotated_path(period, Some(n));
+            n += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+        state.file = open(&self.path)?;
+        if let Some(max_files) = self.max_files {
+            self.prune(max_files)?;
+        }
+        Ok(())
+    }
+
+    fn rotated_path(&self, period: &str, n: Option<usize>) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(".");
+        name.push(period);
+        if let Some(n) = n {
+            name.push(format!(".{}", n));
+        }
+        self.path.with_file_name(name)
+    }
+
+    /// The period and the counter of the file named `name` if it was rotated by this sink,
+    /// like `("2024-01-31", 1)` for `access.log.2024-01-31.1`. Other files next to it, like
+    /// `access.log.bak` or compressed ones, are not.
+    fn rotated_suffix(&self, name: &str) -> Option<(String, usize)> {
+        let file_name = self.path.file_name()?.to_str()?;
+        let suffix = name.strip_prefix(file_name)?.strip_prefix('.')?;
+        let (period, n) = match suffix.split_once('.') {
+            Some((period, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                (period, n.parse().ok()?)
+            }
+            Some(_) => return None,
+            None => (suffix, 0),
+        };
+        self.rotation
+            .is_period(period)
+            .then(|| (period.to_owned(), n))
+    }
+
+    /// Delete the oldest rotated files, keeping `max_files`.
+    fn prune(&self, max_files: usize) -> io::Result<()> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut rotated = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let suffix = self.rotated_suffix(entry.file_name().to_str()?)?;
+                Some((suffix, entry.path()))
+            })
+            .collect::<Vec<_>>();
+        // the periods sort chronologically
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(max_files);
+        for (_, path) in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl LogSink for FileSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        if let Some(message) = record.message() {
+            if let Err(err) = self.write(record.directives(), message, record.time()) {
+                error!(
+                    "failed to write access record to {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The period of the records a previous run left in `file`, the one it was last written in,
+/// `None` if it is empty.
+fn left_period(file: &File, rotation: Rotation) -> io::Result<Option<String>> {
+    let meta = file.metadata()?;
+    if meta.len() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(rotation.period(meta.modified()?.into())))
+}
@@ -0,0 +1,122 @@
+//! Counters of what the middleware did with the access records.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A handle to the counters of the access records of a middleware, returned by
+/// [`TracingMiddleware::stats`](crate::TracingMiddleware::stats), to tell whether missing
+/// records were dropped by the middleware or further down the pipeline.
+///
+/// The handle is cheap to clone, all clones share the same counters:
+///
+/// ```rust
+/// use tide_tracing_middleware::TracingMiddlewareBuilder;
+///
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::default()
+///     .sample_rate(0.1)
+///     .build();
+/// let stats = tracing_middleware.stats();
+/// let mut app = tide::new();
+/// app.with(tracing_middleware);
+/// // later, e.g. in an admin endpoint
+/// println!("{} written, {} sampled out", stats.emitted(), stats.sampled_out());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LogStats(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    emitted: AtomicU64,
+    excluded: AtomicU64,
+    filtered: AtomicU64,
+    sampled_out: AtomicU64,
+    rate_limited: AtomicU64,
+    disabled: AtomicU64,
+    render_errors: AtomicU64,
+    body_errors: AtomicU64,
+}
+
+/// Why an access record was not written.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Dropped {
+    Excluded,
+    Filtered,
+    Sampled,
+    RateLimited,
+    Disabled,
+}
+
+impl LogStats {
+    /// The records handed to the subscriber or the [sinks](crate::LogSink). Whether the
+    /// subscriber writes them out is up to it.
+    pub fn emitted(&self) -> u64 {
+        self.0.emitted.load(Ordering::Relaxed)
+    }
+
+    /// The requests not logged because their path, the request or the response status is
+    /// excluded, like with [`exclude`](crate::TracingMiddlewareBuilder::exclude) or
+    /// [`exclude_status`](crate::TracingMiddlewareBuilder::exclude_status).
+    pub fn excluded(&self) -> u64 {
+        self.0.excluded.load(Ordering::Relaxed)
+    }
+
+    /// The records dropped by
+    /// [`only_slow_requests`](crate::TracingMiddlewareBuilder::only_slow_requests),
+    /// [`only_errors`](crate::TracingMiddlewareBuilder::only_errors) or
+    /// [`on_log`](crate::TracingMiddlewareBuilder::on_log).
+    pub fn filtered(&self) -> u64 {
+        self.0.filtered.load(Ordering::Relaxed)
+    }
+
+    /// The records dropped by [`sample_rate`](crate::TracingMiddlewareBuilder::sample_rate).
+    pub fn sampled_out(&self) -> u64 {
+        self.0.sampled_out.load(Ordering::Relaxed)
+    }
+
+    /// The records dropped by
+    /// [`max_logs_per_second`](crate::TracingMiddlewareBuilder::max_logs_per_second).
+    pub fn rate_limited(&self) -> u64 {
+        self.0.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// The records not rendered because the subscriber does not record their level.
+    pub fn disabled(&self) -> u64 {
+        self.0.disabled.load(Ordering::Relaxed)
+    }
+
+    /// The values whose `Display` implementation failed, logged as `-`, and the records that
+    /// failed to render, written as far as they got.
+    pub fn render_errors(&self) -> u64 {
+        self.0.render_errors.load(Ordering::Relaxed)
+    }
+
+    /// The response bodies that failed while they were sent.
+    pub fn body_errors(&self) -> u64 {
+        self.0.body_errors.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_emitted(&self) {
+        self.0.emitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self, reason: Dropped) {
+        let counter = match reason {
+            Dropped::Excluded => &self.0.excluded,
+            Dropped::Filtered => &self.0.filtered,
+            Dropped::Sampled => &self.0.sampled_out,
+            Dropped::RateLimited => &self.0.rate_limited,
+            Dropped::Disabled => &self.0.disabled,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_render_errors(&self, n: u64) {
+        if n > 0 {
+            self.0.render_errors.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_body_error(&self) {
+        self.0.body_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,103 @@
+//! A StatsD sink for the request metrics.
+
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Sends a count and a timing per request to a StatsD server over UDP, enabled with
+/// [`TracingMiddlewareBuilder::statsd`](crate::TracingMiddlewareBuilder::statsd).
+///
+/// Each request sends `http.requests` as a counter and `http.request.duration` as a timing
+/// in milliseconds, in a single datagram. With [`dogstatsd`](Self::dogstatsd) they are
+/// tagged with the method, status, route and the static tags. Sending never blocks, failed
+/// sends are dropped.
+///
+/// ```rust,no_run
+/// use tide_tracing_middleware::{StatsdSink, TracingMiddlewareBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let sink = StatsdSink::new("127.0.0.1:8125")?
+///     .prefix("myapp")
+///     .dogstatsd()
+///     .tag("env", "prod");
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::default().statsd(sink).build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+    tags: String,
+}
+
+impl StatsdSink {
+    /// Create a sink sending to the StatsD server at `addr`.
+    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: String::new(),
+            dogstatsd: false,
+            tags: String::new(),
+        })
+    }
+
+    /// Prefix the metric names with `prefix` and a `.`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_end_matches('.').to_owned();
+        if !self.prefix.is_empty() {
+            self.prefix.push('.');
+        }
+        self
+    }
+
+    /// Tag the metrics in the DogStatsD format `|#method:GET,status:200,route:/users/:id`,
+    /// which plain StatsD servers do not understand. The route tag is left out if the route
+    /// is not set with [`route`](crate::TracingMiddlewareBuilder::route) or
+    /// [`metrics_route`](crate::TracingMiddlewareBuilder::metrics_route).
+    pub fn dogstatsd(mut self) -> Self {
+        self.dogstatsd = true;
+        self
+    }
+
+    /// Add a static tag to every metric.
+    ///
+    /// Enables [`dogstatsd`](Self::dogstatsd).
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.dogstatsd = true;
+        let _ = write!(self.tags, ",{}:{}", key, value);
+        self
+    }
+
+    /// Send the metrics of a finished request.
+    pub(crate) fn record(&self, method: &str, status: u16, route: &str, duration: f64) {
+        let mut tags = String::new();
+        if self.dogstatsd {
+            let _ = write!(tags, "|#method:{},status:{}", method, status);
+            if !route.is_empty() {
+                let _ = write!(tags, ",route:{}", route);
+            }
+            tags.push_str(&self.tags);
+        }
+        let packet = format!(
+            "{prefix}http.requests:1|c{tags}\n{prefix}http.request.duration:{ms:.3}|ms{tags}",
+            prefix = self.prefix,
+            tags = tags,
+            ms = duration * 1000.0,
+        );
+        let _ = self.socket.send(packet.as_bytes());
+    }
+}
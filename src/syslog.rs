@@ -0,0 +1,260 @@
+//! A syslog sink for the access records.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::Mutex;
+
+use time::OffsetDateTime;
+use tracing::{error, Level};
+
+use crate::{LogRecord, LogSink};
+
+/// The syslog facility of the access records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facility {
+    /// Generic user-level messages, the default.
+    User,
+    /// System daemons.
+    Daemon,
+    /// Local use 0.
+    Local0,
+    /// Local use 1.
+    Local1,
+    /// Local use 2.
+    Local2,
+    /// Local use 3.
+    Local3,
+    /// Local use 4.
+    Local4,
+    /// Local use 5.
+    Local5,
+    /// Local use 6.
+    Local6,
+    /// Local use 7.
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// The syslog message format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFormat {
+    /// The BSD format of RFC 3164, `<134>Jan 31 13:05:09 host app[42]: ...`, the default.
+    Rfc3164,
+    /// The format of RFC 5424, `<134>1 2024-01-31T13:05:09.123Z host app 42 access - ...`.
+    Rfc5424,
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    Tcp {
+        addr: SocketAddr,
+        stream: Mutex<Option<TcpStream>>,
+    },
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// A sink sending the access records to a syslog server, over UDP, TCP or a local unix
+/// socket, enabled with the `syslog` feature.
+///
+/// The severity of a message follows the level of the record, `ERROR` is sent as `err`,
+/// `WARN` as `warning`, `INFO` as `info` and lower levels as `debug`.
+///
+/// ```rust,no_run
+/// use tide_tracing_middleware::{Facility, SyslogFormat, SyslogSink, TracingMiddlewareBuilder};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let sink = SyslogSink::udp("127.0.0.1:514")?
+///     .facility(Facility::Local0)
+///     .app_name("myapp")
+///     .format(SyslogFormat::Rfc5424);
+/// let tracing_middleware = TracingMiddlewareBuilder::<()>::default().sink(sink).build();
+/// # Ok(())
+/// # }
+/// ```
+///
+/// UDP and unix socket sends never block, records that do not fit in the socket buffer are
+/// dropped. TCP messages are framed by a trailing newline for RFC 3164 and by octet counting
+/// for RFC 5424 (RFC 6587), and written with blocking I/O; the connection is reopened on the
+/// next record after an error.
+#[derive(Debug)]
+pub struct SyslogSink {
+    transport: Transport,
+    facility: Facility,
+    format: SyslogFormat,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl SyslogSink {
+    /// Send to the syslog server at `addr` over UDP.
+    pub fn udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = resolve(addr)?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_transport(Transport::Udp(socket)))
+    }
+
+    /// Send to the syslog server at `addr` over TCP.
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = resolve(addr)?;
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self::with_transport(Transport::Tcp {
+            addr,
+            stream: Mutex::new(Some(stream)),
+        }))
+    }
+
+    /// Send to the local syslog daemon at the unix datagram socket `path`, usually
+    /// `/dev/log`.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::with_transport(Transport::Unix(socket)))
+    }
+
+    fn with_transport(transport: Transport) -> Self {
+        let app_name = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "tide".to_owned());
+        Self {
+            transport,
+            facility: Facility::User,
+            format: SyslogFormat::Rfc3164,
+            hostname: crate::hostname().to_owned(),
+            app_name,
+            pid: std::process::id(),
+        }
+    }
+
+    /// Set the facility of the messages, `user` by default.
+    pub fn facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Set the message format, RFC 3164 by default.
+    pub fn format(mut self, format: SyslogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the app name (the tag of RFC 3164), the name of the executable by default.
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = app_name.to_owned();
+        self
+    }
+
+    /// Set the host name, the name of this host by default.
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.hostname = hostname.to_owned();
+        self
+    }
+
+    /// The syslog message of `message` at `level`.
+    fn message(&self, level: Level, message: &str) -> String {
+        let pri = self.facility.code() * 8 + crate::syslog_severity(level);
+        let now = OffsetDateTime::now_utc();
+        let mut packet = String::with_capacity(message.len() + 64);
+        let _ = match self.format {
+            SyslogFormat::Rfc3164 => write!(
+                packet,
+                "<{}>{} {} {}[{}]: {}",
+                pri,
+                now.format("%b %_d %H:%M:%S"),
+                self.hostname,
+                self.app_name,
+                self.pid,
+                message
+            ),
+            SyslogFormat::Rfc5424 => write!(
+                packet,
+                "<{}>1 {}.{:03}Z {} {} {} access - {}",
+                pri,
+                now.format("%Y-%m-%dT%H:%M:%S"),
+                now.millisecond(),
+                self.hostname,
+                self.app_name,
+                self.pid,
+                message
+            ),
+        };
+        packet
+    }
+
+    fn send(&self, packet: &str) -> io::Result<()> {
+        match &self.transport {
+            Transport::Udp(socket) => socket.send(packet.as_bytes()).map(drop),
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(packet.as_bytes()).map(drop),
+            Transport::Tcp { addr, stream } => {
+                let framed = match self.format {
+                    SyslogFormat::Rfc3164 => format!("{}\n", packet),
+                    SyslogFormat::Rfc5424 => format!("{} {}", packet.len(), packet),
+                };
+                let mut stream = stream.lock().unwrap_or_else(|err| err.into_inner());
+                if stream.is_none() {
+                    *stream = Some(TcpStream::connect(addr)?);
+                }
+                let result = stream
+                    .as_mut()
+                    .map_or(Ok(()), |conn| conn.write_all(framed.as_bytes()));
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+impl LogSink for SyslogSink {
+    fn emit(&self, record: &LogRecord<'_>) {
+        for line in record.lines() {
+            let packet = self.message(record.level(), line);
+            if let Err(err) = self.send(&packet) {
+                if err.kind() != io::ErrorKind::WouldBlock {
+                    error!("failed to send access record to syslog: {}", err);
+                }
+            }
+        }
+    }
+}
+
+fn resolve(addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to send to"))
+}
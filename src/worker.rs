@@ -0,0 +1,81 @@
+//! The background threads of the sinks sending the access records over the network.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the records dropped because a queue is full are reported.
+const OVERFLOW_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long dropping a sink waits for the records left to be sent.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A background thread sending the records of its queue.
+#[derive(Debug)]
+pub(crate) struct Worker<T> {
+    queue: SyncSender<T>,
+    thread: JoinHandle<()>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    /// Start the thread `name`, running `run` on a queue of `size` records.
+    pub(crate) fn spawn(
+        name: &str,
+        size: usize,
+        run: impl FnOnce(Receiver<T>) + Send + 'static,
+    ) -> Self {
+        let (queue, receiver) = mpsc::sync_channel(size);
+        let thread = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || run(receiver))
+            .unwrap_or_else(|err| panic!("failed to spawn the {} thread: {}", name, err));
+        Self { queue, thread }
+    }
+
+    /// Queue `record` without waiting.
+    pub(crate) fn send(&self, record: T) -> Result<(), TrySendError<T>> {
+        self.queue.try_send(record)
+    }
+
+    /// Close the queue, so the thread sends the records left and exits, and wait up to
+    /// [`SHUTDOWN_TIMEOUT`] for it. Returns `false` if it is still running.
+    pub(crate) fn shutdown(self) -> bool {
+        drop(self.queue);
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !self.thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if self.thread.is_finished() {
+            let _ = self.thread.join();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The records dropped because a queue is full since they were last reported.
+#[derive(Debug, Default)]
+pub(crate) struct Overflow {
+    dropped: AtomicU64,
+    last_report: Mutex<Option<Instant>>,
+}
+
+impl Overflow {
+    /// Count a dropped record at `now`, and return the number of records dropped since the
+    /// last report if the next one is due. Records racing with a report are counted in the
+    /// next one.
+    pub(crate) fn record(&self, now: Instant) -> Option<u64> {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        let mut last_report = self.last_report.try_lock().ok()?;
+        if last_report
+            .is_some_and(|at| now.saturating_duration_since(at) < OVERFLOW_REPORT_INTERVAL)
+        {
+            return None;
+        }
+        *last_report = Some(now);
+        Some(self.dropped.swap(0, Ordering::Relaxed))
+    }
+}
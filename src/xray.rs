@@ -0,0 +1,158 @@
+//! The AWS X-Ray trace header `X-Amzn-Trace-Id`.
+
+use std::fmt::{self, Display, Formatter};
+
+use tide::Request;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub(crate) const HEADER: &str = "X-Amzn-Trace-Id";
+
+/// The AWS X-Ray trace header `X-Amzn-Trace-Id`, enabled with
+/// [`TracingMiddlewareBuilder::xray`](crate::TracingMiddlewareBuilder::xray).
+///
+/// The middleware stores it in the request extensions, so handlers can read it with
+/// `req.ext::<XRayTraceHeader>()` and pass it on to downstream calls, and the format can render
+/// its segments with `%{xray_root}` and `%{xray_self}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XRayTraceHeader {
+    root: String,
+    parent: Option<String>,
+    sampled: Option<bool>,
+    self_id: Option<String>,
+}
+
+impl XRayTraceHeader {
+    /// Parse a header value like
+    /// `Self=1-67891234-12456789abcdef0123456789;Root=1-67891233-abcdef012345678912345678;Sampled=1`.
+    ///
+    /// Returns `None` if the value has no valid `Root`. Unknown fields are ignored.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut root = None;
+        let mut parent = None;
+        let mut sampled = None;
+        let mut self_id = None;
+        for field in value.split(';') {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            match key {
+                "Root" if is_trace_id(value) => root = Some(value.to_owned()),
+                "Parent" if value.len() == 16 && is_hex(value) => parent = Some(value.to_owned()),
+                "Sampled" => {
+                    sampled = match value {
+                        "1" => Some(true),
+                        "0" => Some(false),
+                        _ => None,
+                    }
+                }
+                "Self" if is_trace_id(value) => self_id = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        Some(Self {
+            root: root?,
+            parent,
+            sampled,
+            self_id,
+        })
+    }
+
+    /// Generate a header with a new `Root` trace ID.
+    pub fn generate() -> Self {
+        let epoch = OffsetDateTime::now_utc().unix_timestamp() as u32;
+        let random = Uuid::new_v4().to_simple().to_string();
+        Self {
+            root: format!("1-{:08x}-{}", epoch, &random[..24]),
+            parent: None,
+            sampled: None,
+            self_id: None,
+        }
+    }
+
+    /// The trace ID of the `Root` segment, like `1-67891233-abcdef012345678912345678`.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// The ID of the caller's segment, 16 hex digits.
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    /// The sampling decision of the caller, `None` if it is left to this service.
+    pub fn sampled(&self) -> Option<bool> {
+        self.sampled
+    }
+
+    /// The `Self` segment added by an Application Load Balancer.
+    pub fn self_id(&self) -> Option<&str> {
+        self.self_id.as_deref()
+    }
+
+    /// The 128 bit trace ID of the `Root` segment, the epoch followed by the unique part.
+    pub fn trace_id(&self) -> [u8; 16] {
+        let hex = self.root[2..].replace('-', "");
+        let mut id = [0; 16];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap_or_default();
+        }
+        id
+    }
+
+    /// Read the header from a request.
+    pub(crate) fn from_request<State: Clone + Send + Sync + 'static>(
+        req: &Request<State>,
+    ) -> Option<Self> {
+        req.header(HEADER).and_then(|v| Self::parse(v.as_str()))
+    }
+}
+
+impl Display for XRayTraceHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(self_id) = &self.self_id {
+            write!(f, "Self={};", self_id)?;
+        }
+        write!(f, "Root={}", self.root)?;
+        if let Some(parent) = &self.parent {
+            write!(f, ";Parent={}", parent)?;
+        }
+        if let Some(sampled) = self.sampled {
+            write!(f, ";Sampled={}", sampled as u8)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct XRayConfig {
+    pub(crate) generate: bool,
+}
+
+impl XRayConfig {
+    /// Read the trace header and store it in the request extensions, generating one if
+    /// configured to. A generated header is also added to the request headers.
+    pub(crate) fn apply<State: Clone + Send + Sync + 'static>(&self, req: &mut Request<State>) {
+        let header = match XRayTraceHeader::from_request(req) {
+            Some(header) => header,
+            None if self.generate => {
+                let header = XRayTraceHeader::generate();
+                req.insert_header(HEADER, header.to_string());
+                header
+            }
+            None => return,
+        };
+        req.set_ext(header);
+    }
+}
+
+/// `1-` followed by 8 hex digits of epoch, `-` and 24 hex digits.
+fn is_trace_id(s: &str) -> bool {
+    let b = s.as_bytes();
+    s.len() == 35 && s.starts_with("1-") && b[10] == b'-' && is_hex(&s[2..10]) && is_hex(&s[11..])
+}
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
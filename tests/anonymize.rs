@@ -0,0 +1,61 @@
+mod common;
+
+use common::{record, request};
+use tide::http::Method;
+use tide_tracing_middleware::{IpAnonymization, TracingMiddlewareBuilder};
+
+fn from(peer: &str) -> tide::http::Request {
+    let mut req = request(Method::Get, "/");
+    req.set_peer_addr(Some(peer));
+    req
+}
+
+#[test]
+fn last_parts_are_zeroed_by_default() {
+    let builder = || {
+        TracingMiddlewareBuilder::new("%a %h").anonymize_ip(IpAnonymization::TruncateV4Octets(1))
+    };
+    assert_eq!(
+        record(builder(), from("192.0.2.17:5678")),
+        "192.0.2.0:5678 192.0.2.0"
+    );
+    assert_eq!(
+        record(builder(), from("[2001:db8:1:2::17]:443")),
+        "[2001:db8:1::]:443 2001:db8:1::"
+    );
+}
+
+#[test]
+fn the_truncation_is_configurable_per_family() {
+    let builder = || {
+        TracingMiddlewareBuilder::new("%h")
+            .anonymize_ip(IpAnonymization::TruncateV4Octets(2))
+            .anonymize_ip(IpAnonymization::TruncateV6Bits(64))
+    };
+    assert_eq!(record(builder(), from("192.0.2.17:1")), "192.0.0.0");
+    assert_eq!(
+        record(builder(), from("[2001:db8:1:2::17]:1")),
+        "2001:db8:1:2::"
+    );
+}
+
+#[test]
+fn truncations_are_capped() {
+    let builder = || {
+        TracingMiddlewareBuilder::new("%h")
+            .anonymize_ip(IpAnonymization::TruncateV4Octets(9))
+            .anonymize_ip(IpAnonymization::TruncateV6Bits(200))
+    };
+    assert_eq!(record(builder(), from("192.0.2.17:1")), "0.0.0.0");
+    assert_eq!(record(builder(), from("[2001:db8::17]:1")), "::");
+}
+
+#[test]
+fn forwarded_clients_are_anonymized() {
+    let mut req = from("10.0.0.1:1234");
+    req.insert_header("X-Forwarded-For", "198.51.100.23");
+    let builder = TracingMiddlewareBuilder::new("%{r}a")
+        .trusted_proxies(["10.0.0.0/8"])
+        .anonymize_ip(IpAnonymization::TruncateV4Octets(1));
+    assert_eq!(record(builder, req), "198.51.100.0");
+}
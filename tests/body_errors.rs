@@ -0,0 +1,49 @@
+mod common;
+
+use std::io;
+
+use common::{get, with_capture, Capture};
+use futures::{stream, TryStreamExt};
+use tide::Body;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+use tracing::Level;
+
+fn app(builder: TracingMiddlewareBuilder<()>) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(builder.build());
+    app.at("/broken").get(|_| async {
+        let chunks = stream::iter(vec![
+            Ok(b"partial".to_vec()),
+            Err(io::Error::other("upstream went away")),
+        ]);
+        Ok(Body::from_reader(chunks.into_async_read(), None))
+    });
+    app.at("/ok").get(|_| async { Ok("ok") });
+    app
+}
+
+#[test]
+fn only_errors_keeps_failed_bodies() {
+    let app = app(TracingMiddlewareBuilder::new("%M %U %s").only_errors(true));
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        get(&app, "/ok");
+        get(&app, "/broken");
+    });
+
+    assert_eq!(capture.messages(), vec!["GET /broken 200"]);
+}
+
+#[test]
+fn sampling_keeps_failed_bodies() {
+    let app = app(TracingMiddlewareBuilder::new("%M %U %s").sample_rate(0.0));
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        get(&app, "/ok");
+        get(&app, "/broken");
+    });
+
+    assert_eq!(capture.messages(), vec!["GET /broken 200"]);
+}
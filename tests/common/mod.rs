@@ -0,0 +1,154 @@
+//! Helpers shared by the integration tests: a subscriber capturing the events, and requests
+//! sent through a tide app without a server.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tide::http::{Method, Request, Url};
+use tide::Server;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+use tracing::callsite::Identifier;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// An event recorded by [`Capture`].
+#[derive(Debug, Clone)]
+pub struct CapturedEvent {
+    pub level: Level,
+    pub target: String,
+    pub callsite: Identifier,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl CapturedEvent {
+    pub fn message(&self) -> &str {
+        self.fields.get("message").map_or("", String::as_str)
+    }
+}
+
+/// A subscriber recording the events at `max_level` and more severe ones.
+#[derive(Clone)]
+pub struct Capture {
+    max_level: Level,
+    events: Arc<Mutex<Vec<CapturedEvent>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Capture {
+    pub fn new(max_level: Level) -> Self {
+        Self {
+            max_level,
+            events: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// The events of the middleware recorded so far.
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.target.starts_with("tide_tracing_middleware"))
+            .cloned()
+            .collect()
+    }
+
+    /// The messages of the events of the middleware recorded so far.
+    pub fn messages(&self) -> Vec<String> {
+        self.events()
+            .iter()
+            .map(|event| event.message().to_owned())
+            .collect()
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut fields = BTreeMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        self.events.lock().unwrap().push(CapturedEvent {
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            callsite: metadata.callsite(),
+            fields,
+        });
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+/// Run `f` with `capture` as the default subscriber of the thread.
+pub fn with_capture<T>(capture: &Capture, f: impl FnOnce() -> T) -> T {
+    tracing::subscriber::with_default(capture.clone(), f)
+}
+
+/// The message of the access record of `req`, sent to an app answering `ok` that logs with
+/// `builder`.
+pub fn record(builder: TracingMiddlewareBuilder<()>, req: Request) -> String {
+    let capture = Capture::new(Level::TRACE);
+    let mut app = tide::new();
+    app.with(builder.build());
+    app.at("/*").all(|_| async { Ok("ok") });
+    app.at("/").all(|_| async { Ok("ok") });
+    with_capture(&capture, || send(&app, req));
+    let messages = capture.messages();
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    messages.into_iter().next().unwrap()
+}
+
+/// Send a `GET` request for `path` to `app` and read the whole response body.
+pub fn get(app: &Server<()>, path: &str) -> (u16, String) {
+    send(app, request(Method::Get, path))
+}
+
+/// A request for `path`, like `/users?id=1`.
+pub fn request(method: Method, path: &str) -> Request {
+    Request::new(
+        method,
+        Url::parse(&format!("http://example.com{}", path)).unwrap(),
+    )
+}
+
+/// Send `req` to `app` and read the whole response body, which is when records are logged.
+pub fn send(app: &Server<()>, req: Request) -> (u16, String) {
+    async_std::task::block_on(async {
+        let mut resp: tide::http::Response = app.respond(req).await.unwrap();
+        let status = resp.status() as u16;
+        let body = resp.body_string().await.unwrap_or_default();
+        (status, body)
+    })
+}
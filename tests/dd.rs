@@ -0,0 +1,54 @@
+#![cfg(feature = "dd")]
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::{request, send, with_capture, Capture};
+use tide::http::Method;
+use tide_tracing_middleware::{TracingMiddlewareBuilder, TracingSink};
+use tracing::Level;
+
+/// The fields of the access record of a request with a W3C trace context, logged with
+/// `builder`.
+fn fields(builder: TracingMiddlewareBuilder<()>) -> BTreeMap<String, String> {
+    let mut app = tide::new();
+    app.with(builder.build());
+    app.at("/").get(|_| async { Ok("ok") });
+    let mut req = request(Method::Get, "/");
+    req.insert_header(
+        "traceparent",
+        "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+    );
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || send(&app, req));
+    let events = capture.events();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    events.into_iter().next().unwrap().fields
+}
+
+#[test]
+fn records_are_correlated_by_the_lower_trace_id() {
+    let fields = fields(TracingMiddlewareBuilder::new("%M %U"));
+    assert_eq!(fields["message"], "GET /");
+    assert_eq!(
+        fields["dd.trace_id"],
+        u64::from_str_radix("8448eb211c80319c", 16)
+            .unwrap()
+            .to_string()
+    );
+    assert_eq!(
+        fields["dd.span_id"],
+        u64::from_str_radix("b7ad6b7169203331", 16)
+            .unwrap()
+            .to_string()
+    );
+}
+
+#[test]
+fn the_tracing_sink_keeps_the_correlation_fields() {
+    assert_eq!(
+        fields(TracingMiddlewareBuilder::new("%M %U").sink(TracingSink)),
+        fields(TracingMiddlewareBuilder::new("%M %U"))
+    );
+}
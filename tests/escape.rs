@@ -0,0 +1,44 @@
+mod common;
+
+use common::{record, request};
+use tide::http::Method;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+
+const FORGED: &str = "ok\n127.0.0.1 GET /admin 200\t\x1b[31m\\ \u{202e}txt.exe\u{7f}";
+
+fn render(builder: TracingMiddlewareBuilder<()>) -> String {
+    record(
+        builder.custom_request_replace("v", |_| FORGED.to_owned()),
+        request(Method::Get, "/"),
+    )
+}
+
+#[test]
+fn control_characters_are_escaped_by_default() {
+    assert_eq!(
+        render(TracingMiddlewareBuilder::new("%{v}xi")),
+        r"ok\n127.0.0.1 GET /admin 200\t\u{1b}[31m\\ \u{202e}txt.exe\u{7f}"
+    );
+}
+
+#[test]
+fn printable_values_are_unchanged() {
+    let builder = TracingMiddlewareBuilder::new("%{v}xi")
+        .custom_request_replace("v", |_| "naïve 日本 \"quoted\"".to_owned());
+    assert_eq!(
+        record(builder, request(Method::Get, "/")),
+        "naïve 日本 \"quoted\""
+    );
+}
+
+#[test]
+fn escaping_can_be_disabled() {
+    let builder = TracingMiddlewareBuilder::new("%{v}xi").escape_control_chars(false);
+    assert_eq!(render(builder), FORGED);
+}
+
+#[test]
+fn literal_text_is_not_escaped() {
+    let builder = TracingMiddlewareBuilder::new("a\tb %{v}xi");
+    assert!(render(builder).starts_with("a\tb ok\\n"));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::{request, send, with_capture, Capture};
+use tide::http::Method;
+use tide_tracing_middleware::{OutputFormat, TracingMiddlewareBuilder};
+use tracing::Level;
+
+#[test]
+fn missing_values_share_the_callsite() {
+    let capture = Capture::new(Level::INFO);
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U %{X-A}i %{X-B}i")
+            .output(OutputFormat::Fields)
+            .build(),
+    );
+    app.at("/").get(|_| async { Ok("ok") });
+
+    with_capture(&capture, || {
+        for headers in [&[][..], &["X-A"], &["X-B"], &["X-A", "X-B"]] {
+            let mut req = request(Method::Get, "/");
+            for name in headers {
+                req.insert_header(*name, "v");
+            }
+            send(&app, req);
+        }
+    });
+
+    let events = capture.events();
+    assert_eq!(events.len(), 4);
+    assert!(events
+        .iter()
+        .all(|event| event.callsite == events[0].callsite));
+    assert_eq!(events[0].fields.get("request_headers.x-a"), None);
+    assert_eq!(events[1].fields["request_headers.x-a"], "v");
+    assert_eq!(events[1].fields.get("request_headers.x-b"), None);
+    assert_eq!(events[3].fields["request_headers.x-b"], "v");
+    assert_eq!(events[3].fields["method"], "GET");
+}
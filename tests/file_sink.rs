@@ -0,0 +1,217 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::get;
+use tide_tracing_middleware::{Clock, FileSink, Rotation, TracingMiddlewareBuilder};
+use time::OffsetDateTime;
+
+/// An empty directory for the test `name`.
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("file-sink-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn files(dir: &PathBuf) -> Vec<String> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    files
+}
+
+/// A clock starting at 2020-01-04 12:00:00 UTC that only moves when told to, shared with
+/// the test.
+#[derive(Clone)]
+struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::unix_epoch()
+            + Duration::from_secs(1_578_139_200)
+            + *self.elapsed.lock().unwrap()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
+
+/// An app writing the records to `sink` at the time of `clock`.
+fn app(sink: FileSink, clock: &MockClock) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U")
+            .clock(clock.clone())
+            .sink(sink)
+            .build(),
+    );
+    app.at("/*").get(|_| async { Ok("ok") });
+    app
+}
+
+const HOUR: Duration = Duration::from_secs(3600);
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+#[test]
+fn rotation_only_prunes_rotated_files() {
+    let dir = dir("prune");
+    for name in [
+        "access.log.2020-01-01",
+        "access.log.2020-01-02",
+        "access.log.2020-01-02.1",
+        "access.log.2020-01-01.gz",
+        "access.log.bak",
+        "access.log.2020-01-01-13",
+    ] {
+        File::create(dir.join(name)).unwrap();
+    }
+    let path = dir.join("access.log");
+    let sink = FileSink::new(&path)
+        .unwrap()
+        .rotation(Rotation::Daily)
+        .max_files(2);
+    let clock = MockClock::new();
+    let app = app(sink, &clock);
+
+    get(&app, "/old");
+    clock.advance(DAY);
+    get(&app, "/new");
+
+    assert_eq!(
+        files(&dir),
+        vec![
+            "access.log",
+            "access.log.2020-01-01-13",
+            "access.log.2020-01-01.gz",
+            "access.log.2020-01-02.1",
+            "access.log.2020-01-04",
+            "access.log.bak",
+        ]
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), "GET /new\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_are_rotated_on_the_next_period() {
+    let dir = dir("rotate");
+    let path = dir.join("access.log");
+    File::create(dir.join("access.log.2020-01-04")).unwrap();
+    let clock = MockClock::new();
+    let app = app(FileSink::new(&path).unwrap(), &clock);
+
+    get(&app, "/old");
+    clock.advance(DAY);
+    get(&app, "/new");
+
+    assert_eq!(
+        files(&dir),
+        vec![
+            "access.log",
+            "access.log.2020-01-04",
+            "access.log.2020-01-04.1"
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("access.log.2020-01-04.1")).unwrap(),
+        "GET /old\n"
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), "GET /new\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn hourly_files_are_named_by_the_hour() {
+    let dir = dir("hourly");
+    let path = dir.join("access.log");
+    let clock = MockClock::new();
+    let app = app(
+        FileSink::new(&path).unwrap().rotation(Rotation::Hourly),
+        &clock,
+    );
+
+    get(&app, "/old");
+    clock.advance(HOUR);
+    get(&app, "/new");
+
+    assert_eq!(files(&dir), vec!["access.log", "access.log.2020-01-04-12"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_of_the_current_period_are_appended_to() {
+    let dir = dir("append");
+    let path = dir.join("access.log");
+    let clock = MockClock::new();
+    let app = app(FileSink::new(&path).unwrap(), &clock);
+
+    get(&app, "/old");
+    clock.advance(HOUR);
+    get(&app, "/new");
+
+    assert_eq!(files(&dir), vec!["access.log"]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "GET /old\nGET /new\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_are_never_rotated_without_rotation() {
+    let dir = dir("never");
+    let path = dir.join("access.log");
+    let clock = MockClock::new();
+    let app = app(
+        FileSink::new(&path).unwrap().rotation(Rotation::Never),
+        &clock,
+    );
+
+    get(&app, "/old");
+    clock.advance(2 * DAY);
+    get(&app, "/new");
+
+    assert_eq!(files(&dir), vec!["access.log"]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "GET /old\nGET /new\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_left_by_a_previous_run_are_appended_to() {
+    let dir = dir("left");
+    let path = dir.join("access.log");
+    fs::write(&path, "GET /old\n").unwrap();
+
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U")
+            .sink(FileSink::new(&path).unwrap())
+            .build(),
+    );
+    app.at("/*").get(|_| async { Ok("ok") });
+    get(&app, "/new");
+
+    assert_eq!(files(&dir), vec!["access.log"]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "GET /old\nGET /new\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
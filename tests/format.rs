@@ -0,0 +1,239 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{record, request, send, with_capture, Capture};
+use tide::http::{Method, Request};
+use tide::{Response, StatusCode};
+use tide_tracing_middleware::{Clock, TracingMiddlewareBuilder};
+use time::OffsetDateTime;
+use tracing::Level;
+
+const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+fn render(format: &str, req: Request) -> String {
+    record(TracingMiddlewareBuilder::new(format), req)
+}
+
+/// A `POST /users/42?page=2` request with a body, a user agent and a cookie.
+fn post() -> Request {
+    let mut req = request(Method::Post, "/users/42?page=2");
+    req.insert_header("User-Agent", "curl/8.0");
+    req.insert_header("Cookie", "session=abc; theme=dark");
+    req.set_body("hello");
+    req
+}
+
+/// The record of `req` sent to an app with a `/users/:id` route answering `created` with
+/// status 201 and an `ETag` header.
+fn render_route(builder: TracingMiddlewareBuilder<()>, req: Request) -> String {
+    let capture = Capture::new(Level::TRACE);
+    let mut app = tide::new();
+    app.with(builder.build());
+    app.at("/users/:id").all(|_| async {
+        let mut resp = Response::new(StatusCode::Created);
+        resp.insert_header("ETag", "\"v1\"");
+        resp.set_body("created");
+        Ok(resp)
+    });
+    with_capture(&capture, || send(&app, req));
+    let messages = capture.messages();
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    messages.into_iter().next().unwrap()
+}
+
+/// A clock stopped at 2023-11-14 22:13:20 UTC, so every duration is zero.
+struct StoppedClock(Instant);
+
+impl Clock for StoppedClock {
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::unix_epoch() + Duration::from_secs(1_700_000_000)
+    }
+
+    fn now(&self) -> Instant {
+        self.0
+    }
+}
+
+#[test]
+fn request_tokens() {
+    for (format, expected) in [
+        ("%%", "%"),
+        ("%a %h %{r}a", "- - -"),
+        ("%r", "POST /users/42?page=2 ?"),
+        ("%M %U %Q", "POST /users/42 page=2"),
+        ("%H %v %p", "http example.com 80"),
+        ("%u", "-"),
+        ("%I", "5"),
+        ("%{User-Agent}i", "curl/8.0"),
+        ("%{session}C %{theme}C", "abc dark"),
+        ("%{X-Missing}i", "-"),
+    ] {
+        assert_eq!(render(format, post()), expected, "{}", format);
+    }
+}
+
+#[test]
+fn hosts_are_logged_without_the_port() {
+    for (host, expected) in [
+        ("example.com", "example.com:80"),
+        ("example.com:8080", "example.com:80"),
+        ("[::1]:8080", "[::1]:80"),
+        ("[::1]", "[::1]:80"),
+    ] {
+        let mut req = request(Method::Get, "/");
+        req.insert_header("Host", host);
+        assert_eq!(render("%v:%p", req), expected, "{}", host);
+    }
+}
+
+#[test]
+fn response_tokens() {
+    for (format, expected) in [
+        ("%s", "201"),
+        ("%{color}s", "\u{1b}[32m201\u{1b}[0m"),
+        ("%b %{clf}b %{declared}b", "7 7 7"),
+        ("%{human}b", "7 B"),
+        ("%{ETag}o", "\"v1\""),
+        ("%C", "text/plain;charset=utf-8"),
+        ("%X %E", "- -"),
+        ("%{id}P", "42"),
+    ] {
+        assert_eq!(
+            render_route(TracingMiddlewareBuilder::new(format), post()),
+            expected,
+            "{}",
+            format
+        );
+    }
+}
+
+#[test]
+fn empty_bodies_are_dashes_in_clf() {
+    let req = request(Method::Get, "/users/1");
+    let builder = TracingMiddlewareBuilder::new("%b %{clf}b").clock(StoppedClock(Instant::now()));
+    let mut app = tide::new();
+    app.with(builder.build());
+    app.at("/users/:id")
+        .get(|_| async { Ok(Response::new(StatusCode::NoContent)) });
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || send(&app, req));
+    assert_eq!(capture.messages(), vec!["0 -"]);
+}
+
+#[test]
+fn time_tokens() {
+    for (format, expected) in [
+        ("%t", "2023-11-14T22:13:20"),
+        ("%{clf}t", "[14/Nov/2023:22:13:20 +0000]"),
+        ("%{s}t %{ms}t", "1700000000 1700000000000"),
+        ("%{us}t", "1700000000000000"),
+        ("%T %{s}T %D %{ms}T", "0.000000 0.000000 0.000000 0.000000"),
+        ("%{us}T %{ns}T", "0 0"),
+        ("%{ttfb}T %{ttfb}D", "0.000000 0.000000"),
+    ] {
+        let builder = TracingMiddlewareBuilder::new(format).clock(StoppedClock(Instant::now()));
+        assert_eq!(render_route(builder, post()), expected, "{}", format);
+    }
+}
+
+#[test]
+fn environment_tokens() {
+    std::env::set_var("TIDE_TRACING_FORMAT_TEST", "staging");
+    assert_eq!(
+        render(
+            "%{TIDE_TRACING_FORMAT_TEST}e %{TIDE_TRACING_FORMAT_UNSET}e",
+            post()
+        ),
+        "staging -"
+    );
+}
+
+#[test]
+fn custom_tokens() {
+    struct Tenant(&'static str);
+    let builder = TracingMiddlewareBuilder::new("%{len}xi %{etag}xo %{ctx}xc %{tenant}xe")
+        .custom_request_replace("len", |req| req.url().path().len().to_string())
+        .custom_response_replace("etag", |resp| {
+            resp.header("ETag")
+                .map_or("-".to_owned(), |etag| etag.as_str().to_owned())
+        })
+        .custom_replace("ctx", |ctx| ctx.status().to_string())
+        .request_ext_with("tenant", |req| {
+            req.ext::<Tenant>().map(|tenant| tenant.0.to_owned())
+        });
+    let mut req = post();
+    req.ext_mut().insert(Tenant("acme"));
+    assert_eq!(render_route(builder, req), "9 \"v1\" 201 acme");
+}
+
+#[test]
+fn body_tokens() {
+    let builder = TracingMiddlewareBuilder::new("%{body}ri %{body}ro")
+        .capture_request_body(3)
+        .capture_response_body(64);
+    assert_eq!(render_route(builder, post()), "hel... created");
+}
+
+#[test]
+fn fallbacks_replace_missing_values() {
+    assert_eq!(
+        render("%{X-Missing?none}i %{User-Agent?none}i %{nope?-}C", post()),
+        "none curl/8.0 -"
+    );
+}
+
+#[test]
+fn trace_context_tokens() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    assert_eq!(
+        render("%{trace_id} %{span_id}", req),
+        "0af7651916cd43dd8448eb211c80319c b7ad6b7169203331"
+    );
+    assert_eq!(
+        render("%{trace_id} %{span_id}", request(Method::Get, "/")),
+        "- -"
+    );
+}
+
+#[test]
+fn reserved_keys_are_followed_by_text() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    req.insert_header("span_id", "header");
+    assert_eq!(
+        render("%{trace_id}a %{span_id}i", req),
+        "0af7651916cd43dd8448eb211c80319ca b7ad6b7169203331i"
+    );
+}
+
+#[test]
+fn reserved_keys_with_fallback_are_headers() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", TRACEPARENT);
+    req.insert_header("span_id", "header");
+    assert_eq!(render("%{span_id?-}i", req), "header");
+}
+
+#[test]
+fn malformed_formats_are_rejected() {
+    for (format, offset, token) in [
+        ("%", 0, "%"),
+        ("status %", 7, "%"),
+        ("%{", 0, "%{"),
+        ("%{x", 0, "%{x"),
+        ("%{x?fallback", 0, "%{x?fallback"),
+        ("%{}i", 0, "%{}i"),
+        ("%Z", 0, "%Z"),
+        ("%s %{User-Agent}q", 3, "%{User-Agent}q"),
+        ("%{foo}t", 0, "%{foo}t"),
+        ("%{x?-}s", 0, "%{x?-}s"),
+        ("%{k?-}xc", 0, "%{k?-}xc"),
+    ] {
+        let err = TracingMiddlewareBuilder::<()>::try_new(format)
+            .err()
+            .unwrap_or_else(|| panic!("`{}` was accepted", format));
+        assert_eq!((err.offset(), err.token()), (offset, token), "{}", format);
+    }
+}
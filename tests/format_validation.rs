@@ -0,0 +1,63 @@
+//! `tracing_format!` checks the formats at compile time with `validate_format`, which must
+//! accept exactly the formats the runtime parser accepts.
+
+use std::panic;
+
+use tide_tracing_middleware::{validate_format, TracingMiddlewareBuilder};
+
+const FORMATS: &[&str] = &[
+    "",
+    "plain text",
+    "%%",
+    "%a %h %t %r %u %p %H %v %s %b %I %C %Cn %M %V %Q %U %R %T %D %E %X",
+    r#"%h - %u %{clf}t "%r" %s %{clf}b "%{Referer}i" "%{User-Agent}i""#,
+    "%{r}a %{s}t %{ms}t %{us}t %{human}b %{declared}b %{color}s",
+    "%{ttfb}T %{s}T %{ms}T %{us}T %{ns}T %{ttfb}D",
+    "%{X-Id}i %{ETag}o %{HOME}e %{session}C %{id}P",
+    "%{k}xi %{k}xo %{k}xe %{k}xoe %{k}xc %{body}ri %{body}ro",
+    "%{trace_id} %{span_id} %{request_id} %{xray_root} %{xray_self}",
+    "%{trace_id}a %{span_id}i %{request_id}s",
+    "%{span_id?-}i %{X-Id?none}i %{X-Id?}o %{c?-}C %{p?-}P %{k?-}xe %{k?-}xoe",
+    "%{a_b-c}i",
+    "%",
+    "text %",
+    "%{",
+    "%{x",
+    "%{x}",
+    "%{x?fallback",
+    "%{}i",
+    "%{?x}i",
+    "%{x y}i",
+    "%Z",
+    "%{x}q",
+    "%{x}x",
+    "%{x}r",
+    "%{head}ri",
+    "%{x}a",
+    "%{foo}t",
+    "%{foo}b",
+    "%{foo}s",
+    "%{foo}T",
+    "%{foo}D",
+    "%{x?-}s",
+    "%{x?-}e",
+    "%{k?-}xi",
+    "%{k?-}xc",
+    "%{trace_id?-}",
+];
+
+#[test]
+fn validate_format_agrees_with_the_parser() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let disagreements: Vec<_> = FORMATS
+        .iter()
+        .filter(|format| {
+            let parsed = TracingMiddlewareBuilder::<()>::try_new(format).is_ok();
+            let validated = panic::catch_unwind(|| validate_format(format)).is_ok();
+            parsed != validated
+        })
+        .collect();
+    panic::set_hook(hook);
+    assert!(disagreements.is_empty(), "{:?}", disagreements);
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{record, request, send, with_capture, Capture};
+use tide::http::Method;
+use tide_tracing_middleware::{ForwardedInfo, TracingMiddlewareBuilder};
+use tracing::Level;
+
+fn from(peer: &str, headers: &[(&str, &str)]) -> tide::http::Request {
+    let mut req = request(Method::Get, "/");
+    req.set_peer_addr(Some(peer));
+    for (name, value) in headers {
+        req.append_header(*name, *value);
+    }
+    req
+}
+
+fn client(req: tide::http::Request) -> String {
+    let builder = TracingMiddlewareBuilder::new("%{r}a").trusted_proxies(["10.0.0.0/8"]);
+    record(builder, req)
+}
+
+#[test]
+fn untrusted_peers_are_the_client() {
+    let req = from("203.0.113.9:4000", &[("X-Forwarded-For", "198.51.100.1")]);
+    assert_eq!(client(req), "203.0.113.9:4000");
+}
+
+#[test]
+fn x_forwarded_for_is_walked_from_the_right() {
+    let req = from(
+        "10.0.0.1:4000",
+        &[("X-Forwarded-For", "6.6.6.6, 198.51.100.1, 10.0.0.2")],
+    );
+    assert_eq!(client(req), "198.51.100.1");
+
+    // the values of repeated headers are one list
+    let req = from(
+        "10.0.0.1:4000",
+        &[
+            ("X-Forwarded-For", "6.6.6.6, 198.51.100.1"),
+            ("X-Forwarded-For", "10.0.0.3"),
+        ],
+    );
+    assert_eq!(client(req), "198.51.100.1");
+}
+
+#[test]
+fn x_real_ip_is_the_last_resort() {
+    let req = from(
+        "10.0.0.1:4000",
+        &[
+            ("X-Forwarded-For", "10.0.0.2"),
+            ("X-Real-IP", "198.51.100.7"),
+        ],
+    );
+    assert_eq!(client(req), "198.51.100.7");
+}
+
+#[test]
+fn forwarded_is_preferred() {
+    let req = from(
+        "10.0.0.1:4000",
+        &[
+            (
+                "Forwarded",
+                r#"for=6.6.6.6, for="[2001:db8::1]:4711";proto=https;host=example.org, for=10.0.0.2"#,
+            ),
+            ("X-Forwarded-For", "198.51.100.1"),
+        ],
+    );
+    let builder = TracingMiddlewareBuilder::new("%{r}a %H %v")
+        .trusted_proxy("10.0.0.1".parse().unwrap())
+        .trusted_proxy("10.0.0.2".parse().unwrap());
+    assert_eq!(record(builder, req), "[2001:db8::1]:4711 https example.org");
+}
+
+#[test]
+fn forwarded_info_is_stored_in_the_request() {
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%s")
+            .trusted_proxies(["10.0.0.0/8"])
+            .build(),
+    );
+    app.at("/").get(|req: tide::Request<()>| async move {
+        let info = req.ext::<ForwardedInfo>().cloned().unwrap_or_default();
+        Ok(format!(
+            "{:?} {:?} {:?}",
+            info.client(),
+            info.proto(),
+            info.host()
+        ))
+    });
+    let req = from(
+        "10.0.0.1:4000",
+        &[
+            ("X-Forwarded-For", "198.51.100.1"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "example.org"),
+        ],
+    );
+
+    let (_, body) = with_capture(&Capture::new(Level::TRACE), || send(&app, req));
+
+    assert_eq!(
+        body,
+        r#"Some("198.51.100.1") Some("https") Some("example.org")"#
+    );
+}
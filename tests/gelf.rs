@@ -0,0 +1,28 @@
+mod common;
+
+use common::{record, request};
+use serde_json::Value;
+use tide::http::Method;
+use tide_tracing_middleware::{OutputFormat, TracingMiddlewareBuilder};
+
+/// The `host` of the GELF message of a request logged with `builder`.
+fn host(builder: TracingMiddlewareBuilder<()>) -> Value {
+    let builder = builder.output(OutputFormat::Gelf);
+    let message: Value = serde_json::from_str(&record(builder, request(Method::Get, "/"))).unwrap();
+    message["host"].clone()
+}
+
+#[test]
+fn the_host_is_looked_up() {
+    let expected = gethostname::gethostname().into_string().unwrap();
+    assert_eq!(
+        host(TracingMiddlewareBuilder::new("%M %U")),
+        expected.trim()
+    );
+}
+
+#[test]
+fn the_host_can_be_set() {
+    let builder = TracingMiddlewareBuilder::new("%M %U").gelf_host("web-1");
+    assert_eq!(host(builder), "web-1");
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{get, Capture};
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+use tracing::Level;
+
+#[test]
+fn slow_requests_report_progress() {
+    // the events are emitted by a task on another thread
+    let capture = Capture::new(Level::WARN);
+    tracing::subscriber::set_global_default(capture.clone()).unwrap();
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U %s")
+            .heartbeat(Duration::from_millis(20), Duration::from_millis(20))
+            .build(),
+    );
+    app.at("/slow").get(|_| async {
+        async_std::task::sleep(Duration::from_millis(150)).await;
+        Ok("done")
+    });
+
+    get(&app, "/slow");
+
+    let events = capture.events();
+    assert!(events.len() >= 2, "{:?}", events);
+    assert!(events.iter().all(|event| event.level == Level::WARN));
+    assert_eq!(events[0].message(), "request still in progress");
+    assert_eq!(events[0].fields["path"], "/slow");
+}
+
+#[test]
+#[should_panic(expected = "the heartbeat interval must not be zero")]
+fn zero_interval_is_rejected() {
+    TracingMiddlewareBuilder::<()>::default().heartbeat(Duration::from_secs(1), Duration::ZERO);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "loki")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use common::{get, with_capture, Capture};
+use tide_tracing_middleware::{LokiSink, TracingMiddlewareBuilder};
+use tracing::Level;
+
+#[test]
+fn full_queues_are_reported_once() {
+    // a server that never answers keeps the worker busy with the first record
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    let sink = LokiSink::new(&url).unwrap().batch_size(1).queue_size(0);
+    let mut app = tide::new();
+    app.with(TracingMiddlewareBuilder::new("%M %U").sink(sink).build());
+    app.at("/").get(|_| async { Ok("ok") });
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        for _ in 0..5 {
+            get(&app, "/");
+        }
+    });
+
+    assert_eq!(
+        capture.messages(),
+        vec!["the Loki sink queue is full, 1 access records dropped"]
+    );
+}
+
+/// Answer the pushes to `server` with `204`, sending the head and the body of each request
+/// to the returned channel.
+fn serve(server: TcpListener) -> mpsc::Receiver<(String, String)> {
+    let (requests, pushed) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in server.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(len) = line.strip_prefix("Content-Length: ") {
+                    content_length = len.trim_end().parse().unwrap();
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            requests
+                .send((head, String::from_utf8(body).unwrap()))
+                .unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+        }
+    });
+    pushed
+}
+
+/// Log a request with `sink`, and drop it so the record is pushed.
+fn push(sink: LokiSink) {
+    let mut app = tide::new();
+    app.with(TracingMiddlewareBuilder::new("%M %U").sink(sink).build());
+    app.at("/").get(|_| async { Ok("ok") });
+    get(&app, "/");
+}
+
+#[test]
+fn queued_records_are_pushed_when_the_sink_is_dropped() {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    let pushed = serve(server);
+    // only the shutdown pushes the record before the interval
+    let sink = LokiSink::new(&url)
+        .unwrap()
+        .batch_interval(Duration::from_secs(60));
+
+    push(sink);
+
+    let (_, body) = pushed
+        .try_recv()
+        .expect("the record was not pushed on drop");
+    assert!(body.contains(r#""GET /""#), "{}", body);
+}
+
+#[test]
+fn ipv6_servers_are_pushed_to() {
+    let server = TcpListener::bind("[::1]:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let pushed = serve(server);
+    let sink = LokiSink::new(&format!("http://[::1]:{}", port))
+        .unwrap()
+        .tenant("team-a")
+        .unwrap();
+
+    push(sink);
+
+    let (head, _) = pushed.try_recv().expect("the record was not pushed");
+    assert!(
+        head.starts_with("POST /loki/api/v1/push HTTP/1.1\r\n"),
+        "{}",
+        head
+    );
+    assert!(
+        head.contains(&format!("\r\nHost: [::1]:{}\r\n", port)),
+        "{}",
+        head
+    );
+    assert!(head.contains("\r\nX-Scope-OrgID: team-a\r\n"), "{}", head);
+}
+
+#[test]
+fn tenants_must_be_visible_ascii() {
+    for tenant in ["", "a b", "a\r\nX-Injected: 1", "é"] {
+        let sink = LokiSink::new("http://loki:3100").unwrap();
+        assert!(sink.tenant(tenant).is_err(), "{:?}", tenant);
+    }
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use common::{request, send, with_capture, Capture};
+use tide::http::Method;
+use tide_tracing_middleware::{TracingMiddleware, TracingMiddlewareBuilder};
+use tracing::Level;
+
+fn app(middleware: TracingMiddleware<()>) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(middleware);
+    app.at("/*").get(|_| async { Ok("hello") });
+    app
+}
+
+#[test]
+fn default_records() {
+    let mut req = request(Method::Get, "/greeting?lang=en");
+    req.set_peer_addr(Some("192.0.2.1:5000"));
+    req.set_version(Some(tide::http::Version::Http1_1));
+    req.insert_header("User-Agent", "curl/8.0");
+    let app = app(TracingMiddleware::default());
+    let capture = Capture::new(Level::TRACE);
+
+    let (status, body) = with_capture(&capture, || send(&app, req));
+
+    assert_eq!((status, body.as_str()), (200, "hello"));
+    let events = capture.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].level, Level::INFO);
+    assert_eq!(events[0].target, "tide_tracing_middleware");
+    let message = events[0].message();
+    let (line, time) = message.rsplit_once(' ').unwrap();
+    assert_eq!(
+        line,
+        r#"192.0.2.1:5000 "GET /greeting?lang=en HTTP/1.1" 200 5 "-" "curl/8.0""#
+    );
+    assert!(time.parse::<f64>().is_ok(), "{}", message);
+}
+
+#[test]
+fn records_are_logged_at_the_configured_level() {
+    let app = app(TracingMiddlewareBuilder::new("%s")
+        .level(Level::DEBUG)
+        .build());
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || send(&app, request(Method::Get, "/")));
+
+    let events = capture.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].level, Level::DEBUG);
+}
+
+#[test]
+fn records_below_the_subscriber_level_are_not_logged() {
+    let middleware = TracingMiddlewareBuilder::new("%s")
+        .level(Level::DEBUG)
+        .build();
+    let stats = middleware.stats();
+    let app = app(middleware);
+    let capture = Capture::new(Level::INFO);
+
+    let (_, body) = with_capture(&capture, || send(&app, request(Method::Get, "/a")));
+
+    assert_eq!(body, "hello");
+    assert!(capture.events().is_empty());
+    assert_eq!(stats.disabled(), 1);
+}
+
+#[test]
+fn requests_are_in_flight_until_their_body_is_sent() {
+    let app = app(TracingMiddlewareBuilder::new("%U %Cn").build());
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        async_std::task::block_on(async {
+            // the body of the first response is still streaming when the second one starts
+            let mut streaming: tide::http::Response =
+                app.respond(request(Method::Get, "/slow")).await.unwrap();
+            let mut fast: tide::http::Response =
+                app.respond(request(Method::Get, "/fast")).await.unwrap();
+            fast.body_string().await.unwrap();
+            streaming.body_string().await.unwrap();
+        })
+    });
+
+    assert_eq!(capture.messages(), vec!["/fast 2", "/slow 1"]);
+}
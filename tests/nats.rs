@@ -0,0 +1,48 @@
+#![cfg(feature = "nats")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+use common::get;
+use tide_tracing_middleware::{NatsSink, TracingMiddlewareBuilder};
+
+/// Read a line of the NATS protocol from `reader`, without the `\r\n`.
+fn read_line(reader: &mut impl BufRead) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line.strip_suffix("\r\n").unwrap().to_owned()
+}
+
+#[test]
+fn records_are_published_and_pings_answered() {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sink = NatsSink::new(server.local_addr().unwrap(), "audit.http")
+        .unwrap()
+        .token("secret");
+    let mut app = tide::new();
+    app.with(TracingMiddlewareBuilder::new("%M %U").sink(sink).build());
+    app.at("/").get(|_| async { Ok("ok") });
+
+    get(&app, "/");
+    let (mut conn, _) = server.accept().unwrap();
+    conn.write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(conn.try_clone().unwrap());
+
+    let connect = read_line(&mut reader);
+    assert!(connect.starts_with("CONNECT {"), "{}", connect);
+    assert!(connect.contains(r#""auth_token":"secret""#), "{}", connect);
+    assert_eq!(read_line(&mut reader), "PUB audit.http 5");
+    assert_eq!(read_line(&mut reader), "GET /");
+
+    // an idle connection answers the pings of the server
+    conn.write_all(b"PING\r\n").unwrap();
+    assert_eq!(read_line(&mut reader), "PONG");
+
+    drop(app);
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "");
+}
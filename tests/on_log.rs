@@ -0,0 +1,75 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{get, with_capture, Capture};
+use futures::{stream, TryStreamExt};
+use tide::Body;
+use tide_tracing_middleware::{OutputFormat, TracingMiddlewareBuilder};
+use tracing::Level;
+
+#[test]
+fn streamed_bodies_report_their_size_and_duration() {
+    let seen = Arc::new(Mutex::new(None));
+    let hook_seen = seen.clone();
+    let mut app = tide::new();
+    // nothing in the format needs the body
+    app.with(
+        TracingMiddlewareBuilder::new("%r %s")
+            .on_log(move |record| {
+                *hook_seen.lock().unwrap() = Some((record.body_size(), record.duration()));
+                true
+            })
+            .build(),
+    );
+    app.at("/stream").get(|_| async {
+        let chunks = stream::unfold(0, |i| async move {
+            if i == 3 {
+                return None;
+            }
+            async_std::task::sleep(Duration::from_millis(30)).await;
+            Some((Ok::<_, std::io::Error>(vec![b'x'; 100]), i + 1))
+        });
+        Ok(Body::from_reader(Box::pin(chunks).into_async_read(), None))
+    });
+
+    let (status, body) = with_capture(&Capture::new(Level::TRACE), || get(&app, "/stream"));
+
+    assert_eq!(status, 200);
+    assert_eq!(body.len(), 300);
+    let (size, duration) = seen.lock().unwrap().expect("the hook was not called");
+    assert_eq!(size, 300);
+    assert!(duration >= Duration::from_millis(90), "{:?}", duration);
+}
+
+#[test]
+fn added_fields_are_kept_by_the_fields_output() {
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U")
+            .output(OutputFormat::Fields)
+            .on_log(|record| {
+                record.set("path", "/hidden");
+                if record.status().is_success() {
+                    record.set("outcome", "ok");
+                }
+                true
+            })
+            .build(),
+    );
+    app.at("/*").get(|_| async { Ok("ok") });
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        get(&app, "/a");
+        get(&app, "/");
+    });
+
+    let events = capture.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].fields["path"], "/hidden");
+    assert_eq!(events[0].fields["extra"], r#"{"outcome":"ok"}"#);
+    assert_eq!(events[1].fields.get("extra"), None);
+    assert_eq!(events[0].callsite, events[1].callsite);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use common::{get, with_capture, Capture};
+use tide_tracing_middleware::{OnPanic, TracingMiddlewareBuilder};
+use tracing::Level;
+
+fn app(on_panic: OnPanic) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%M %U %s %E")
+            .catch_panics(on_panic)
+            .build(),
+    );
+    app.at("/panic").get(|_| async {
+        if true {
+            panic!("boom");
+        }
+        Ok("unreachable")
+    });
+    app
+}
+
+#[test]
+fn panics_are_answered_with_500() {
+    let app = app(OnPanic::Respond);
+    let capture = Capture::new(Level::TRACE);
+
+    let (status, _) = with_capture(&capture, || get(&app, "/panic"));
+
+    assert_eq!(status, 500);
+    let events = capture.events();
+    assert_eq!(events.len(), 2, "{:?}", events);
+    assert_eq!(events[0].level, Level::ERROR);
+    assert_eq!(events[0].message(), "handler panicked: boom");
+    assert_eq!(events[0].fields["method"], "GET");
+    assert_eq!(events[0].fields["path"], "/panic");
+    assert_eq!(events[0].fields["panic"], "boom");
+    assert_eq!(events[1].message(), "GET /panic 500 handler panicked: boom");
+}
+
+#[test]
+fn panics_are_resumed_without_an_access_record() {
+    let app = app(OnPanic::Resume);
+    let capture = Capture::new(Level::TRACE);
+
+    let result = with_capture(&capture, || {
+        panic::catch_unwind(AssertUnwindSafe(|| get(&app, "/panic")))
+    });
+
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    let events = capture.events();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!(events[0].message(), "handler panicked: boom");
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "pii")]
+
+mod common;
+
+use common::{record, request};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use tide::http::Method;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+
+fn user(id: Option<&str>) -> tide::http::Request {
+    let mut req = request(Method::Get, "/");
+    if let Some(id) = id {
+        req.insert_header("X-User-Id", id);
+    }
+    req
+}
+
+fn hashed(salt: &str, req: tide::http::Request) -> String {
+    let builder = TracingMiddlewareBuilder::new("%{X-User-Id}i")
+        .hash_pii("%{X-User-Id}i")
+        .pii_salt(salt);
+    record(builder, req)
+}
+
+#[test]
+fn values_are_replaced_by_their_salted_hash() {
+    let mut mac = Hmac::<Sha256>::new_varkey(b"pepper").unwrap();
+    mac.update(b"user-42");
+    let expected: String = mac.finalize().into_bytes()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    assert_eq!(hashed("pepper", user(Some("user-42"))), expected);
+    assert_eq!(hashed("pepper", user(Some("user-42"))), expected);
+    assert_ne!(hashed("pepper", user(Some("user-43"))), expected);
+    assert_ne!(hashed("salt", user(Some("user-42"))), expected);
+}
+
+#[test]
+fn missing_values_are_not_hashed() {
+    assert_eq!(hashed("pepper", user(None)), "-");
+}
+
+#[test]
+fn the_default_salt_is_random() {
+    let builder = || TracingMiddlewareBuilder::new("%{X-User-Id}i").hash_pii("%{X-User-Id}i");
+    assert_ne!(
+        record(builder(), user(Some("user-42"))),
+        record(builder(), user(Some("user-42")))
+    );
+}
+
+#[test]
+#[should_panic(expected = "is not a single format token")]
+fn only_single_tokens_are_hashed() {
+    TracingMiddlewareBuilder::<()>::default().hash_pii("%a %h");
+}
@@ -0,0 +1,186 @@
+mod common;
+
+use common::{request, send, with_capture, Capture};
+use serde_json::{json, Value};
+use tide::http::Method;
+use tide_tracing_middleware::TracingMiddleware;
+use tracing::Level;
+
+/// The access record of `req` sent to an app answering `ok`, logged by `middleware`.
+fn log(middleware: TracingMiddleware<()>, req: tide::http::Request) -> String {
+    let mut app = tide::new();
+    app.with(middleware);
+    app.at("/").get(|_| async { Ok("ok") });
+    app.at("/*").get(|_| async { Ok("ok") });
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || send(&app, req));
+    let messages = capture.messages();
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    messages.into_iter().next().unwrap()
+}
+
+/// A `GET` request for `path` from `192.0.2.1:5000` by curl.
+fn curl(path: &str) -> tide::http::Request {
+    let mut req = request(Method::Get, path);
+    req.set_peer_addr(Some("192.0.2.1:5000"));
+    req.set_version(Some(tide::http::Version::Http1_1));
+    req.insert_header("User-Agent", "curl/8.0");
+    req
+}
+
+/// `line` with the `[...]` timestamp and the durations, which change with every request,
+/// replaced by `[t]` and `t`.
+fn masked(line: &str) -> String {
+    let line = match (line.find('['), line.find(']')) {
+        (Some(start), Some(end)) => format!("{}[t]{}", &line[..start], &line[end + 1..]),
+        _ => line.to_owned(),
+    };
+    line.split(' ')
+        .map(|word| match word.parse::<f64>() {
+            Ok(_) if word.contains('.') => "t",
+            _ => word,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn common_log() {
+    let line = log(TracingMiddleware::common_log(), curl("/users?id=1"));
+
+    assert_eq!(
+        masked(&line),
+        r#"192.0.2.1 - - [t] "GET /users?id=1 HTTP/1.1" 200 2"#
+    );
+}
+
+#[test]
+fn combined_log() {
+    let mut req = curl("/users?id=1");
+    req.insert_header("Referer", "http://example.com/");
+
+    let line = log(TracingMiddleware::combined_log(), req);
+
+    assert_eq!(
+        masked(&line),
+        r#"192.0.2.1 - - [t] "GET /users?id=1 HTTP/1.1" 200 2 "http://example.com/" "curl/8.0""#
+    );
+}
+
+#[test]
+fn dev() {
+    let line = log(TracingMiddleware::dev(), curl("/users?id=1"));
+
+    assert_eq!(masked(&line), "GET /users \u{1b}[32m200\u{1b}[0m t ms - 2");
+}
+
+#[test]
+fn tiny() {
+    let line = log(TracingMiddleware::tiny(), curl("/users?id=1"));
+
+    assert_eq!(masked(&line), "GET /users 200 2 - t ms");
+}
+
+#[test]
+fn short() {
+    let line = log(TracingMiddleware::short(), curl("/users?id=1"));
+
+    assert_eq!(
+        masked(&line),
+        "192.0.2.1 - GET /users HTTP/1.1 200 2 - t ms"
+    );
+}
+
+#[test]
+fn ecs_logs_the_client_ip_without_the_port() {
+    let line = log(TracingMiddleware::ecs(), curl("/users?id=1"));
+
+    let mut record: Value = serde_json::from_str(&line).unwrap();
+    let fields = record.as_object_mut().unwrap();
+    assert!(fields.remove("@timestamp").is_some(), "{}", line);
+    assert!(
+        fields.remove("event.duration").unwrap().is_u64(),
+        "{}",
+        line
+    );
+    assert_eq!(
+        record,
+        json!({
+            "ecs.version": "8.11.0",
+            "http.request.method": "GET",
+            "http.request.referrer": null,
+            "http.response.body.bytes": 2,
+            "http.response.status_code": 200,
+            "source.ip": "192.0.2.1",
+            "url.original": "/users?id=1",
+            "url.path": "/users",
+            "url.query": "id=1",
+            "user_agent.original": "curl/8.0",
+        })
+    );
+}
+
+#[test]
+fn nginx() {
+    let line = log(TracingMiddleware::nginx(), curl("/users?id=1"));
+
+    assert_eq!(
+        masked(&line),
+        r#"192.0.2.1 - - [t] "GET /users?id=1 HTTP/1.1" 200 2 "-" "curl/8.0" t t"#
+    );
+    let times: Vec<&str> = line.rsplit(' ').take(2).collect();
+    assert!(
+        times
+            .iter()
+            .all(|time| time.split_once('.').unwrap().1.len() == 3),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn alb_logs_the_port_once() {
+    let mut app = tide::new();
+    app.with(TracingMiddleware::<()>::alb());
+    app.at("/").get(|_| async { Ok("ok") });
+    let mut req = request(Method::Get, "/");
+    req.insert_header("Host", "example.com:8080");
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || send(&app, req));
+
+    let messages = capture.messages();
+    assert_eq!(messages.len(), 1);
+    let columns: Vec<&str> = messages[0].split(' ').collect();
+    assert_eq!(columns[4], "example.com:80");
+    assert!(
+        messages[0].contains(r#""forward" "-" "-" "example.com:80" "200""#),
+        "{}",
+        messages[0]
+    );
+    assert_alb_times(&messages[0]);
+}
+
+#[test]
+fn alb_times_ignore_the_time_format() {
+    let middleware = TracingMiddleware::<()>::alb()
+        .into_builder()
+        .time_format("%d/%m/%Y")
+        .build();
+    assert_alb_times(&log(middleware, request(Method::Get, "/")));
+}
+
+/// Assert the request and creation times of the ALB `line` are UTC ISO 8601 times with
+/// microseconds, like `2024-01-01T00:00:00.123456Z`.
+fn assert_alb_times(line: &str) {
+    let request_time = line.split(' ').nth(1).unwrap();
+    let creation_time = line.split(r#" "forward""#).next().unwrap();
+    let creation_time = creation_time.rsplit(' ').next().unwrap();
+    for time in [request_time, creation_time] {
+        let shape: String = time
+            .chars()
+            .map(|c| if c.is_ascii_digit() { '0' } else { c })
+            .collect();
+        assert_eq!(shape, "0000-00-00T00:00:00.000000Z", "{}", line);
+    }
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use common::{record, request};
+use tide::http::Method;
+use tide_tracing_middleware::{
+    Propagation, TraceContext, TracingMiddlewareBuilder, XRayTraceHeader,
+};
+
+const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+const SPAN_ID: &str = "b7ad6b7169203331";
+
+#[test]
+fn w3c_traceparent() {
+    let traceparent = format!("00-{}-{}-01", TRACE_ID, SPAN_ID);
+    let context = TraceContext::from_w3c(&traceparent, Some(" vendor=value ")).unwrap();
+    assert_eq!(context.trace_id_hex(), TRACE_ID);
+    assert_eq!(context.span_id_hex(), SPAN_ID);
+    assert_eq!(
+        context.span_id(),
+        [0xb7, 0xad, 0x6b, 0x71, 0x69, 0x20, 0x33, 0x31]
+    );
+    assert_eq!(context.flags(), 0x01);
+    assert!(context.is_sampled());
+    assert_eq!(context.trace_state(), Some("vendor=value"));
+
+    let context = TraceContext::from_w3c(&format!("00-{}-{}-00", TRACE_ID, SPAN_ID), Some(""));
+    let context = context.unwrap();
+    assert!(!context.is_sampled());
+    assert_eq!(context.trace_state(), None);
+}
+
+#[test]
+fn w3c_later_versions_may_add_fields() {
+    let traceparent = format!("01-{}-{}-01-extra", TRACE_ID, SPAN_ID);
+    assert!(TraceContext::from_w3c(&traceparent, None).is_some());
+}
+
+#[test]
+fn w3c_invalid_traceparents() {
+    for traceparent in [
+        "",
+        "00",
+        &format!("00-{}-{}", TRACE_ID, SPAN_ID),
+        &format!("00-{}-{}-01-extra", TRACE_ID, SPAN_ID),
+        &format!("ff-{}-{}-01", TRACE_ID, SPAN_ID),
+        &format!("0-{}-{}-01", TRACE_ID, SPAN_ID),
+        &format!("zz-{}-{}-01", TRACE_ID, SPAN_ID),
+        &format!("00-{}-{}-01", &TRACE_ID[1..], SPAN_ID),
+        &format!("00-{}-{}-01", TRACE_ID, &SPAN_ID[1..]),
+        &format!("00-{}-{}-1", TRACE_ID, SPAN_ID),
+        &format!("00-{}-{}-01", "0".repeat(32), SPAN_ID),
+        &format!("00-{}-{}-01", TRACE_ID, "0".repeat(16)),
+        &format!("00-{}g-{}-01", &TRACE_ID[1..], SPAN_ID),
+    ] {
+        assert_eq!(
+            TraceContext::from_w3c(traceparent, None),
+            None,
+            "{}",
+            traceparent
+        );
+    }
+}
+
+#[test]
+fn w3c_headers_are_read_from_requests() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", format!("00-{}-{}-01", TRACE_ID, SPAN_ID));
+    assert_eq!(
+        record(TracingMiddlewareBuilder::new("%{trace_id} %{span_id}"), req),
+        format!("{} {}", TRACE_ID, SPAN_ID)
+    );
+
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", "garbage");
+    assert_eq!(
+        record(TracingMiddlewareBuilder::new("%{trace_id} %{span_id}"), req),
+        "- -"
+    );
+}
+
+#[test]
+fn b3_single_header() {
+    let context = TraceContext::from_b3(&format!("{}-{}-1", TRACE_ID, SPAN_ID)).unwrap();
+    assert_eq!(context.trace_id_hex(), TRACE_ID);
+    assert_eq!(context.span_id_hex(), SPAN_ID);
+    assert!(context.is_sampled());
+
+    let context = TraceContext::from_b3(&format!("{}-{}", TRACE_ID, SPAN_ID)).unwrap();
+    assert!(!context.is_sampled());
+    let context = TraceContext::from_b3(&format!("{}-{}-d", TRACE_ID, SPAN_ID)).unwrap();
+    assert!(context.is_sampled());
+    let b3 = format!("{}-{}-0-{}", TRACE_ID, SPAN_ID, SPAN_ID);
+    assert!(!TraceContext::from_b3(&b3).unwrap().is_sampled());
+}
+
+#[test]
+fn b3_64_bit_trace_ids_are_padded() {
+    let context = TraceContext::from_b3(&format!("{}-{}", &TRACE_ID[16..], SPAN_ID)).unwrap();
+    assert_eq!(
+        context.trace_id_hex(),
+        format!("{}{}", "0".repeat(16), &TRACE_ID[16..])
+    );
+}
+
+#[test]
+fn b3_invalid_single_headers() {
+    for b3 in [
+        "0",
+        "1",
+        "d",
+        &format!("{}-{}-1-{}-extra", TRACE_ID, SPAN_ID, SPAN_ID),
+        &format!("{}-{}-1-abc", TRACE_ID, SPAN_ID),
+        &format!("{}-{}", &TRACE_ID[1..], SPAN_ID),
+        &format!("{}-{}", TRACE_ID, &SPAN_ID[1..]),
+        &format!("{}-{}", "0".repeat(32), SPAN_ID),
+        &format!("{}-{}", TRACE_ID, "0".repeat(16)),
+    ] {
+        assert_eq!(TraceContext::from_b3(b3), None, "{}", b3);
+    }
+}
+
+#[test]
+fn b3_multiple_headers() {
+    let context = TraceContext::from_b3_multi(TRACE_ID, SPAN_ID, Some("true"), None).unwrap();
+    assert_eq!(context.trace_id_hex(), TRACE_ID);
+    assert!(context.is_sampled());
+    // the debug flag implies sampling
+    let context = TraceContext::from_b3_multi(TRACE_ID, SPAN_ID, None, Some("1")).unwrap();
+    assert!(context.is_sampled());
+    let context = TraceContext::from_b3_multi(TRACE_ID, SPAN_ID, Some("0"), None).unwrap();
+    assert!(!context.is_sampled());
+    assert_eq!(TraceContext::from_b3_multi("x", SPAN_ID, None, None), None);
+}
+
+#[test]
+fn b3_headers_are_read_from_requests() {
+    let builder =
+        || TracingMiddlewareBuilder::new("%{trace_id} %{span_id}").propagation(Propagation::B3);
+    let mut req = request(Method::Get, "/");
+    req.insert_header("b3", format!("{}-{}-1", TRACE_ID, SPAN_ID));
+    assert_eq!(record(builder(), req), format!("{} {}", TRACE_ID, SPAN_ID));
+
+    let mut req = request(Method::Get, "/");
+    req.insert_header("X-B3-TraceId", TRACE_ID);
+    req.insert_header("X-B3-SpanId", SPAN_ID);
+    assert_eq!(record(builder(), req), format!("{} {}", TRACE_ID, SPAN_ID));
+
+    // the W3C headers are not read
+    let mut req = request(Method::Get, "/");
+    req.insert_header("traceparent", format!("00-{}-{}-01", TRACE_ID, SPAN_ID));
+    assert_eq!(record(builder(), req), "- -");
+}
+
+const ROOT: &str = "1-67891233-abcdef012345678912345678";
+
+#[test]
+fn xray_header() {
+    let value = format!(
+        "Self=1-67891234-12456789abcdef0123456789;Root={};Parent={};Sampled=1;Lineage=a:1",
+        ROOT, SPAN_ID
+    );
+    let header = XRayTraceHeader::parse(&value).unwrap();
+    assert_eq!(header.root(), ROOT);
+    assert_eq!(header.parent(), Some(SPAN_ID));
+    assert_eq!(header.sampled(), Some(true));
+    assert_eq!(
+        header.self_id(),
+        Some("1-67891234-12456789abcdef0123456789")
+    );
+    assert_eq!(
+        header.to_string(),
+        format!(
+            "Self=1-67891234-12456789abcdef0123456789;Root={};Parent={};Sampled=1",
+            ROOT, SPAN_ID
+        )
+    );
+
+    let header = XRayTraceHeader::parse(&format!(" Root = {} ;Sampled=?", ROOT)).unwrap();
+    assert_eq!(header.parent(), None);
+    assert_eq!(header.sampled(), None);
+}
+
+#[test]
+fn xray_invalid_headers() {
+    for value in [
+        "",
+        &format!("Parent={};Sampled=1", SPAN_ID),
+        "Root=1-67891233",
+        "Root=2-67891233-abcdef012345678912345678",
+        "Root=1-6789123-abcdef0123456789123456789",
+        "Root=1-67891233-abcdef01234567891234567g",
+    ] {
+        assert_eq!(XRayTraceHeader::parse(value), None, "{}", value);
+    }
+}
+
+#[test]
+fn xray_trace_context() {
+    let value = format!("Root={};Parent={};Sampled=1", ROOT, SPAN_ID);
+    let context = TraceContext::from_xray(&value).unwrap();
+    assert_eq!(context.trace_id_hex(), "67891233abcdef012345678912345678");
+    assert_eq!(context.span_id_hex(), SPAN_ID);
+    assert!(context.is_sampled());
+    // a header added by a load balancer has no parent
+    assert_eq!(TraceContext::from_xray(&format!("Root={}", ROOT)), None);
+}
+
+#[test]
+fn xray_headers_are_read_from_requests() {
+    let mut req = request(Method::Get, "/");
+    req.insert_header(
+        "X-Amzn-Trace-Id",
+        format!("Self=1-67891234-12456789abcdef0123456789;Root={}", ROOT),
+    );
+    let builder = TracingMiddlewareBuilder::new("%{xray_root} %{xray_self}").xray();
+    assert_eq!(
+        record(builder, req),
+        format!("{} 1-67891234-12456789abcdef0123456789", ROOT)
+    );
+
+    let builder = TracingMiddlewareBuilder::new("%{xray_root}").generate_xray_trace_header();
+    let root = record(builder, request(Method::Get, "/"));
+    assert!(
+        XRayTraceHeader::parse(&format!("Root={}", root)).is_some(),
+        "{}",
+        root
+    );
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::{get, with_capture, Capture};
+use tide_tracing_middleware::{Clock, TracingMiddlewareBuilder};
+use time::OffsetDateTime;
+use tracing::Level;
+
+/// A clock that only moves when told to, shared with the test.
+#[derive(Clone)]
+struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now_utc(&self) -> OffsetDateTime {
+        OffsetDateTime::unix_epoch() + *self.elapsed.lock().unwrap()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
+
+#[test]
+fn bursts_are_limited_and_summarized() {
+    let clock = MockClock::new();
+    let middleware = TracingMiddlewareBuilder::new("%U")
+        .max_logs_per_second(3)
+        .clock(clock.clone())
+        .build();
+    let stats = middleware.stats();
+    let mut app = tide::new();
+    app.with(middleware);
+    app.at("/*").get(|_| async { Ok("ok") });
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        for i in 0..10 {
+            get(&app, &format!("/{}", i));
+        }
+    });
+    assert_eq!(capture.messages(), vec!["/0", "/1", "/2"]);
+    assert_eq!(stats.rate_limited(), 7);
+
+    // a token is back, but the summary is not due yet
+    clock.advance(Duration::from_millis(500));
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || get(&app, "/early"));
+    assert_eq!(capture.messages(), vec!["/early"]);
+
+    clock.advance(Duration::from_millis(500));
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || get(&app, "/late"));
+    let events = capture.events();
+    assert_eq!(events.len(), 2, "{:?}", events);
+    assert_eq!(events[0].level, Level::WARN);
+    assert_eq!(
+        events[0].message(),
+        "7 access records suppressed by max_logs_per_second"
+    );
+    assert_eq!(events[0].fields["suppressed"], "7");
+    assert_eq!(events[1].message(), "/late");
+}
+
+#[test]
+fn suppressed_records_are_reported_after_the_burst() {
+    // the summary is logged by a task on another thread
+    let capture = Capture::new(Level::INFO);
+    tracing::subscriber::set_global_default(capture.clone()).unwrap();
+    let clock = MockClock::new();
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%U")
+            .max_logs_per_second(2)
+            .clock(clock)
+            .build(),
+    );
+    app.at("/*").get(|_| async { Ok("ok") });
+
+    for i in 0..5 {
+        get(&app, &format!("/{}", i));
+    }
+    // no record is let through after the burst
+    std::thread::sleep(Duration::from_millis(1500));
+
+    let summaries: Vec<_> = capture
+        .events()
+        .into_iter()
+        .filter(|event| event.level == Level::WARN)
+        .collect();
+    assert_eq!(summaries.len(), 1, "{:?}", summaries);
+    assert_eq!(
+        summaries[0].message(),
+        "3 access records suppressed by max_logs_per_second"
+    );
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{record, request, send, with_capture, Capture};
+use tide::http::Method;
+use tide::Response;
+use tide_tracing_middleware::{OutputFormat, TracingMiddlewareBuilder};
+use tracing::Level;
+
+fn secret_request() -> tide::http::Request {
+    let mut req = request(Method::Get, "/");
+    req.insert_header("Authorization", "Bearer secret");
+    req.insert_header("Cookie", "session=secret");
+    req.insert_header("X-Api-Key", "secret");
+    req.insert_header("X-Session", "secret");
+    req.insert_header("User-Agent", "curl/8.0");
+    req
+}
+
+#[test]
+fn sensitive_headers_are_redacted_by_default() {
+    let format = "%{Authorization}i %{cookie}i %{X-Api-Key}i %{User-Agent}i";
+    assert_eq!(
+        record(TracingMiddlewareBuilder::new(format), secret_request()),
+        "[REDACTED] [REDACTED] [REDACTED] curl/8.0"
+    );
+}
+
+#[test]
+fn response_headers_are_redacted() {
+    let mut app = tide::new();
+    app.with(
+        TracingMiddlewareBuilder::new("%{Set-Cookie}o %{X-Token}o")
+            .redact_header("x-token")
+            .build(),
+    );
+    app.at("/").get(|_| async {
+        let mut resp = Response::new(200);
+        resp.insert_header("Set-Cookie", "session=secret");
+        resp.insert_header("X-Token", "secret");
+        Ok(resp)
+    });
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || send(&app, request(Method::Get, "/")));
+    assert_eq!(capture.messages(), vec!["[REDACTED] [REDACTED]"]);
+}
+
+#[test]
+fn headers_can_be_redacted_and_unredacted() {
+    let builder = TracingMiddlewareBuilder::new("%{X-Session}i %{X-Api-Key}i")
+        .redact_header("X-SESSION")
+        .unredact_header("x-api-key");
+    assert_eq!(record(builder, secret_request()), "[REDACTED] secret");
+}
+
+#[test]
+fn structured_outputs_are_redacted() {
+    let builder = TracingMiddlewareBuilder::new("%{Authorization}i").output(OutputFormat::Json);
+    assert_eq!(
+        record(builder, secret_request()),
+        r#"{"request_headers":{"authorization":"[REDACTED]"}}"#
+    );
+}
+
+#[test]
+fn only_listed_headers_are_logged() {
+    let builder = TracingMiddlewareBuilder::new("%{User-Agent}i %{X-Session}i %{Authorization}i")
+        .log_only_headers(["user-agent", "authorization"]);
+    assert_eq!(
+        record(builder, secret_request()),
+        "curl/8.0 [REDACTED] [REDACTED]"
+    );
+}
+
+#[test]
+fn cookies_can_be_redacted() {
+    let builder =
+        TracingMiddlewareBuilder::new("%{session}C %{theme}C %{missing}C").redact_cookie("session");
+    let mut req = request(Method::Get, "/");
+    req.insert_header("Cookie", "session=secret; theme=dark");
+    assert_eq!(record(builder, req), "[REDACTED] dark -");
+}
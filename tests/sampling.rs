@@ -0,0 +1,100 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{get, with_capture, Capture};
+use tide::{Response, StatusCode};
+use tide_tracing_middleware::{PrefixConfig, TracingMiddleware, TracingMiddlewareBuilder};
+use tracing::Level;
+
+fn app(middleware: TracingMiddleware<()>) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(middleware);
+    app.at("/*").get(|_| async { Ok("ok") });
+    app.at("/error")
+        .get(|_| async { Ok(Response::new(StatusCode::InternalServerError)) });
+    app
+}
+
+/// The records of `paths` sent to an app logging with `builder`.
+fn records(builder: TracingMiddlewareBuilder<()>, paths: &[&str]) -> Vec<String> {
+    let app = app(builder.build());
+    let capture = Capture::new(Level::TRACE);
+    with_capture(&capture, || {
+        for path in paths {
+            get(&app, path);
+        }
+    });
+    capture.messages()
+}
+
+#[test]
+fn errors_are_always_sampled() {
+    let middleware = TracingMiddlewareBuilder::new("%U %s")
+        .sample_rate(0.0)
+        .build();
+    let stats = middleware.stats();
+    let app = app(middleware);
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        get(&app, "/a");
+        get(&app, "/error");
+        get(&app, "/b");
+    });
+
+    assert_eq!(capture.messages(), vec!["/error 500"]);
+    assert_eq!(stats.sampled_out(), 2);
+    assert_eq!(stats.emitted(), 1);
+}
+
+#[test]
+fn full_rate_keeps_every_record() {
+    let builder = TracingMiddlewareBuilder::new("%U").sample_rate(1.0);
+    assert_eq!(
+        records(builder, &["/a", "/b", "/c"]),
+        vec!["/a", "/b", "/c"]
+    );
+}
+
+#[test]
+fn rates_are_clamped() {
+    let builder = TracingMiddlewareBuilder::new("%U").sample_rate(7.0);
+    assert_eq!(records(builder, &["/a", "/b"]), vec!["/a", "/b"]);
+    let builder = TracingMiddlewareBuilder::new("%U").sample_rate(-1.0);
+    assert!(records(builder, &["/a", "/b"]).is_empty());
+}
+
+#[test]
+fn partial_rates_keep_a_share_of_the_records() {
+    let builder = TracingMiddlewareBuilder::new("%U").sample_rate(0.5);
+    let kept = records(builder, &["/a"; 400]).len();
+    // 10 standard deviations on either side
+    assert!((100..=300).contains(&kept), "{}", kept);
+}
+
+#[test]
+fn slow_requests_are_always_sampled() {
+    let builder = TracingMiddlewareBuilder::new("%U")
+        .sample_rate(0.0)
+        .slow_request_threshold(Duration::ZERO);
+    assert_eq!(records(builder, &["/a"]), vec!["/a"]);
+}
+
+#[test]
+fn prefixes_have_their_own_rates() {
+    let builder = TracingMiddlewareBuilder::new("%U")
+        .prefix_config("/static", PrefixConfig::new().sample_rate(0.0));
+    assert_eq!(
+        records(builder, &["/static/app.js", "/api/users", "/static"]),
+        vec!["/api/users"]
+    );
+}
+
+#[test]
+fn only_slow_requests_drops_the_fast_ones() {
+    let builder = TracingMiddlewareBuilder::new("%U").only_slow_requests(Duration::from_secs(60));
+    assert!(records(builder, &["/a", "/error"]).is_empty());
+    let builder = TracingMiddlewareBuilder::new("%U").only_slow_requests(Duration::ZERO);
+    assert_eq!(records(builder, &["/a"]), vec!["/a"]);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::{record, request};
+use tide::http::Method;
+use tide_tracing_middleware::TracingMiddlewareBuilder;
+
+fn render(builder: TracingMiddlewareBuilder<()>, path: &str) -> String {
+    record(builder, request(Method::Get, path))
+}
+
+#[test]
+fn scrubbed_parameters_keep_their_names() {
+    let builder = TracingMiddlewareBuilder::new("%Q | %r").scrub_query_param("token");
+    assert_eq!(
+        render(builder, "/?page=2&token=secret&token=again&flag"),
+        "page=2&token=***&token=***&flag | GET /?page=2&token=***&token=***&flag ?"
+    );
+}
+
+#[test]
+fn names_are_matched_decoded() {
+    let builder = TracingMiddlewareBuilder::new("%Q").scrub_query_param("api key");
+    assert_eq!(
+        render(builder, "/?api%20key=secret&api+key=secret"),
+        "api%20key=***&api+key=***"
+    );
+}
+
+#[test]
+fn other_queries_are_left_alone() {
+    let builder = TracingMiddlewareBuilder::new("%Q").scrub_query_param("token");
+    assert_eq!(render(builder, "/?tokens=1&a=token"), "tokens=1&a=token");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn names_can_be_matched_by_regex() {
+    let builder = TracingMiddlewareBuilder::new("%Q").scrub_query_param_regex("(?i)secret|^key$");
+    assert_eq!(
+        render(builder, "/?client_SECRET=1&key=2&keys=3"),
+        "client_SECRET=***&key=***&keys=3"
+    );
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn invalid_regexes_are_errors() {
+    assert!(TracingMiddlewareBuilder::<()>::default()
+        .try_scrub_query_param_regex("(")
+        .is_err());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{record, request};
+use tide::http::Method;
+use tide_tracing_middleware::{OutputFormat, TracingMiddlewareBuilder};
+
+fn render(builder: TracingMiddlewareBuilder<()>, path: &str) -> String {
+    record(builder, request(Method::Get, path))
+}
+
+#[test]
+fn long_records_are_truncated() {
+    let builder = TracingMiddlewareBuilder::new("%M %U").max_line_len(8);
+    assert_eq!(
+        render(builder, "/abcdefgh"),
+        "GET /abc...[truncated 5 bytes]"
+    );
+}
+
+#[test]
+fn short_records_are_unchanged() {
+    let builder = || TracingMiddlewareBuilder::new("%M %U").max_line_len(8);
+    assert_eq!(render(builder(), "/abc"), "GET /abc");
+    assert_eq!(render(builder(), "/ab"), "GET /ab");
+}
+
+#[test]
+fn characters_are_not_split() {
+    // `é` is 2 bytes, the 8th byte is in its middle
+    let builder = TracingMiddlewareBuilder::new("%{v}xi")
+        .custom_request_replace("v", |_| "abcdefgé".to_owned())
+        .max_line_len(8);
+    assert_eq!(render(builder, "/"), "abcdefg...[truncated 2 bytes]");
+}
+
+#[test]
+fn json_values_are_truncated() {
+    let builder = TracingMiddlewareBuilder::new("%M %U")
+        .output(OutputFormat::Json)
+        .max_line_len(8);
+    let record = render(builder, "/abcdefghij");
+    let json: serde_json::Value = serde_json::from_str(&record).unwrap();
+    assert_eq!(json["method"], "GET");
+    assert_eq!(json["path"], "/abcdefg...[truncated 3 bytes]");
+}
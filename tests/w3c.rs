@@ -0,0 +1,136 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{get, with_capture, Capture};
+use tide_tracing_middleware::{FileSink, OutputFormat, TracingMiddlewareBuilder, TracingSink};
+use tracing::Level;
+
+fn app(builder: TracingMiddlewareBuilder<()>) -> tide::Server<()> {
+    let mut app = tide::new();
+    app.with(builder.output(OutputFormat::W3c).build());
+    app.at("/*").get(|_| async {
+        async_std::task::sleep(Duration::from_millis(5)).await;
+        Ok("ok")
+    });
+    app
+}
+
+#[test]
+fn directives_are_events_of_their_own() {
+    let app = app(TracingMiddlewareBuilder::new("%M %U %s"));
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || {
+        get(&app, "/a");
+        get(&app, "/b");
+    });
+
+    assert_eq!(
+        capture.messages(),
+        vec![
+            "#Version: 1.0",
+            "#Fields: cs-method cs-uri-stem sc-status",
+            "GET /a 200",
+            "GET /b 200",
+        ]
+    );
+}
+
+#[test]
+fn the_tracing_sink_writes_the_directives() {
+    let messages = |builder: TracingMiddlewareBuilder<()>| {
+        let app = app(builder);
+        let capture = Capture::new(Level::TRACE);
+        with_capture(&capture, || {
+            get(&app, "/a");
+            get(&app, "/b");
+        });
+        capture.messages()
+    };
+
+    assert_eq!(
+        messages(TracingMiddlewareBuilder::new("%M %U %s").sink(TracingSink)),
+        messages(TracingMiddlewareBuilder::new("%M %U %s"))
+    );
+}
+
+#[test]
+fn milliseconds_are_converted_to_seconds() {
+    let app = app(TracingMiddlewareBuilder::new("%T %D"));
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || get(&app, "/slow"));
+
+    let messages = capture.messages();
+    assert_eq!(messages[1], "#Fields: time-taken time-taken");
+    let columns: Vec<f64> = messages[2]
+        .split(' ')
+        .map(|column| column.parse().unwrap())
+        .collect();
+    assert!(columns[0] >= 0.005 && columns[0] < 1.0, "{}", messages[2]);
+    assert!((columns[0] - columns[1]).abs() < 0.001, "{}", messages[2]);
+}
+
+#[test]
+fn records_are_truncated() {
+    let app = app(TracingMiddlewareBuilder::new("%M %U").max_line_len(10));
+    let capture = Capture::new(Level::TRACE);
+
+    with_capture(&capture, || get(&app, "/a/very/long/path"));
+
+    let messages = capture.messages();
+    assert_eq!(messages[1], "#Fields: cs-method cs-uri-stem");
+    assert_eq!(messages[2], "GET /a/ver...[truncated 11 bytes]");
+}
+
+#[test]
+fn file_sinks_write_the_directives_first() {
+    let path = std::env::temp_dir().join(format!("w3c-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sink = FileSink::new(&path).unwrap();
+    let app = app(TracingMiddlewareBuilder::new("%M %U %s").sink(sink));
+
+    get(&app, "/a");
+    get(&app, "/b");
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        written,
+        "#Version: 1.0\n#Fields: cs-method cs-uri-stem sc-status\nGET /a 200\nGET /b 200\n"
+    );
+}
+
+#[cfg(feature = "tracing-appender")]
+#[test]
+fn non_blocking_writers_get_the_directives_first() {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let written = Shared::default();
+    let (builder, guard) = TracingMiddlewareBuilder::new("%M %U %s").non_blocking(written.clone());
+    let app = app(builder);
+
+    get(&app, "/a");
+    get(&app, "/b");
+    drop(guard);
+
+    assert_eq!(
+        String::from_utf8(written.0.lock().unwrap().clone()).unwrap(),
+        "#Version: 1.0\n#Fields: cs-method cs-uri-stem sc-status\nGET /a 200\nGET /b 200\n"
+    );
+}